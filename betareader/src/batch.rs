@@ -0,0 +1,71 @@
+use anyhow::Result;
+use polars::{
+    lazy::dsl::Expr,
+    prelude::{read_impl::OwnedBatchedCsvReaderMmap, DataFrame, IntoLazy},
+};
+
+use crate::{columnar::ColumnarBatches, validate::Validator};
+
+/// Batched reader of beta. Beta is either read from csv batch by batch, or
+/// has been loaded into memory (such as merged beta from multiple models) and
+/// is sliced into batches. Columnar file is read by row groups or record
+/// batches, and required columns are selected by the expressions. Batches from
+/// file are validated, and the validator keeps state across batches such as
+/// duplicated variants.
+pub enum BetaBatchReader {
+    Csv(OwnedBatchedCsvReaderMmap, Box<Validator>),
+    Columnar(ColumnarBatches, Vec<Expr>, Box<Validator>),
+    Frame {
+        beta: DataFrame,
+        offset: usize,
+        batch_size: usize,
+    },
+}
+
+impl BetaBatchReader {
+    pub fn from_frame(beta: DataFrame, batch_size: usize) -> BetaBatchReader {
+        BetaBatchReader::Frame {
+            beta,
+            offset: 0,
+            batch_size,
+        }
+    }
+
+    /// get next batch of beta, None if there is no more
+    pub fn next_batch(&mut self) -> Result<Option<DataFrame>> {
+        match self {
+            BetaBatchReader::Csv(reader, validator) => {
+                // skip batches with all rows rejected
+                while let Some(raw) = reader.next_batches(1)? {
+                    let batch = validator.validate(&raw[0])?;
+                    if batch.height() > 0 {
+                        return Ok(Some(batch));
+                    }
+                }
+                Ok(None)
+            }
+            BetaBatchReader::Columnar(reader, exprs, validator) => {
+                while let Some(raw) = reader.next_batch()? {
+                    let raw = raw.lazy().select(exprs.as_slice()).collect()?;
+                    let batch = validator.validate(&raw)?;
+                    if batch.height() > 0 {
+                        return Ok(Some(batch));
+                    }
+                }
+                Ok(None)
+            }
+            BetaBatchReader::Frame {
+                beta,
+                offset,
+                batch_size,
+            } => {
+                if *offset >= beta.height() {
+                    return Ok(None);
+                }
+                let batch = beta.slice(*offset as i64, *batch_size);
+                *offset += *batch_size;
+                Ok(Some(batch))
+            }
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//! A set of models to be scored in one run. Each model is read by its own
+//! [BetaArg], and their score columns are renamed to output score names. When
+//! there are multiple models, betas are unioned by CHR, POS, A1 (and A2 if
+//! present) into one beta with a score column for each model, so that each
+//! snp in bed only need to be decoded once. Score of a model is null for snp
+//! not in the model. Other columns, such as FREQ, P and genetic model flags,
+//! are shared by all models and should agree on each snp.
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use polars::{
+    lazy::dsl::{col, lit, when, Expr},
+    prelude::{DataFrame, DataType, IntoLazy, NamedFrom, TakeRandomUtf8},
+    series::Series,
+};
+
+use crate::{
+    batch::BetaBatchReader, weight_type::WeightType, BetaArg, Float, A1, A2, CHR, FLOAT_DTYPE,
+    FREQ, IS_DOMINANT, IS_RECESSIVE, POS,
+};
+
+/// temporary column flagging snp to be swapped
+const SWAP_FLAG: &str = "__SWAP";
+
+pub struct BetaModel<'a> {
+    pub beta_arg: BetaArg<'a>,
+    /// output score names, in the same order of `beta_arg.score_names`
+    pub out_names: &'a Vec<String>,
+}
+
+pub struct BetaSet<'a> {
+    pub models: Vec<BetaModel<'a>>,
+}
+
+impl<'a> BetaSet<'a> {
+    /// all output score names
+    pub fn out_names(&self) -> Vec<String> {
+        self.models
+            .iter()
+            .flat_map(|v| v.out_names.iter().cloned())
+            .collect()
+    }
+
+    /// weight type of each output score
+    pub fn weight_types(&self) -> Result<BTreeMap<String, WeightType>> {
+        let mut weight_types = BTreeMap::new();
+        for model in &self.models {
+            let model_types = model.beta_arg.get_weight_types()?;
+            for (out_name, weight_type) in model.out_names.iter().zip(model_types) {
+                weight_types.insert(out_name.to_owned(), weight_type);
+            }
+        }
+        Ok(weight_types)
+    }
+
+    /// Read beta for all models. Return beta and columns to be used.
+    pub fn read(&self) -> Result<(DataFrame, Vec<String>)> {
+        if self.models.len() == 1 {
+            let model = &self.models[0];
+            let (beta, cols) = model.beta_arg.read()?;
+            return rename_scores(beta, cols, model);
+        }
+        self.read_merged()
+    }
+
+    /// Read beta in batches. Single model is read batch by batch from file,
+    /// while multiple models are merged in memory and then sliced.
+    pub fn batch_read(&self, batch_size: usize) -> Result<(BetaBatchReader, Vec<String>)> {
+        if self.models.len() == 1 && is_identical(&self.models[0]) {
+            let (reader, cols) = self.models[0].beta_arg.batch_read(batch_size)?;
+            return Ok((reader, cols));
+        }
+        let (beta, cols) = self.read()?;
+        Ok((BetaBatchReader::from_frame(beta, batch_size), cols))
+    }
+
+    fn read_merged(&self) -> Result<(DataFrame, Vec<String>)> {
+        let all_out_names = self.out_names();
+        let mut read_models = vec![];
+        for model in &self.models {
+            let (beta, cols) = model.beta_arg.read()?;
+            read_models.push(rename_scores(beta, cols, model)?);
+        }
+        // genetic model flags are optional, models without them are additive
+        let flag_cols: Vec<&str> = [IS_DOMINANT, IS_RECESSIVE]
+            .into_iter()
+            .filter(|flag| read_models.iter().any(|(_, cols)| cols.iter().any(|v| v == flag)))
+            .collect();
+        for (beta, cols) in read_models.iter_mut() {
+            for flag in &flag_cols {
+                if !cols.iter().any(|v| v == flag) {
+                    beta.with_column(Series::new(flag, vec![false; beta.height()]))?;
+                    cols.push(flag.to_string());
+                }
+            }
+        }
+        // columns other than scores are shared, and should be in all models
+        let mut merged_cols: Vec<String> = vec![];
+        for (_, cols) in &read_models {
+            for v in cols {
+                if !all_out_names.contains(v) && !merged_cols.contains(v) {
+                    merged_cols.push(v.to_owned());
+                }
+            }
+        }
+        for ((_, cols), model) in read_models.iter().zip(&self.models) {
+            if let Some(name) = merged_cols.iter().find(|v| !cols.contains(v)) {
+                return Err(anyhow!(
+                    "Column {} is not found in model {}, but in other models",
+                    name,
+                    model.beta_arg.weight_path
+                ));
+            }
+        }
+        let has_a2 = merged_cols.iter().any(|v| v == A2);
+        merged_cols.extend(all_out_names.iter().cloned());
+
+        let mut frames = vec![];
+        let mut seen_snp = HashSet::new();
+        for ((mut beta, _), model) in read_models.into_iter().zip(&self.models) {
+            // add null score for other models
+            for out_name in &all_out_names {
+                if !model.out_names.contains(out_name) {
+                    beta.with_column(Series::full_null(
+                        out_name,
+                        beta.height(),
+                        &FLOAT_DTYPE,
+                    ))?;
+                }
+            }
+            if has_a2 {
+                beta = orient_alleles(beta, model.out_names, &mut seen_snp)?;
+            }
+            frames.push(beta.select(&merged_cols)?);
+        }
+
+        let mut beta = frames.remove(0);
+        for frame in frames {
+            beta.vstack_mut(&frame)?;
+        }
+
+        // union snp, take the first non-null value for each column, and
+        // count distinct values of shared columns to find conflicts
+        let mut key_cols = vec![CHR, POS, A1];
+        if has_a2 {
+            key_cols.push(A2);
+        }
+        let shared_cols: Vec<&String> = merged_cols
+            .iter()
+            .filter(|v| !key_cols.contains(&v.as_str()) && !all_out_names.contains(v))
+            .collect();
+        let agg_exprs: Vec<_> = merged_cols
+            .iter()
+            .filter(|v| !key_cols.contains(&v.as_str()))
+            .map(|v| col(v).drop_nulls().first())
+            .chain(
+                shared_cols
+                    .iter()
+                    .map(|v| col(v).drop_nulls().n_unique().alias(&n_unique_name(v))),
+            )
+            .collect();
+        let key_exprs: Vec<_> = key_cols.iter().map(|v| col(v)).collect();
+        let beta = beta
+            .lazy()
+            .groupby_stable(key_exprs)
+            .agg(agg_exprs)
+            .collect()?;
+        check_conflicts(&beta, &shared_cols)?;
+        Ok((beta.select(&merged_cols)?, merged_cols))
+    }
+}
+
+/// Swap A1 and A2 of snp whose alleles are in the opposite order in an earlier
+/// model, so that they are unioned into one row. Scores of the model are
+/// negated, and FREQ becomes the frequency of the new A1. Dominant or recessive
+/// snp can not be swapped.
+fn orient_alleles(
+    beta: DataFrame,
+    out_names: &[String],
+    seen_snp: &mut HashSet<String>,
+) -> Result<DataFrame> {
+    let chrom = utf8_values(&beta, CHR)?;
+    let pos = utf8_values(&beta, POS)?;
+    let a1 = utf8_values(&beta, A1)?;
+    let a2 = utf8_values(&beta, A2)?;
+    let mut is_swap = vec![];
+    let mut snp_keys = vec![];
+    for i in 0..beta.height() {
+        let key = format!("{}:{}:{}:{}", chrom[i], pos[i], a1[i], a2[i]);
+        let swapped = format!("{}:{}:{}:{}", chrom[i], pos[i], a2[i], a1[i]);
+        let swap = !seen_snp.contains(&key) && seen_snp.contains(&swapped);
+        is_swap.push(swap);
+        snp_keys.push(if swap { swapped } else { key });
+    }
+    // snp are recorded after the whole model is checked, so that snp within
+    // a model are never swapped
+    seen_snp.extend(snp_keys);
+    if !is_swap.contains(&true) {
+        return Ok(beta);
+    }
+    // dominant or recessive coding of A1 can not be turned into A2
+    for flag in [IS_DOMINANT, IS_RECESSIVE] {
+        let Ok(flags) = beta.column(flag) else {
+            continue;
+        };
+        let row = flags
+            .bool()?
+            .into_iter()
+            .zip(&is_swap)
+            .position(|(v, swap)| *swap && v == Some(true));
+        if let Some(row) = row {
+            return Err(anyhow!(
+                "Snp {}:{} is {} with A1 {}, but has swapped alleles in an earlier model",
+                chrom[row],
+                pos[row],
+                flag,
+                a1[row]
+            ));
+        }
+    }
+
+    let mut beta = beta;
+    beta.with_column(Series::new(SWAP_FLAG, is_swap))?;
+    let swap_or = |swapped: Expr, name: &str| {
+        when(col(SWAP_FLAG))
+            .then(swapped)
+            .otherwise(col(name))
+            .alias(name)
+    };
+    let mut exprs = vec![swap_or(col(A2), A1), swap_or(col(A1), A2)];
+    for out_name in out_names {
+        exprs.push(swap_or(col(out_name) * lit(-1.0 as Float), out_name));
+    }
+    if beta.get_column_names().contains(&FREQ) {
+        exprs.push(swap_or(lit(1.0_f32) - col(FREQ), FREQ));
+    }
+    let beta = beta.lazy().with_columns(exprs).collect()?;
+    Ok(beta.drop(SWAP_FLAG)?)
+}
+
+/// values of a column as strings, empty for null
+fn utf8_values(beta: &DataFrame, name: &str) -> Result<Vec<String>> {
+    let values = beta.column(name)?.cast(&DataType::Utf8)?;
+    Ok(values
+        .utf8()?
+        .into_iter()
+        .map(|v| v.unwrap_or_default().to_owned())
+        .collect())
+}
+
+/// column counting distinct values of a shared column for each snp
+fn n_unique_name(name: &str) -> String {
+    format!("{}_n_unique", name)
+}
+
+/// Models disagreeing on a shared column of a snp, e.g. FREQ or P, can not
+/// be merged into one row, since only one value is used for all scores
+fn check_conflicts(beta: &DataFrame, shared_cols: &[&String]) -> Result<()> {
+    for name in shared_cols {
+        let n_unique = beta.column(&n_unique_name(name))?.cast(&DataType::UInt32)?;
+        let row = n_unique
+            .u32()?
+            .into_iter()
+            .position(|v| v.is_some_and(|v| v > 1));
+        if let Some(row) = row {
+            let mut snp = vec![];
+            for key in [CHR, POS, A1] {
+                let values = beta.column(key)?.cast(&DataType::Utf8)?;
+                snp.push(values.utf8()?.get(row).unwrap_or_default().to_owned());
+            }
+            return Err(anyhow!(
+                "Models have conflicting {} for snp {}, score them in separate runs",
+                name,
+                snp.join(":")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// whether output score names are identical to score names in weight file
+fn is_identical(model: &BetaModel) -> bool {
+    model.beta_arg.score_names == model.out_names
+}
+
+/// select columns and rename score columns to output score names
+fn rename_scores(
+    beta: DataFrame,
+    cols: Vec<String>,
+    model: &BetaModel,
+) -> Result<(DataFrame, Vec<String>)> {
+    let mut beta = beta.select(&cols)?;
+    let mut new_cols = cols;
+    for (score_name, out_name) in model.beta_arg.score_names.iter().zip(model.out_names) {
+        if score_name == out_name {
+            continue;
+        }
+        beta.rename(score_name, out_name)?;
+        for i in new_cols.iter_mut() {
+            if i == score_name {
+                *i = out_name.to_owned();
+            }
+        }
+    }
+    Ok((beta, new_cols))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use super::{check_conflicts, n_unique_name, BetaModel, BetaSet};
+    use crate::{dedup::DupPolicy, BetaArg, FloatType, A1, A2, CHR, FREQ, POS};
+
+    static WEIGHT_TYPES: Vec<String> = vec![];
+
+    fn beta_arg<'a>(
+        path: &'a str,
+        a2: Option<&'a str>,
+        score_names: &'a Vec<String>,
+    ) -> BetaArg<'a> {
+        BetaArg {
+            chrom: CHR,
+            pos: POS,
+            a1: A1,
+            a2,
+            freq: FREQ,
+            snp_id: "ID",
+            pvalue: "P",
+            score_names,
+            weight_path: path,
+            need_freq: true,
+            need_id: false,
+            need_pvalue: false,
+            pvalue_range: None,
+            parse_id: false,
+            rejected_path: format!("{path}.rejected"),
+            duplicates_path: format!("{path}.duplicates"),
+            dup_policy: DupPolicy::First,
+            strict: true,
+            weight_types: &WEIGHT_TYPES,
+            extra_cols: vec![],
+        }
+    }
+
+    /// write a weight file in temp dir and return its path
+    fn write_weight(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn test_read_merged() {
+        let path1 = write_weight(
+            "pgs_beta_set_test1.tsv",
+            "CHR\tPOS\tA1\tA2\tFREQ\tBeta\n1\t100\tA\tG\t0.25\t0.5\n1\t200\tC\tT\t0.3\t0.1\n",
+        );
+        // snp at 100 has swapped alleles, and is unioned with model 1
+        let path2 = write_weight(
+            "pgs_beta_set_test2.tsv",
+            "CHR\tPOS\tA1\tA2\tFREQ\tBeta\n1\t100\tG\tA\t0.75\t0.4\n1\t300\tT\tC\t0.1\t0.2\n",
+        );
+        let score_names = vec!["Beta".to_owned()];
+        let (out1, out2) = (vec!["m1".to_owned()], vec!["m2".to_owned()]);
+        let beta_set = BetaSet {
+            models: vec![
+                BetaModel {
+                    beta_arg: beta_arg(&path1, Some(A2), &score_names),
+                    out_names: &out1,
+                },
+                BetaModel {
+                    beta_arg: beta_arg(&path2, Some(A2), &score_names),
+                    out_names: &out2,
+                },
+            ],
+        };
+        let (beta, cols) = beta_set.read().unwrap();
+        assert_eq!(&cols[cols.len() - 2..], ["m1", "m2"]);
+        assert_eq!(beta.height(), 3);
+        let a1: Vec<_> = beta
+            .column(A1)
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(a1, [Some("A"), Some("C"), Some("T")]);
+        let m2: Vec<_> = beta
+            .column("m2")
+            .unwrap()
+            .unpack::<FloatType>()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(m2, [Some(-0.4), None, Some(0.2)]);
+        // FREQ of swapped snp agrees with model 1, and is not a conflict
+        let freq: Vec<_> = beta
+            .column(FREQ)
+            .unwrap()
+            .f32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(freq, [Some(0.25), Some(0.3), Some(0.1)]);
+
+        // A2 is given for only one model
+        let beta_set = BetaSet {
+            models: vec![
+                BetaModel {
+                    beta_arg: beta_arg(&path1, Some(A2), &score_names),
+                    out_names: &out1,
+                },
+                BetaModel {
+                    beta_arg: beta_arg(&path2, None, &score_names),
+                    out_names: &out2,
+                },
+            ],
+        };
+        let err = beta_set.read().unwrap_err().to_string();
+        assert!(err.contains("Column A2 is not found in model"));
+        assert!(err.contains("pgs_beta_set_test2.tsv"));
+    }
+
+    #[test]
+    fn test_check_conflicts() {
+        let beta = DataFrame::new(vec![
+            Series::new(CHR, ["1", "1"]),
+            Series::new(POS, [100, 200]),
+            Series::new(A1, ["A", "C"]),
+            Series::new(&n_unique_name(FREQ), [1_u32, 1]),
+        ])
+        .unwrap();
+        let freq = FREQ.to_owned();
+        assert!(check_conflicts(&beta, &[&freq]).is_ok());
+
+        let mut beta = beta;
+        let n_unique = Series::new(&n_unique_name(FREQ), [1_u32, 2]);
+        beta.replace(&n_unique_name(FREQ), n_unique).unwrap();
+        let err = check_conflicts(&beta, &[&freq]).unwrap_err().to_string();
+        assert!(err.contains("conflicting FREQ for snp 1:200:C"));
+    }
+}
@@ -0,0 +1,172 @@
+//! Columnar weight file, Parquet or Arrow IPC. Only required columns are read
+//! (projection) in their own types. When the whole file is read, the P value
+//! filter of Q ranges is pushed down to the scan so that row groups out of
+//! range are skipped by statistics. In batches, Parquet is read by row groups
+//! and Arrow IPC by record batches, and the filter is applied on each batch.
+use std::{fs::File, io::Read, path::Path};
+
+use anyhow::Result;
+use polars::{
+    export::arrow::io::ipc::read::{read_file_metadata, FileReader},
+    prelude::{
+        BatchedParquetReader, DataFrame, IpcReader, LazyFrame, ParquetReader, ScanArgsIpc,
+        ScanArgsParquet, SerReader, Series,
+    },
+};
+
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+const IPC_MAGIC: &[u8] = b"ARROW1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnarFormat {
+    Parquet,
+    Ipc,
+}
+
+impl ColumnarFormat {
+    /// detect columnar format by magic bytes, None for text file
+    pub fn detect(path: impl AsRef<Path>) -> Result<Option<ColumnarFormat>> {
+        let mut magic = Vec::with_capacity(IPC_MAGIC.len());
+        File::open(path)?
+            .take(IPC_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        let format = if magic.starts_with(PARQUET_MAGIC) {
+            Some(ColumnarFormat::Parquet)
+        } else if magic.starts_with(IPC_MAGIC) {
+            Some(ColumnarFormat::Ipc)
+        } else {
+            None
+        };
+        Ok(format)
+    }
+
+    /// column names in file
+    pub fn header(&self, path: impl AsRef<Path>) -> Result<Vec<String>> {
+        let file = File::open(path)?;
+        let schema = match self {
+            ColumnarFormat::Parquet => ParquetReader::new(file).schema()?,
+            ColumnarFormat::Ipc => IpcReader::new(file).schema()?,
+        };
+        Ok(schema.iter_names().map(|v| v.to_string()).collect())
+    }
+
+    /// lazy scan of the file, projection and predicate are pushed down
+    pub fn scan(&self, path: impl AsRef<Path>) -> Result<LazyFrame> {
+        let lazy_frame = match self {
+            ColumnarFormat::Parquet => LazyFrame::scan_parquet(path, ScanArgsParquet::default())?,
+            ColumnarFormat::Ipc => LazyFrame::scan_ipc(path, ScanArgsIpc::default())?,
+        };
+        Ok(lazy_frame)
+    }
+
+    /// Read in batches with columns in `projection`. Parquet is read by row
+    /// groups in batches of about `batch_size` rows, and Arrow IPC by record
+    /// batches as written.
+    pub fn batched(
+        &self,
+        path: impl AsRef<Path>,
+        projection: Vec<usize>,
+        batch_size: usize,
+    ) -> Result<ColumnarBatches> {
+        let batches = match self {
+            ColumnarFormat::Parquet => ColumnarBatches::Parquet(
+                ParquetReader::new(File::open(path)?)
+                    .with_projection(Some(projection))
+                    .batched(batch_size)?,
+            ),
+            ColumnarFormat::Ipc => {
+                let mut file = File::open(path)?;
+                let metadata = read_file_metadata(&mut file)?;
+                ColumnarBatches::Ipc(Box::new(FileReader::new(
+                    file,
+                    metadata,
+                    Some(projection),
+                    None,
+                )))
+            }
+        };
+        Ok(batches)
+    }
+}
+
+/// Batches of columnar file, see [ColumnarFormat::batched]
+pub enum ColumnarBatches {
+    Parquet(BatchedParquetReader),
+    Ipc(Box<FileReader<File>>),
+}
+
+impl ColumnarBatches {
+    /// next batch of raw columns, None if there is no more
+    pub fn next_batch(&mut self) -> Result<Option<DataFrame>> {
+        match self {
+            ColumnarBatches::Parquet(reader) => match reader.next_batches(1)? {
+                Some(mut batches) if !batches.is_empty() => Ok(Some(batches.remove(0))),
+                _ => Ok(None),
+            },
+            ColumnarBatches::Ipc(reader) => {
+                let chunk = match reader.next() {
+                    Some(v) => v?,
+                    None => return Ok(None),
+                };
+                let columns = reader
+                    .schema()
+                    .fields
+                    .iter()
+                    .zip(chunk.into_arrays())
+                    .map(|(field, array)| Ok(Series::try_from((field.name.as_str(), array))?))
+                    .collect::<Result<Vec<Series>>>()?;
+                Ok(Some(DataFrame::new(columns)?))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use polars::prelude::{DataFrame, IpcWriter, NamedFrom, SerWriter, Series, TakeRandom};
+
+    use super::ColumnarFormat;
+
+    #[test]
+    fn test_ipc_batched() {
+        let path = std::env::temp_dir().join("pgs_columnar_test.arrow");
+        let batch = DataFrame::new(vec![
+            Series::new("CHR", [1, 1]),
+            Series::new("ID", ["rs1", "rs2"]),
+            Series::new("POS", [100, 200]),
+        ])
+        .unwrap();
+        // one record batch for each chunk
+        let mut df = batch.clone();
+        df.vstack_mut(&batch).unwrap();
+        IpcWriter::new(File::create(&path).unwrap())
+            .finish(&mut df)
+            .unwrap();
+
+        let format = ColumnarFormat::detect(&path).unwrap().unwrap();
+        assert_eq!(format, ColumnarFormat::Ipc);
+        assert_eq!(format.header(&path).unwrap(), ["CHR", "ID", "POS"]);
+        let mut batches = format.batched(&path, vec![0, 2], 1).unwrap();
+        let mut n_rows = 0;
+        while let Some(batch) = batches.next_batch().unwrap() {
+            assert_eq!(batch.get_column_names(), ["CHR", "POS"]);
+            assert_eq!(
+                batch.column("POS").unwrap().i32().unwrap().get(1),
+                Some(200)
+            );
+            n_rows += batch.height();
+        }
+        assert_eq!(n_rows, 4);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_detect_text() {
+        let path = std::env::temp_dir().join("pgs_columnar_test.txt");
+        std::fs::write(&path, "CHR\tPOS\n1\t100\n").unwrap();
+        assert_eq!(ColumnarFormat::detect(&path).unwrap(), None);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! Transparent decompression for text inputs. Compression is detected by
+//! magic bytes rather than file extension, so `Weights.tsv.gz`,
+//! `Weights.tsv.bgz` and `Weights.tsv.zst` are all read the same way as a
+//! plain tsv. bgzip is a concatenation of gzip members and is handled by
+//! [MultiGzDecoder].
+//!
+//! Compressed files are decompressed into memory once, since polars need the
+//! whole bytes (mmap or owned buffer) to parse csv in parallel and in batches.
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Read, Seek},
+    path::Path,
+};
+
+use anyhow::Result;
+use flate2::read::MultiGzDecoder;
+use polars::io::mmap::MmapBytesReader;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Plain,
+    /// gzip and bgzip
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// detect compression from the first bytes of file
+    pub fn detect(path: impl AsRef<Path>) -> Result<Compression> {
+        Compression::from_reader(&mut File::open(path)?)
+    }
+
+    /// detect compression from the first bytes of an opened file, which is
+    /// rewound to the start
+    fn from_reader(file: &mut File) -> Result<Compression> {
+        let mut magic = [0_u8; 4];
+        let mut n = 0;
+        // a file may be shorter than 4 bytes, read until eof
+        while n < magic.len() {
+            let read = file.read(&mut magic[n..])?;
+            if read == 0 {
+                break;
+            }
+            n += read;
+        }
+        file.rewind()?;
+        let compression = if n >= 2 && magic[..2] == GZIP_MAGIC {
+            Compression::Gzip
+        } else if n >= 4 && magic == ZSTD_MAGIC {
+            Compression::Zstd
+        } else {
+            Compression::Plain
+        };
+        Ok(compression)
+    }
+}
+
+/// buffered line reader of an opened file, decompressed on the fly
+fn decode(file: File, compression: Compression) -> Result<Box<dyn BufRead>> {
+    let reader: Box<dyn BufRead> = match compression {
+        Compression::Plain => Box::new(BufReader::new(file)),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+    };
+    Ok(reader)
+}
+
+/// Open a buffered line reader, decompress on the fly if needed. Used for
+/// reading header or small files line by line.
+pub fn open_bufread(path: impl AsRef<Path>) -> Result<Box<dyn BufRead>> {
+    let mut file = File::open(path)?;
+    let compression = Compression::from_reader(&mut file)?;
+    decode(file, compression)
+}
+
+/// Open a reader for polars csv reader. Plain file is returned as [File] so
+/// that polars can mmap it, compressed file is decompressed into memory.
+pub fn open_mmap_reader(path: impl AsRef<Path>) -> Result<Box<dyn MmapBytesReader>> {
+    let mut file = File::open(path)?;
+    let reader: Box<dyn MmapBytesReader> = match Compression::from_reader(&mut file)? {
+        Compression::Plain => Box::new(file),
+        compression => {
+            let mut bytes = vec![];
+            decode(file, compression)?.read_to_end(&mut bytes)?;
+            Box::new(Cursor::new(bytes))
+        }
+    };
+    Ok(reader)
+}
+
+/// read the first line of a file without new line
+pub fn read_first_line(path: impl AsRef<Path>) -> Result<String> {
+    let mut first_line = "".to_string();
+    open_bufread(path)?.read_line(&mut first_line)?;
+    first_line = first_line.replace(['\n', '\r'], "");
+    Ok(first_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+    };
+
+    use flate2::{write::GzEncoder, Compression as GzLevel};
+
+    use super::{open_mmap_reader, read_first_line, Compression};
+
+    const TEXT: &str = "CHR\tPOS\tA1\tBeta\r\n1\t100\tA\t0.1\n";
+
+    #[test]
+    fn test_detect() {
+        let dir = std::env::temp_dir();
+        let plain = dir.join("pgs_compress_test.tsv");
+        fs::write(&plain, TEXT).unwrap();
+        assert_eq!(Compression::detect(&plain).unwrap(), Compression::Plain);
+        assert_eq!(read_first_line(&plain).unwrap(), "CHR\tPOS\tA1\tBeta");
+
+        // bgzip is concatenated gzip members, detected by magic not extension
+        let bgzip = dir.join("pgs_compress_test.tsv.txt");
+        let mut bytes = vec![];
+        for part in [&TEXT[..10], &TEXT[10..]] {
+            let mut encoder = GzEncoder::new(vec![], GzLevel::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            bytes.extend(encoder.finish().unwrap());
+        }
+        fs::write(&bgzip, bytes).unwrap();
+        assert_eq!(Compression::detect(&bgzip).unwrap(), Compression::Gzip);
+        assert_eq!(read_first_line(&bgzip).unwrap(), "CHR\tPOS\tA1\tBeta");
+
+        let zst = dir.join("pgs_compress_test.tsv.zst");
+        fs::write(&zst, zstd::encode_all(TEXT.as_bytes(), 0).unwrap()).unwrap();
+        assert_eq!(Compression::detect(&zst).unwrap(), Compression::Zstd);
+        assert_eq!(read_first_line(&zst).unwrap(), "CHR\tPOS\tA1\tBeta");
+        let mut text = String::new();
+        open_mmap_reader(&zst)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, TEXT);
+
+        // shorter than magic bytes
+        let short = dir.join("pgs_compress_test_short.tsv");
+        fs::write(&short, [0x1f]).unwrap();
+        assert_eq!(Compression::detect(&short).unwrap(), Compression::Plain);
+    }
+}
@@ -0,0 +1,276 @@
+//! Policy on duplicated variants in weight file. Variants are told apart by
+//! CHR:POS:A1[:A2], together with ID when matching by ID. Alleles are
+//! normalized, and a pair of alleles is put in a canonical order and strand
+//! by [canonical_alleles], so that a row with alleles swapped or on the
+//! opposite strand is the same variant. The policy is applied when
+//! validating rows, and the state is kept across batches, so that which row
+//! is kept does not depend on batch boundaries.
+//!
+//! Occurrences of variants are counted by [DupCounter] beforehand, either on
+//! the whole weights or by a first pass over batches, so that
+//! [DupTracker] only keeps state for duplicated variants.
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use genoreader::allele::{complement, normalize_allele, trim_common};
+
+use crate::Float;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum DupPolicy {
+    /// keep the first occurrence
+    First,
+    /// keep the last occurrence
+    Last,
+    /// keep one row with weights summed over all occurrences
+    Sum,
+    /// abort on any duplicated variant
+    Error,
+    /// drop all occurrences
+    Drop,
+}
+
+impl DupPolicy {
+    pub fn new(policy: &str) -> Result<DupPolicy> {
+        let my_policy = match policy {
+            "First" => DupPolicy::First,
+            "Last" => DupPolicy::Last,
+            "Sum" => DupPolicy::Sum,
+            "Error" => DupPolicy::Error,
+            "Drop" => DupPolicy::Drop,
+            _ => {
+                return Err(anyhow!(
+                    "Argument dup_policy should be one of the following: [ First, Last, Sum, \
+                     Error, Drop ], got {}",
+                    policy
+                ))
+            }
+        };
+        Ok(my_policy)
+    }
+}
+
+/// What to do with a row
+#[derive(Debug, PartialEq)]
+pub enum DupAction {
+    Keep,
+    /// keep the row with weights replaced by the sums over all occurrences
+    KeepSummed(Vec<Option<Float>>),
+    /// drop the row for the reason
+    Drop(String),
+}
+
+/// Occurrences of variants, counted before the policy is applied
+#[derive(Default)]
+pub struct DupCounter {
+    counts: HashMap<String, usize>,
+}
+
+impl DupCounter {
+    pub fn add(&mut self, variant: &str) {
+        match self.counts.get_mut(variant) {
+            Some(v) => *v += 1,
+            None => {
+                self.counts.insert(variant.to_owned(), 1);
+            }
+        }
+    }
+
+    /// variants found more than once and their number of occurrences
+    pub fn duplicated(self) -> HashMap<String, usize> {
+        self.counts.into_iter().filter(|(_, v)| *v > 1).collect()
+    }
+}
+
+pub struct DupTracker {
+    policy: DupPolicy,
+    /// variant -> number of occurrences, only for duplicated variants
+    counts: HashMap<String, usize>,
+    /// duplicated variant -> (line first seen, occurrences seen so far),
+    /// removed after the last occurrence. Not used by [DupPolicy::Drop].
+    seen: HashMap<String, (usize, usize)>,
+    /// duplicated variant -> weights summed so far, for [DupPolicy::Sum]
+    sums: HashMap<String, Vec<Option<Float>>>,
+}
+
+impl DupTracker {
+    /// `counts` is from [DupCounter::duplicated]
+    pub fn new(policy: DupPolicy, counts: HashMap<String, usize>) -> DupTracker {
+        DupTracker {
+            policy,
+            counts,
+            seen: HashMap::new(),
+            sums: HashMap::new(),
+        }
+    }
+
+    /// Action on a valid row of `variant` at `line`, `weights` are the scores
+    /// of the row, which are only used by [DupPolicy::Sum].
+    pub fn check(
+        &mut self,
+        variant: &str,
+        line: usize,
+        weights: &[Option<Float>],
+    ) -> Result<DupAction> {
+        let total = match self.counts.get(variant) {
+            Some(v) => *v,
+            None => return Ok(DupAction::Keep),
+        };
+        if self.policy == DupPolicy::Drop {
+            return Ok(DupAction::Drop(format!(
+                "variant found {} times, all dropped",
+                total
+            )));
+        }
+        let entry = self.seen.entry(variant.to_owned()).or_insert((line, 0));
+        entry.1 += 1;
+        let (first_line, n_seen) = *entry;
+        if n_seen == total {
+            self.seen.remove(variant);
+        }
+        let action = match self.policy {
+            DupPolicy::First if n_seen > 1 => {
+                DupAction::Drop(format!("duplicate of line {}, first one kept", first_line))
+            }
+            DupPolicy::Error if n_seen > 1 => {
+                return Err(anyhow!(
+                    "Duplicated variant {} at line {}, first seen at line {}",
+                    variant,
+                    line,
+                    first_line
+                ))
+            }
+            DupPolicy::Last if n_seen < total => {
+                DupAction::Drop(format!("duplicate of line {}, last one kept", first_line))
+            }
+            DupPolicy::Sum => {
+                let sums = self
+                    .sums
+                    .entry(variant.to_owned())
+                    .or_insert_with(|| vec![None; weights.len()]);
+                for (sum, weight) in sums.iter_mut().zip(weights) {
+                    if let Some(weight) = weight {
+                        *sum = Some(sum.unwrap_or(0.) + weight);
+                    }
+                }
+                if n_seen < total {
+                    DupAction::Drop(format!(
+                        "duplicate of line {}, summed into the last one",
+                        first_line
+                    ))
+                } else {
+                    DupAction::KeepSummed(self.sums.remove(variant).unwrap_or_default())
+                }
+            }
+            _ => DupAction::Keep,
+        };
+        Ok(action)
+    }
+}
+
+/// Canonical form of a pair of A1 and A2, the least of the pair swapped or
+/// complemented. Also return whether A1 is swapped, where weights of the row
+/// should be negated to be on the canonical A1. Palindromic pairs, such as
+/// A/T, are taken as swapped rather than complemented.
+pub fn canonical_alleles(a1: &str, a2: &str) -> ((String, String), bool) {
+    let (a1, a2) = trim_common(&normalize_allele(a1), &normalize_allele(a2));
+    let (c1, c2) = (complement(&a1), complement(&a2));
+    let forms = [
+        ((a1.clone(), a2.clone()), false),
+        ((a2, a1), true),
+        ((c1.clone(), c2.clone()), false),
+        ((c2, c1), true),
+    ];
+    // first of the least ones, so that swap is preferred for palindromic pairs
+    forms.into_iter().min_by(|a, b| a.0.cmp(&b.0)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_alleles, DupAction, DupCounter, DupPolicy, DupTracker};
+    use crate::Float;
+
+    fn key(a1: &str, a2: &str) -> (String, bool) {
+        let ((a1, a2), swapped) = canonical_alleles(a1, a2);
+        (format!("{}:{}", a1, a2), swapped)
+    }
+
+    #[test]
+    fn test_canonical_alleles() {
+        assert_eq!(key("A", "G"), ("A:G".to_owned(), false));
+        assert_eq!(key("g", "a"), ("A:G".to_owned(), true));
+        // opposite strand
+        assert_eq!(key("T", "C"), ("A:G".to_owned(), false));
+        assert_eq!(key("C", "T"), ("A:G".to_owned(), true));
+        // palindromic
+        assert_eq!(key("A", "T"), ("A:T".to_owned(), false));
+        assert_eq!(key("T", "A"), ("A:T".to_owned(), true));
+        // padded indel
+        assert_eq!(key("AT", "A"), key("T", "-"));
+    }
+
+    /// actions of the policy on variants a, b, a, c, a at lines 1 to 5, with
+    /// weights of the line number
+    fn check_all(policy: DupPolicy) -> Vec<DupAction> {
+        let variants = ["a", "b", "a", "c", "a"];
+        let mut counter = DupCounter::default();
+        for v in variants {
+            counter.add(v);
+        }
+        let mut tracker = DupTracker::new(policy, counter.duplicated());
+        let actions = variants
+            .iter()
+            .enumerate()
+            .map(|(i, v)| tracker.check(v, i + 1, &[Some(i as Float + 1.)]).unwrap())
+            .collect();
+        // nothing is left for duplicated variants after the last occurrence
+        assert!(tracker.seen.is_empty() && tracker.sums.is_empty());
+        actions
+    }
+
+    fn drop(reason: &str) -> DupAction {
+        DupAction::Drop(reason.to_owned())
+    }
+
+    #[test]
+    fn test_dup_policy() {
+        let keep = || DupAction::Keep;
+        let first = || drop("duplicate of line 1, first one kept");
+        assert_eq!(
+            check_all(DupPolicy::First),
+            vec![keep(), keep(), first(), keep(), first()]
+        );
+        let last = || drop("duplicate of line 1, last one kept");
+        assert_eq!(
+            check_all(DupPolicy::Last),
+            vec![last(), keep(), last(), keep(), keep()]
+        );
+        let summed = || drop("duplicate of line 1, summed into the last one");
+        assert_eq!(
+            check_all(DupPolicy::Sum),
+            vec![
+                summed(),
+                keep(),
+                summed(),
+                keep(),
+                DupAction::KeepSummed(vec![Some(9.)])
+            ]
+        );
+        let all = || drop("variant found 3 times, all dropped");
+        assert_eq!(
+            check_all(DupPolicy::Drop),
+            vec![all(), keep(), all(), keep(), all()]
+        );
+    }
+
+    #[test]
+    fn test_dup_policy_error() {
+        let mut counter = DupCounter::default();
+        counter.add("a");
+        counter.add("a");
+        let mut tracker = DupTracker::new(DupPolicy::Error, counter.duplicated());
+        assert_eq!(tracker.check("a", 2, &[]).unwrap(), DupAction::Keep);
+        let err = tracker.check("a", 5, &[]).unwrap_err().to_string();
+        assert_eq!(err, "Duplicated variant a at line 5, first seen at line 2");
+    }
+}
@@ -1,18 +1,35 @@
+pub mod batch;
+pub mod beta_set;
+pub mod columnar;
+pub mod compress;
+pub mod dedup;
+pub mod manifest;
+pub mod sniff;
+pub mod validate;
+pub mod weight_type;
+
 use std::{
     collections::HashMap,
-    fs::File,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, Seek},
     sync::Arc,
 };
 
 use anyhow::{anyhow, Result};
+use batch::BetaBatchReader;
+use columnar::ColumnarFormat;
+use dedup::DupPolicy;
+use log::info;
 use polars::{
     io::mmap::MmapBytesReader,
+    lazy::dsl::{col, lit, Expr},
     prelude::{
-        read_impl::OwnedBatchedCsvReader, CsvEncoding, CsvReader, DataFrame, DataType, Field,
-        Schema, SerReader,
+        read_impl::OwnedBatchedCsvReaderMmap, CsvEncoding, CsvReader, DataFrame, DataType, Field,
+        IntoLazy, Schema, SerReader,
     },
 };
+use sniff::TableFormat;
+use validate::{TypedFields, Validator};
+use weight_type::{WeightType, WEIGHT_TYPE_KEY};
 
 /// const for default column name
 pub const ID: &str = "ID";
@@ -20,25 +37,70 @@ pub const CHR: &str = "CHR";
 pub const POS: &str = "POS";
 pub const A1: &str = "A1";
 /// optional default column name
+pub const A2: &str = "A2";
 pub const FREQ: &str = "FREQ";
 pub const PVALUE: &str = "P";
 pub const RANK: &str = "RANK";
 
+/// Float type of weights and scores, f64 with the `f64` feature when
+/// reproducibility matters more than speed
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+/// polars type of [Float]
+#[cfg(not(feature = "f64"))]
+pub type FloatType = polars::prelude::Float32Type;
+#[cfg(feature = "f64")]
+pub type FloatType = polars::prelude::Float64Type;
+/// dtype of score columns
+#[cfg(not(feature = "f64"))]
+pub const FLOAT_DTYPE: DataType = DataType::Float32;
+#[cfg(feature = "f64")]
+pub const FLOAT_DTYPE: DataType = DataType::Float64;
+
+/// [Float] into f64, which is lossless in either precision
+#[cfg_attr(feature = "f64", allow(clippy::useless_conversion))]
+pub fn float_to_f64(value: Float) -> f64 {
+    f64::from(value)
+}
+/// optional genetic model flags, as in pgs catalog scoring file
+pub const IS_DOMINANT: &str = "is_dominant";
+pub const IS_RECESSIVE: &str = "is_recessive";
+
 #[derive(Debug)]
 pub struct BetaArg<'a> {
     // col
     pub chrom: &'a str,
     pub pos: &'a str,
     pub a1: &'a str,
+    pub a2: Option<&'a str>,
     pub freq: &'a str,
     pub snp_id: &'a str,
     pub pvalue: &'a str,
     // misc
     pub score_names: &'a Vec<String>,
+    /// `--weight-type` specs, such as `OR` or `Lassosum=OR`
+    pub weight_types: &'a Vec<String>,
     pub weight_path: &'a str,
+    /// other numeric columns to read, such as INFO used by Q ranges
+    pub extra_cols: Vec<String>,
+    /// path to write rejected rows in lenient mode
+    pub rejected_path: String,
+    /// path to write rows of duplicated variants dropped by `dup_policy`
+    pub duplicates_path: String,
+    pub dup_policy: DupPolicy,
     // flag
+    /// abort on invalid rows instead of rejecting them
+    pub strict: bool,
+    /// only keep snp with `from <= P <= to`, used to prune snp out of all
+    /// Q ranges when reading
+    pub pvalue_range: Option<(f32, f32)>,
     pub need_freq: bool,
     pub need_id: bool,
+    /// fill missing CHR and POS from structured ID, for matching by ID with
+    /// fallback to position
+    pub parse_id: bool,
     pub need_pvalue: bool,
 }
 
@@ -48,9 +110,12 @@ impl<'a> BetaArg<'a> {
         schema_table.insert(self.chrom, (CHR, DataType::Utf8));
         schema_table.insert(self.pos, (POS, DataType::Int32));
         schema_table.insert(self.a1, (A1, DataType::Utf8));
+        if let Some(a2) = self.a2 {
+            schema_table.insert(a2, (A2, DataType::Utf8));
+        }
 
         for i in self.score_names {
-            schema_table.insert(i, (i, DataType::Float32));
+            schema_table.insert(i, (i, FLOAT_DTYPE));
         }
 
         if self.need_freq {
@@ -65,80 +130,241 @@ impl<'a> BetaArg<'a> {
             schema_table.insert(self.pvalue, (PVALUE, DataType::Float32));
         }
 
+        for i in &self.extra_cols {
+            schema_table
+                .entry(i.as_str())
+                .or_insert((i.as_str(), DataType::Float32));
+        }
+
         Ok(schema_table)
     }
 
-    pub fn get_beta_schema(&self) -> Result<(Schema, Vec<String>)> {
-        // read first line and remove new line
-        let file = File::open(self.weight_path)?;
-        let mut first_line = "".to_string();
-        BufReader::new(file).read_line(&mut first_line)?;
-        first_line = first_line.replace(['\n', '\r'], "");
-
-        // get required col
+    /// Map header of weight file to required columns, including genetic model
+    /// flags if present. Return header with required columns renamed, and
+    /// required columns with their types in header order.
+    fn map_header(&self, header: &[String]) -> Result<(Vec<String>, TypedFields)> {
         let mut schema_table = self.get_schema_table()?;
-        let cols: Vec<String> = schema_table
-            .keys()
-            .map(|v| v.to_owned().to_owned())
-            .collect();
+        for flag in [IS_DOMINANT, IS_RECESSIVE] {
+            if header.iter().any(|v| v == flag) {
+                schema_table.insert(flag, (flag, DataType::Boolean));
+            }
+        }
 
-        // generate schema
-        let mut field_vec = vec![];
-        for i in first_line.split('\t') {
-            let (colname, my_datatype) = match schema_table.remove(i) {
-                Some(v) => v.clone(),
-                None => (i, DataType::Utf8),
+        let mut renamed_header = vec![];
+        let mut typed_fields = vec![];
+        for i in header.iter() {
+            let colname = match schema_table.remove(i.as_str()) {
+                Some((colname, my_datatype)) => {
+                    typed_fields.push((colname.to_owned(), my_datatype));
+                    colname
+                }
+                None => i.as_str(),
             };
-            field_vec.push(Field::new(colname, my_datatype))
+            renamed_header.push(colname.to_owned());
         }
 
         // check if there is some column not found
         if !schema_table.is_empty() {
             return Err(anyhow!(
-                "Required column not found in beta file {:?}",
-                schema_table.keys()
+                "Required column not found in beta file {:?}, the detected header is {:?}",
+                schema_table.keys(),
+                header,
             ));
         }
+        Ok((renamed_header, typed_fields))
+    }
+
+    /// Detect format of the weight file, and generate schema from header.
+    /// All columns are read as string and validated by the returned
+    /// [Validator], which also holds the columns to be used.
+    pub fn get_beta_schema(&self) -> Result<(Schema, Validator, TableFormat)> {
+        // detect delimiter and header, the file may be compressed
+        let format = TableFormat::sniff(self.weight_path)?;
+        info!(
+            "Detected {} delimited header in {} (skip {} comment lines): {:?}",
+            format.delimiter, self.weight_path, format.skip_rows, format.header
+        );
+
+        // generate schema, columns are renamed in schema
+        let (renamed_header, typed_fields) = self.map_header(&format.header)?;
+        let schema: Schema = renamed_header
+            .iter()
+            .map(|v| Field::new(v, DataType::Utf8))
+            .collect();
 
-        Ok((Schema::from_iter(field_vec), cols))
+        // line number of the first data row, after comments and header
+        let validator = self.new_validator(typed_fields, format.skip_rows + 2)?;
+        Ok((schema, validator, format))
     }
 
-    pub fn batch_read(&self, mut batch_size: usize) -> Result<(OwnedBatchedCsvReader, Vec<String>)> {
-        let (my_schmema, cols) = self.get_beta_schema()?;
+    fn new_validator(
+        &self,
+        typed_fields: TypedFields,
+        first_line: usize,
+    ) -> Result<Validator> {
+        let weight_types = self.get_weight_types()?;
+        for (score_name, weight_type) in self.score_names.iter().zip(&weight_types) {
+            if weight_type.is_ratio() {
+                info!(
+                    "Score {} in {} is {}, apply {} transform",
+                    score_name,
+                    self.weight_path,
+                    weight_type,
+                    weight_type.transform_name()
+                );
+            }
+        }
+        let validator = Validator::new(
+            typed_fields,
+            self.score_names,
+            &weight_types,
+            self.strict,
+            self.weight_path,
+            &self.rejected_path,
+            first_line,
+        )
+        .with_pvalue_range(self.pvalue_range)
+        .with_parse_id(self.parse_id)
+        .with_dup_policy(self.dup_policy, &self.duplicates_path);
+        Ok(validator)
+    }
+
+    /// Weight type of each score, ratio will be log transformed into beta.
+    /// Weight type in header is only available for text file.
+    pub fn get_weight_types(&self) -> Result<Vec<WeightType>> {
+        let format = match ColumnarFormat::detect(self.weight_path)? {
+            Some(_) => None,
+            None => Some(TableFormat::sniff(self.weight_path)?),
+        };
+        let header_type = format.as_ref().and_then(|v| v.get_meta(WEIGHT_TYPE_KEY));
+        WeightType::resolve(self.weight_types, self.score_names, header_type)
+    }
+
+    pub fn batch_read(&self, mut batch_size: usize) -> Result<(BetaBatchReader, Vec<String>)> {
+        if let Some(format) = ColumnarFormat::detect(self.weight_path)? {
+            return self.batch_read_columnar(format, batch_size);
+        }
+        let (my_schmema, mut validator, format) = self.get_beta_schema()?;
+        let cols = validator.cols();
         let my_schmema = Arc::new(my_schmema);
         // https://github.com/pola-rs/polars/blob/main/py-polars/src/batched_csv.rs
-        let file = File::open(self.weight_path)?;
-        // make sure batch_size > line number 
-        let tmp_beta: DataFrame = CsvReader::from_path(self.weight_path)?
-            .with_delimiter(b'\t')
-            .with_encoding(CsvEncoding::LossyUtf8)
-            .with_schema(my_schmema.clone())
-            .with_n_rows(Some(batch_size))
-            .has_header(true)
-            .finish()?;
-        if tmp_beta.height() < batch_size{
-            batch_size = tmp_beta.height() ;
+        // use batched mmap reader since compressed file is decompressed into memory,
+        // which cannot be read by the file based batched reader.
+        let mut reader = format.open_reader(self.weight_path)?;
+        // make sure batch_size > line number. Lines are counted on the opened
+        // reader, which is then rewound, so that the file is only opened and
+        // decompressed once.
+        let n_lines = BufReader::new(&mut reader)
+            .lines()
+            .skip(format.skip_rows + 1)
+            .take(batch_size)
+            .count();
+        reader.rewind()?;
+        if n_lines < batch_size {
+            batch_size = n_lines;
+        }
+
+        // first pass counting duplicated variants on the same reader
+        {
+            let mut count_reader = csv_reader(&mut reader, &format)
+                .with_chunk_size(batch_size)
+                .with_schema(my_schmema.clone());
+            let mut batches = count_reader.batched_borrowed_mmap()?;
+            while let Some(raw) = batches.next_batches(1)? {
+                validator.count(&raw[0])?;
+            }
         }
+        reader.rewind()?;
+        validator.finish_count();
 
-        let reader = Box::new(file) as Box<dyn MmapBytesReader>;
-        let reader: OwnedBatchedCsvReader = CsvReader::new(reader)
+        let reader: OwnedBatchedCsvReaderMmap = csv_reader(reader, &format)
             .with_chunk_size(batch_size)
-            .with_delimiter(b'\t')
-            .with_encoding(CsvEncoding::LossyUtf8)
-            .has_header(true)
-            .batched_read(Some(my_schmema))?;
-        Ok((reader, cols))
+            .batched_mmap(Some(my_schmema))?;
+        Ok((BetaBatchReader::Csv(reader, Box::new(validator)), cols))
     }
 
     pub fn read(&self) -> Result<(DataFrame, Vec<String>)> {
-        let (my_schmema, cols) = self.get_beta_schema()?;
-        let beta: DataFrame = CsvReader::from_path(self.weight_path)?
-            .with_delimiter(b'\t')
-            .with_encoding(CsvEncoding::LossyUtf8)
+        if let Some(format) = ColumnarFormat::detect(self.weight_path)? {
+            return self.read_columnar(format);
+        }
+        let (my_schmema, mut validator, format) = self.get_beta_schema()?;
+        let raw: DataFrame = csv_reader(format.open_reader(self.weight_path)?, &format)
             .with_schema(Arc::new(my_schmema))
-            .has_header(true)
             .finish()?;
+        let beta = validator.validate(&raw)?;
+        let cols = validator.cols();
 
         Ok((beta, cols))
     }
+
+    /// Expressions to select required columns from columnar file, which are
+    /// renamed and kept in their own types, to be cast by the validator.
+    /// Return the expressions and index of required columns in header.
+    fn get_select_exprs(&self, header: &[String]) -> Result<(Vec<Expr>, Vec<usize>, Validator)> {
+        info!("Got columnar weight file {} with columns {:?}", self.weight_path, header);
+        let (renamed_header, typed_fields) = self.map_header(header)?;
+        let mut exprs = vec![];
+        let mut projection = vec![];
+        for (idx, (raw_name, new_name)) in header.iter().zip(&renamed_header).enumerate() {
+            if typed_fields.iter().any(|(v, _)| v == new_name) {
+                exprs.push(col(raw_name).alias(new_name));
+                projection.push(idx);
+            }
+        }
+        // row number instead of line number for columnar file
+        let validator = self.new_validator(typed_fields, 1)?;
+        Ok((exprs, projection, validator))
+    }
+
+    /// Read Parquet or Arrow IPC file. Only required columns are read, and P
+    /// value range is pushed down to the scan.
+    fn read_columnar(&self, format: ColumnarFormat) -> Result<(DataFrame, Vec<String>)> {
+        let header = format.header(self.weight_path)?;
+        let (exprs, _, mut validator) = self.get_select_exprs(&header)?;
+        let mut lazy_frame = format.scan(self.weight_path)?;
+        if let Some((from, to)) = self.pvalue_range {
+            // compared in the precision P is parsed in, whatever the type in
+            // the file is
+            let pvalue = col(self.pvalue).cast(DataType::Float32);
+            lazy_frame = lazy_frame.filter(
+                pvalue
+                    .clone()
+                    .gt_eq(lit(from))
+                    .and(pvalue.lt_eq(lit(to))),
+            );
+        }
+        let raw = lazy_frame.select(exprs).collect()?;
+        let beta = validator.validate(&raw)?;
+        Ok((beta, validator.cols()))
+    }
+
+    /// Columnar file is read in batches with required columns only, see
+    /// [ColumnarFormat::batched]. P value range is applied on each batch by
+    /// the validator.
+    fn batch_read_columnar(
+        &self,
+        format: ColumnarFormat,
+        batch_size: usize,
+    ) -> Result<(BetaBatchReader, Vec<String>)> {
+        let header = format.header(self.weight_path)?;
+        let (exprs, projection, mut validator) = self.get_select_exprs(&header)?;
+        let cols = validator.cols();
+        // first pass counting duplicated variants
+        let mut count_reader = format.batched(self.weight_path, projection.clone(), batch_size)?;
+        while let Some(raw) = count_reader.next_batch()? {
+            validator.count(&raw.lazy().select(exprs.as_slice()).collect()?)?;
+        }
+        validator.finish_count();
+        let reader = format.batched(self.weight_path, projection, batch_size)?;
+        Ok((BetaBatchReader::Columnar(reader, exprs, Box::new(validator)), cols))
+    }
+}
+
+/// csv reader of weight file in the detected format
+fn csv_reader<'a, R: MmapBytesReader + 'a>(reader: R, format: &TableFormat) -> CsvReader<'a, R> {
+    CsvReader::new(reader)
+        .with_delimiter(format.delimiter.as_byte())
+        .with_skip_rows(format.skip_rows)
+        .with_encoding(CsvEncoding::LossyUtf8)
+        .has_header(true)
 }
@@ -0,0 +1,180 @@
+//! Model manifest, a tsv listing models to be scored in one run. The
+//! following columns are required:
+//! - name: model name, used as prefix of output score names
+//! - path: path to weight file
+//! - score_names: comma separated score columns in weight file
+//!
+//! Optional columns `chrom`, `pos`, `a1`, `a2`, `snp_id`, `freq` and `pvalue`
+//! override the column names of the weight file for that model. Empty value
+//! means the global column name is used.
+use std::{io::BufRead, path::Path};
+
+use anyhow::{anyhow, Result};
+
+use crate::compress::open_bufread;
+
+const NAME: &str = "name";
+const PATH: &str = "path";
+const SCORE_NAMES: &str = "score_names";
+
+/// One model to be read from a weight file
+#[derive(Debug, Clone)]
+pub struct ModelSpec {
+    pub name: String,
+    pub path: String,
+    /// score columns in weight file
+    pub score_names: Vec<String>,
+    /// output score names, which are unique among models
+    pub out_names: Vec<String>,
+    // column mapping, None for global setting
+    pub chrom: Option<String>,
+    pub pos: Option<String>,
+    pub a1: Option<String>,
+    pub a2: Option<String>,
+    pub snp_id: Option<String>,
+    pub freq: Option<String>,
+    pub pvalue: Option<String>,
+}
+
+impl ModelSpec {
+    pub fn new(name: &str, path: &str, score_names: Vec<String>) -> ModelSpec {
+        // model with single score is named after model, otherwise
+        // {model}_{score}
+        let out_names = if score_names.len() == 1 {
+            vec![name.to_owned()]
+        } else {
+            score_names
+                .iter()
+                .map(|v| format!("{name}_{v}"))
+                .collect()
+        };
+        ModelSpec {
+            name: name.to_owned(),
+            path: path.to_owned(),
+            score_names,
+            out_names,
+            chrom: None,
+            pos: None,
+            a1: None,
+            a2: None,
+            snp_id: None,
+            freq: None,
+            pvalue: None,
+        }
+    }
+
+    /// Models from several weight paths sharing the same score names. Model
+    /// name is the file name without extensions.
+    pub fn from_paths(paths: &[&str], score_names: &[String]) -> Result<Vec<ModelSpec>> {
+        let mut specs = vec![];
+        for path in paths {
+            let file_name = Path::new(path)
+                .file_name()
+                .and_then(|v| v.to_str())
+                .ok_or_else(|| anyhow!("Invalid weight path {}", path))?;
+            let name = file_name.split('.').next().unwrap_or(file_name);
+            specs.push(ModelSpec::new(name, path, score_names.to_vec()));
+        }
+        check_unique(&specs)?;
+        Ok(specs)
+    }
+
+    /// read models from manifest
+    pub fn from_manifest(manifest_path: &str) -> Result<Vec<ModelSpec>> {
+        let mut lines = open_bufread(manifest_path)?.lines();
+        let header: Vec<String> = match lines.next() {
+            Some(v) => v?.trim_end().split('\t').map(|v| v.to_owned()).collect(),
+            None => return Err(anyhow!("Manifest {} is empty", manifest_path)),
+        };
+        for required in [NAME, PATH, SCORE_NAMES] {
+            if !header.iter().any(|v| v == required) {
+                return Err(anyhow!(
+                    "Required column {} not found in manifest {}",
+                    required,
+                    manifest_path
+                ));
+            }
+        }
+
+        let mut specs = vec![];
+        for line in lines {
+            let line = line?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let get = |col: &str| -> Option<String> {
+                header
+                    .iter()
+                    .position(|v| v == col)
+                    .and_then(|i| fields.get(i))
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+            };
+            let (name, path, score_names) = match (get(NAME), get(PATH), get(SCORE_NAMES)) {
+                (Some(name), Some(path), Some(score_names)) => (name, path, score_names),
+                _ => {
+                    return Err(anyhow!(
+                        "name, path and score_names should not be empty in manifest, got {}",
+                        line
+                    ))
+                }
+            };
+            let score_names = score_names.split(',').map(|v| v.to_owned()).collect();
+            let mut spec = ModelSpec::new(&name, &path, score_names);
+            spec.chrom = get("chrom");
+            spec.pos = get("pos");
+            spec.a1 = get("a1");
+            spec.a2 = get("a2");
+            spec.snp_id = get("snp_id");
+            spec.freq = get("freq");
+            spec.pvalue = get("pvalue");
+            specs.push(spec);
+        }
+        if specs.is_empty() {
+            return Err(anyhow!("No model found in manifest {}", manifest_path));
+        }
+        check_unique(&specs)?;
+        Ok(specs)
+    }
+}
+
+/// output score names should be unique among models
+fn check_unique(specs: &[ModelSpec]) -> Result<()> {
+    let mut out_names: Vec<&String> = specs.iter().flat_map(|v| &v.out_names).collect();
+    let total = out_names.len();
+    out_names.sort();
+    out_names.dedup();
+    if out_names.len() != total {
+        return Err(anyhow!(
+            "Duplicated model name found, output score names should be unique: {:?}",
+            out_names
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ModelSpec;
+
+    #[test]
+    fn test_out_names() {
+        let single = ModelSpec::new("PGS1", "PGS1.tsv", vec!["Lassosum".to_owned()]);
+        assert_eq!(single.out_names, vec!["PGS1"]);
+        let multi = ModelSpec::new("PGS1", "PGS1.tsv", vec!["A".to_owned(), "B".to_owned()]);
+        assert_eq!(multi.out_names, vec!["PGS1_A", "PGS1_B"]);
+    }
+
+    #[test]
+    fn test_from_paths() {
+        let score_names = vec!["Lassosum".to_owned()];
+        let specs =
+            ModelSpec::from_paths(&["data/ModelA.tsv.gz", "ModelB.tsv"], &score_names).unwrap();
+        let names: Vec<&str> = specs.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["ModelA", "ModelB"]);
+        // same file name in different folders
+        assert!(ModelSpec::from_paths(&["a/Model.tsv", "b/Model.tsv"], &score_names).is_err());
+    }
+}
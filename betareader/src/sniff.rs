@@ -0,0 +1,229 @@
+//! Detect the format of a delimited text file, such as weight file from
+//! different tools:
+//! - delimiter: tab, comma or runs of whitespace
+//! - comment preamble: leading lines starting with `#`, such as the header of
+//!   pgs catalog scoring file. A `#` line immediately followed by the header
+//!   with the same number of fields is regarded as header (e.g. `#CHROM`).
+//! - quoted fields and utf8 BOM in header
+use std::{
+    fmt,
+    io::{BufRead, Cursor, Read},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use polars::io::mmap::MmapBytesReader;
+
+use crate::compress::{open_bufread, open_mmap_reader};
+
+const BOM: char = '\u{feff}';
+const COMMENT: char = '#';
+const QUOTE: char = '"';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    Tab,
+    Comma,
+    /// runs of space or tab
+    Whitespace,
+}
+
+impl Delimiter {
+    fn detect(line: &str) -> Delimiter {
+        if line.contains('\t') {
+            Delimiter::Tab
+        } else if split_quoted(line, ',').len() > 1 {
+            Delimiter::Comma
+        } else {
+            Delimiter::Whitespace
+        }
+    }
+
+    /// delimiter for polars csv reader. Whitespace delimited file is
+    /// converted into tab delimited in [TableFormat::open_reader].
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            Delimiter::Tab | Delimiter::Whitespace => b'\t',
+            Delimiter::Comma => b',',
+        }
+    }
+
+    /// split a line into fields, quotes are removed
+    pub fn split(&self, line: &str) -> Vec<String> {
+        match self {
+            Delimiter::Tab => split_quoted(line, '\t'),
+            Delimiter::Comma => split_quoted(line, ','),
+            Delimiter::Whitespace => line.split_whitespace().map(unquote).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Delimiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Delimiter::Tab => "tab",
+            Delimiter::Comma => "comma",
+            Delimiter::Whitespace => "whitespace",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// split by delimiter outside of quotes
+fn split_quoted(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut in_quote = false;
+    for c in line.chars() {
+        if c == QUOTE {
+            in_quote = !in_quote;
+        } else if c == delimiter && !in_quote {
+            fields.push(std::mem::take(&mut field));
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn unquote(field: &str) -> String {
+    field.trim_matches(QUOTE).to_owned()
+}
+
+/// The detected format of a delimited text file
+#[derive(Debug, Clone)]
+pub struct TableFormat {
+    pub delimiter: Delimiter,
+    /// number of comment lines before header
+    pub skip_rows: usize,
+    /// column names in header
+    pub header: Vec<String>,
+    /// comment lines before header, `#` are removed
+    pub preamble: Vec<String>,
+}
+
+impl TableFormat {
+    pub fn sniff(path: impl AsRef<Path>) -> Result<TableFormat> {
+        let path = path.as_ref();
+        let mut comment_lines: Vec<String> = vec![];
+        let mut header_line: Option<String> = None;
+        for line in open_bufread(path)?.lines() {
+            let line = line?;
+            let line = line.trim_start_matches(BOM).trim_end_matches(['\n', '\r']);
+            if line.starts_with(COMMENT) {
+                comment_lines.push(line.to_owned());
+            } else {
+                header_line = Some(line.to_owned());
+                break;
+            }
+        }
+        let header_line = header_line
+            .ok_or_else(|| anyhow!("No header found in {}", path.display()))?;
+        let delimiter = Delimiter::detect(&header_line);
+        let mut header = delimiter.split(&header_line);
+
+        // the last comment line may be the header itself, such as #CHROM
+        if let Some(last) = comment_lines.last() {
+            let last_fields = delimiter.split(last.trim_start_matches(COMMENT));
+            if last_fields.len() > 1 && last_fields.len() == header.len() {
+                header = last_fields;
+                comment_lines.pop();
+            }
+        }
+        let skip_rows = comment_lines.len();
+        let preamble = comment_lines
+            .into_iter()
+            .map(|v| v.trim_start_matches(COMMENT).to_owned())
+            .collect();
+
+        Ok(TableFormat {
+            delimiter,
+            skip_rows,
+            header,
+            preamble,
+        })
+    }
+
+    /// Get value of `key=value` in preamble, such as `#weight_type=OR` in
+    /// pgs catalog scoring file.
+    pub fn get_meta(&self, key: &str) -> Option<&str> {
+        self.preamble.iter().find_map(|line| {
+            let (k, v) = line.split_once('=')?;
+            (k.trim() == key).then_some(v.trim())
+        })
+    }
+
+    /// Open reader for polars. Whitespace delimited file is loaded into
+    /// memory and converted into tab delimited.
+    pub fn open_reader(&self, path: impl AsRef<Path>) -> Result<Box<dyn MmapBytesReader>> {
+        if self.delimiter != Delimiter::Whitespace {
+            return open_mmap_reader(path);
+        }
+        let mut text = String::new();
+        open_bufread(path)?.read_to_string(&mut text)?;
+        let mut bytes: Vec<u8> = Vec::with_capacity(text.len());
+        for (cc, line) in text.lines().enumerate() {
+            if cc < self.skip_rows {
+                bytes.extend_from_slice(line.as_bytes());
+            } else {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                bytes.extend_from_slice(fields.join("\t").as_bytes());
+            }
+            bytes.push(b'\n');
+        }
+        Ok(Box::new(Cursor::new(bytes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Read};
+
+    use super::{Delimiter, TableFormat};
+
+    #[test]
+    fn test_delimiter() {
+        assert_eq!(Delimiter::detect("CHR\tPOS\tA1"), Delimiter::Tab);
+        assert_eq!(Delimiter::detect("CHR,POS,A1"), Delimiter::Comma);
+        assert_eq!(Delimiter::detect("CHR  POS A1"), Delimiter::Whitespace);
+        // comma in quotes is not a delimiter
+        assert_eq!(Delimiter::detect("\"CHR,POS\" A1"), Delimiter::Whitespace);
+        assert_eq!(Delimiter::Comma.split("\"a,b\",c"), vec!["a,b", "c"]);
+        assert_eq!(Delimiter::Whitespace.split(" \"a\"   b "), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_sniff_preamble() {
+        let path = std::env::temp_dir().join("pgs_sniff_test_catalog.txt");
+        let lines = [
+            "\u{feff}###PGS CATALOG SCORING FILE",
+            "#weight_type=OR",
+            "chr_name\tchr_position\teffect_allele\teffect_weight",
+            "1\t100\tA\t1.2",
+        ];
+        fs::write(&path, lines.join("\n")).unwrap();
+        let format = TableFormat::sniff(&path).unwrap();
+        assert_eq!(format.delimiter, Delimiter::Tab);
+        assert_eq!(format.skip_rows, 2);
+        assert_eq!(format.header[0], "chr_name");
+        assert_eq!(format.get_meta("weight_type"), Some("OR"));
+    }
+
+    #[test]
+    fn test_sniff_comment_header() {
+        let path = std::env::temp_dir().join("pgs_sniff_test_chrom.txt");
+        fs::write(&path, "##source=x\n#CHROM POS  A1 BETA\n1  100 A 0.1\n").unwrap();
+        let format = TableFormat::sniff(&path).unwrap();
+        assert_eq!(format.delimiter, Delimiter::Whitespace);
+        assert_eq!(format.skip_rows, 1);
+        assert_eq!(format.header, vec!["CHROM", "POS", "A1", "BETA"]);
+        assert_eq!(format.preamble, vec!["source=x"]);
+
+        // whitespace is converted to tab, preamble is kept as is
+        let mut text = String::new();
+        let mut reader = format.open_reader(&path).unwrap();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "##source=x\n#CHROM\tPOS\tA1\tBETA\n1\t100\tA\t0.1\n");
+    }
+}
@@ -0,0 +1,1103 @@
+//! Line level validation of weight file. All columns of text file are read as
+//! string, and parsed column by column into the required types, while typed
+//! columns of columnar file are cast instead. A malformed row is reported
+//! with its line number and reason instead of an opaque parse error or being
+//! silently dropped. Raw rows are only looked up again when they are written
+//! to the rejected file.
+//!
+//! In strict mode, any invalid row aborts the reading. Otherwise invalid rows
+//! are written to the rejected file with line number and reasons. Valid rows
+//! of duplicated variants are resolved by [DupPolicy], and dropped ones are
+//! written to the duplicates file.
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+};
+
+use anyhow::{anyhow, Result};
+use genoreader::{
+    allele::{normalize_allele, DELETION, EMPTY_ALLELE, INSERTION},
+    variant_id::VariantId,
+};
+use log::warn;
+use polars::{
+    lazy::dsl::{col, lit},
+    prelude::{AnyValue, DataFrame, DataType, IntoLazy, NamedFrom, Series, TakeRandomUtf8},
+};
+use thiserror::Error;
+
+use crate::{
+    dedup::{canonical_alleles, DupAction, DupCounter, DupPolicy, DupTracker},
+    float_to_f64,
+    weight_type::WeightType,
+    Float, A1, A2, CHR, FREQ, ID, POS, PVALUE,
+};
+
+/// values regarded as missing
+const NULL_VALUES: [&str; 3] = ["", "NA", "."];
+/// values of flag column
+const TRUE_VALUES: [&str; 5] = ["true", "t", "1", "yes", "y"];
+const FALSE_VALUES: [&str; 5] = ["false", "f", "0", "no", "n"];
+/// number of errors shown in strict mode
+const SHOW_ERRORS: usize = 10;
+
+#[derive(Debug, Clone, Error)]
+pub enum LineError {
+    #[error("bad number in {col}: {value:?}")]
+    BadNumber { col: String, value: String },
+    #[error("missing required field {0}")]
+    MissingField(String),
+    #[error("invalid allele in {col}: {value:?}")]
+    InvalidAllele { col: String, value: String },
+    #[error("non-finite weight in {col}: {value}")]
+    NonFinite { col: String, value: String },
+    #[error("non-positive {weight_type} in {col}: {value}")]
+    NonPositiveRatio {
+        col: String,
+        value: Float,
+        weight_type: WeightType,
+    },
+    #[error("bad flag in {col}: {value:?}")]
+    BadFlag { col: String, value: String },
+    #[error("variant should not be both dominant and recessive")]
+    ConflictingModel,
+}
+
+/// required columns and their types after validation, in header order
+pub type TypedFields = Vec<(String, DataType)>;
+
+/// parsed value of a field
+enum Value<'a> {
+    Str(Option<Cow<'a, str>>),
+    Int(Option<i32>),
+    /// f32 or f64 by the dtype of the column, held exactly in f64
+    Float(Option<f64>),
+    Bool(Option<bool>),
+}
+
+impl Value<'_> {
+    /// value not borrowing the raw field
+    fn into_owned<'b>(self) -> Value<'b> {
+        match self {
+            Value::Str(v) => Value::Str(v.map(|v| Cow::Owned(v.into_owned()))),
+            Value::Int(v) => Value::Int(v),
+            Value::Float(v) => Value::Float(v),
+            Value::Bool(v) => Value::Bool(v),
+        }
+    }
+}
+
+/// parsed values of a column, rows with errors are None
+enum Column<'a> {
+    Str(Vec<Option<Cow<'a, str>>>),
+    Int(Vec<Option<i32>>),
+    /// f32 or f64 by the dtype of the column, held exactly in f64
+    Float(Vec<Option<f64>>),
+    Bool(Vec<Option<bool>>),
+}
+
+impl<'a> Column<'a> {
+    fn with_capacity(dtype: &DataType, capacity: usize) -> Column<'a> {
+        match dtype {
+            DataType::Int32 => Column::Int(Vec::with_capacity(capacity)),
+            DataType::Float32 | DataType::Float64 => Column::Float(Vec::with_capacity(capacity)),
+            DataType::Boolean => Column::Bool(Vec::with_capacity(capacity)),
+            _ => Column::Str(Vec::with_capacity(capacity)),
+        }
+    }
+
+    /// value is parsed by the dtype of the column
+    fn push(&mut self, value: Option<Value<'a>>) {
+        match (self, value) {
+            (Column::Str(c), Some(Value::Str(v))) => c.push(v),
+            (Column::Int(c), Some(Value::Int(v))) => c.push(v),
+            (Column::Float(c), Some(Value::Float(v))) => c.push(v),
+            (Column::Bool(c), Some(Value::Bool(v))) => c.push(v),
+            (Column::Str(c), _) => c.push(None),
+            (Column::Int(c), _) => c.push(None),
+            (Column::Float(c), _) => c.push(None),
+            (Column::Bool(c), _) => c.push(None),
+        }
+    }
+
+    fn float(&self, idx: usize) -> Option<f64> {
+        match self {
+            Column::Float(c) => c[idx],
+            _ => None,
+        }
+    }
+
+    fn str(&self, idx: usize) -> Option<&str> {
+        match self {
+            Column::Str(c) => c[idx].as_deref(),
+            _ => None,
+        }
+    }
+
+    fn set_float(&mut self, idx: usize, value: Option<f64>) {
+        if let Column::Float(c) = self {
+            c[idx] = value;
+        }
+    }
+
+    /// append the value of row `idx` to a variant key
+    fn push_key(&self, idx: usize, key: &mut String) {
+        match self {
+            Column::Str(c) => match &c[idx] {
+                Some(v) => key.push_str(v),
+                None => return,
+            },
+            Column::Int(c) => match c[idx] {
+                Some(v) => key.push_str(&v.to_string()),
+                None => return,
+            },
+            Column::Float(_) | Column::Bool(_) => return,
+        }
+        key.push(':');
+    }
+
+    /// series of rows to be kept
+    fn into_series(self, name: &str, dtype: &DataType, keep: &[bool]) -> Series {
+        fn kept<T>(values: Vec<T>, keep: &[bool]) -> Vec<T> {
+            values
+                .into_iter()
+                .zip(keep)
+                .filter_map(|(v, k)| k.then_some(v))
+                .collect()
+        }
+        match (self, dtype) {
+            (Column::Int(c), _) => Series::new(name, kept(c, keep)),
+            (Column::Bool(c), _) => Series::new(name, kept(c, keep)),
+            (Column::Float(c), DataType::Float32) => Series::new(
+                name,
+                kept(c, keep)
+                    .into_iter()
+                    .map(|v| v.map(|v| v as f32))
+                    .collect::<Vec<Option<f32>>>(),
+            ),
+            (Column::Float(c), _) => Series::new(name, kept(c, keep)),
+            (Column::Str(c), _) => Series::new(
+                name,
+                kept(c, keep)
+                    .into_iter()
+                    .map(|v| v.map(|v| v.into_owned()))
+                    .collect::<Vec<Option<String>>>(),
+            ),
+        }
+    }
+}
+
+/// Raw values of a column, string from text file, or typed from columnar
+/// file, which is cast into the required type instead of parsed from string
+enum RawColumn<'a> {
+    Str(Vec<Option<&'a str>>),
+    /// cast values, None if missing or failed to cast, told apart by the raw
+    /// column
+    Int(Vec<Option<i32>>, &'a Series),
+    Float(Vec<Option<f64>>, &'a Series),
+    Bool(Vec<Option<bool>>, &'a Series),
+}
+
+impl<'a> RawColumn<'a> {
+    /// `str_col` is the raw column cast into string, for string field of
+    /// other types
+    fn new(
+        raw: &'a Series,
+        str_col: Option<&'a Series>,
+        dtype: &DataType,
+    ) -> Result<RawColumn<'a>> {
+        let raw_col = match (str_col, dtype) {
+            (Some(v), _) => RawColumn::Str(v.utf8()?.into_iter().collect()),
+            (None, _) if raw.dtype() == &DataType::Utf8 => {
+                RawColumn::Str(raw.utf8()?.into_iter().collect())
+            }
+            (None, DataType::Int32) => {
+                RawColumn::Int(raw.cast(dtype)?.i32()?.into_iter().collect(), raw)
+            }
+            (None, DataType::Boolean) => {
+                RawColumn::Bool(raw.cast(dtype)?.bool()?.into_iter().collect(), raw)
+            }
+            (None, _) => RawColumn::Float(
+                raw.cast(&DataType::Float64)?.f64()?.into_iter().collect(),
+                raw,
+            ),
+        };
+        Ok(raw_col)
+    }
+
+    /// whether the value is missing, which may be filled or optional
+    fn is_null(&self, idx: usize) -> bool {
+        match self {
+            RawColumn::Str(v) => is_null(v[idx]),
+            RawColumn::Int(v, raw) if v[idx].is_none() => raw_is_null(raw, idx),
+            RawColumn::Float(v, raw) if v[idx].is_none() => raw_is_null(raw, idx),
+            RawColumn::Bool(v, raw) if v[idx].is_none() => raw_is_null(raw, idx),
+            _ => false,
+        }
+    }
+
+    /// value of a row not missing
+    fn value(&self, name: &str, dtype: &DataType, idx: usize) -> Result<Value<'a>, LineError> {
+        let raw_value = |raw: &Series| raw.get(idx).map(|v| v.to_string()).unwrap_or_default();
+        match self {
+            RawColumn::Str(v) => parse_field(name, dtype, v[idx]),
+            RawColumn::Int(v, raw) => match v[idx] {
+                Some(v) => Ok(Value::Int(Some(v))),
+                None => Err(LineError::BadNumber {
+                    col: name.to_owned(),
+                    value: raw_value(raw),
+                }),
+            },
+            RawColumn::Float(v, raw) => match v[idx] {
+                Some(v) => finite_float(name, v, || raw_value(raw)),
+                None => Err(LineError::BadNumber {
+                    col: name.to_owned(),
+                    value: raw_value(raw),
+                }),
+            },
+            RawColumn::Bool(v, raw) => match v[idx] {
+                Some(v) => Ok(Value::Bool(Some(v))),
+                None => Err(LineError::BadFlag {
+                    col: name.to_owned(),
+                    value: raw_value(raw),
+                }),
+            },
+        }
+    }
+}
+
+pub struct Validator {
+    fields: TypedFields,
+    score_names: Vec<String>,
+    /// weight type of score columns to be log transformed
+    ratio_types: HashMap<String, WeightType>,
+    strict: bool,
+    source: String,
+    rejected: RowWriter,
+    duplicates: RowWriter,
+    dup_policy: DupPolicy,
+    /// occurrences of variants, None once counting is finished
+    counter: Option<DupCounter>,
+    dup_tracker: DupTracker,
+    /// only keep rows with `from <= P <= to`
+    pvalue_range: Option<(f32, f32)>,
+    /// fill missing CHR and POS from structured ID, and allow missing ID
+    parse_id: bool,
+    /// line number of the first row in next batch, 1-based
+    next_line: usize,
+    pub n_rejected: usize,
+    pub n_duplicates: usize,
+}
+
+impl Validator {
+    /// `first_line` is the line number of the first data row in file.
+    /// `weight_types` is in the same order of `score_names`.
+    pub fn new(
+        fields: TypedFields,
+        score_names: &[String],
+        weight_types: &[WeightType],
+        strict: bool,
+        source: &str,
+        rejected_path: &str,
+        first_line: usize,
+    ) -> Validator {
+        Validator {
+            fields,
+            score_names: score_names.to_vec(),
+            ratio_types: score_names
+                .iter()
+                .cloned()
+                .zip(weight_types.iter().copied())
+                .filter(|(_, v)| v.is_ratio())
+                .collect(),
+            strict,
+            source: source.to_owned(),
+            // rejected rows are not written in strict mode
+            rejected: RowWriter::new(if strict { "" } else { rejected_path }),
+            duplicates: RowWriter::new(""),
+            dup_policy: DupPolicy::First,
+            counter: Some(DupCounter::default()),
+            dup_tracker: DupTracker::new(DupPolicy::First, HashMap::new()),
+            pvalue_range: None,
+            parse_id: false,
+            next_line: first_line,
+            n_rejected: 0,
+            n_duplicates: 0,
+        }
+    }
+
+    /// Drop valid rows with P out of range. They are not rejected since the
+    /// range is a filter rather than a requirement.
+    pub fn with_pvalue_range(mut self, pvalue_range: Option<(f32, f32)>) -> Validator {
+        self.pvalue_range = pvalue_range;
+        self
+    }
+
+    /// Fill missing CHR and POS from structured ID, such as `chr1:12345:A:G`.
+    /// Either ID or position is then required, rows with rsID only are
+    /// matched by ID.
+    pub fn with_parse_id(mut self, parse_id: bool) -> Validator {
+        self.parse_id = parse_id;
+        self
+    }
+
+    /// Resolve duplicated variants by the policy, dropped rows are written to
+    /// `duplicates_path`.
+    pub fn with_dup_policy(mut self, dup_policy: DupPolicy, duplicates_path: &str) -> Validator {
+        self.dup_policy = dup_policy;
+        self.duplicates = RowWriter::new(duplicates_path);
+        self
+    }
+
+    /// Count occurrences of variants in a batch by a first pass over batches,
+    /// before any batch is validated. Invalid rows are not counted.
+    pub fn count(&mut self, raw: &DataFrame) -> Result<()> {
+        let str_cols = self.str_cols(raw)?;
+        let (columns, errors) = self.parse(raw, &str_cols)?;
+        let keys = self.variant_keys(&columns, &errors);
+        self.add_counts(&keys);
+        Ok(())
+    }
+
+    /// Finish counting occurrences, the policy is then applied on duplicated
+    /// variants when validating.
+    pub fn finish_count(&mut self) {
+        if let Some(counter) = self.counter.take() {
+            self.dup_tracker = DupTracker::new(self.dup_policy, counter.duplicated());
+        }
+    }
+
+    fn add_counts(&mut self, keys: &[Option<(String, bool)>]) {
+        if let Some(counter) = &mut self.counter {
+            for (variant, _) in keys.iter().flatten() {
+                counter.add(variant);
+            }
+        }
+    }
+
+    /// columns to be used
+    pub fn cols(&self) -> Vec<String> {
+        self.fields.iter().map(|(v, _)| v.to_owned()).collect()
+    }
+
+    /// Validate rows of raw beta with all columns in string. Return beta of
+    /// valid rows with required columns in required types. Fields are parsed
+    /// column by column, and only the rows with errors are looked up again
+    /// to be reported.
+    pub fn validate(&mut self, raw: &DataFrame) -> Result<DataFrame> {
+        let height = raw.height();
+        let str_cols = self.str_cols(raw)?;
+        let (mut columns, errors) = self.parse(raw, &str_cols)?;
+        let score_idx: Vec<usize> = self.field_idx(&self.score_names);
+        let keys = self.variant_keys(&columns, &errors);
+        // occurrences are counted on this frame if not counted by a first
+        // pass, which should then be the whole weights
+        if self.counter.is_some() {
+            self.add_counts(&keys);
+            self.finish_count();
+        }
+
+        let mut keep = vec![true; height];
+        let mut rejected: Vec<(usize, usize, Vec<LineError>)> = vec![];
+        let mut duplicates: Vec<(usize, usize, String)> = vec![];
+        for (idx, (row_errors, key)) in errors.into_iter().zip(keys).enumerate() {
+            let line = self.next_line + idx;
+            let (variant, swapped) = match key {
+                Some(v) => v,
+                None => {
+                    keep[idx] = false;
+                    rejected.push((idx, line, row_errors));
+                    continue;
+                }
+            };
+            // weights on the canonical A1
+            let sign = if swapped { -1. } else { 1. };
+            let weights: Vec<Option<Float>> = score_idx
+                .iter()
+                .map(|i| columns[*i].float(idx).map(|v| (sign * v) as Float))
+                .collect();
+            match self.dup_tracker.check(&variant, line, &weights)? {
+                DupAction::Keep => (),
+                DupAction::KeepSummed(sums) => {
+                    for (i, sum) in score_idx.iter().zip(sums) {
+                        columns[*i].set_float(idx, sum.map(|v| sign * float_to_f64(v)));
+                    }
+                }
+                DupAction::Drop(reason) => {
+                    keep[idx] = false;
+                    duplicates.push((idx, line, reason));
+                }
+            }
+        }
+        self.next_line += height;
+
+        if !rejected.is_empty() {
+            self.reject(raw, &rejected)?;
+        }
+        if !duplicates.is_empty() {
+            self.n_duplicates += duplicates.len();
+            if !self.duplicates.path.is_empty() {
+                warn!(
+                    "{} rows of duplicated variants in {} are dropped by policy {:?}, see {}",
+                    duplicates.len(),
+                    self.source,
+                    self.dup_policy,
+                    self.duplicates.path
+                );
+            }
+            self.duplicates.write(raw, &duplicates)?;
+        }
+
+        let series = self
+            .fields
+            .iter()
+            .zip(columns)
+            .map(|((name, dtype), column)| column.into_series(name, dtype, &keep))
+            .collect();
+        let beta = DataFrame::new(series)?;
+        match self.pvalue_range {
+            Some((from, to)) => Ok(beta
+                .lazy()
+                .filter(col(PVALUE).gt_eq(lit(from)).and(col(PVALUE).lt_eq(lit(to))))
+                .collect()?),
+            None => Ok(beta),
+        }
+    }
+
+    /// string field of other types in columnar file, such as integer CHR
+    fn str_cols(&self, raw: &DataFrame) -> Result<Vec<Option<Series>>> {
+        self.fields
+            .iter()
+            .map(|(name, dtype)| {
+                let raw_col = raw.column(name)?;
+                match is_typed(dtype) || raw_col.dtype() == &DataType::Utf8 {
+                    true => Ok(None),
+                    false => Ok(Some(raw_col.cast(&DataType::Utf8)?)),
+                }
+            })
+            .collect()
+    }
+
+    /// Parse fields column by column, `str_cols` is from
+    /// [Validator::str_cols]. Return parsed columns and errors of each row.
+    fn parse<'a>(
+        &self,
+        raw: &'a DataFrame,
+        str_cols: &'a [Option<Series>],
+    ) -> Result<(Vec<Column<'a>>, Vec<Vec<LineError>>)> {
+        let raw_cols = self
+            .fields
+            .iter()
+            .zip(str_cols.iter())
+            .map(|((name, dtype), str_col)| {
+                RawColumn::new(raw.column(name)?, str_col.as_ref(), dtype)
+            })
+            .collect::<Result<Vec<RawColumn>>>()?;
+
+        let id_col = self
+            .fields
+            .iter()
+            .position(|(name, _)| name == ID)
+            .map(|i| &raw_cols[i]);
+        let variant_ids: Vec<Option<VariantId>> = match id_col {
+            Some(RawColumn::Str(ids)) if self.parse_id => {
+                ids.iter().map(|v| v.and_then(VariantId::parse)).collect()
+            }
+            _ => vec![],
+        };
+
+        let mut errors: Vec<Vec<LineError>> = vec![vec![]; raw.height()];
+        let columns: Vec<Column> = self
+            .fields
+            .iter()
+            .zip(raw_cols.iter())
+            .map(|((name, dtype), raw_col)| {
+                self.parse_column(name, dtype, raw_col, id_col, &variant_ids, &mut errors)
+            })
+            .collect();
+        self.check_rows(&columns, &mut errors);
+        Ok((columns, errors))
+    }
+
+    /// Variant key of each row and whether A1 is swapped, see [variant_key].
+    /// Rows with errors are None. Rows without position are told apart by ID.
+    fn variant_keys(
+        &self,
+        columns: &[Column],
+        errors: &[Vec<LineError>],
+    ) -> Vec<Option<(String, bool)>> {
+        let key_idx: Vec<usize> = match self.parse_id {
+            true => self.field_idx(&[ID, CHR, POS]),
+            false => self.field_idx(&[CHR, POS]),
+        };
+        let allele_idx: Vec<usize> = self.field_idx(&[A1, A2]);
+        errors
+            .iter()
+            .enumerate()
+            .map(|(idx, row_errors)| {
+                row_errors
+                    .is_empty()
+                    .then(|| variant_key(columns, &key_idx, &allele_idx, idx))
+            })
+            .collect()
+    }
+
+    /// Parse a raw column into required type. Rows with errors are None, and
+    /// the errors are added to `errors` by row.
+    fn parse_column<'a>(
+        &self,
+        name: &str,
+        dtype: &DataType,
+        raw_col: &RawColumn<'a>,
+        id_col: Option<&RawColumn>,
+        variant_ids: &[Option<VariantId>],
+        errors: &mut [Vec<LineError>],
+    ) -> Column<'a> {
+        let mut column = Column::with_capacity(dtype, errors.len());
+        for (idx, row_errors) in errors.iter_mut().enumerate() {
+            let null = raw_col.is_null(idx);
+            // missing CHR and POS are filled from structured ID
+            let filled = match (variant_ids.get(idx), name) {
+                (Some(Some(v)), CHR) if null => Some(v.chrom.clone()),
+                (Some(Some(v)), POS) if null => Some(v.pos.to_string()),
+                _ => None,
+            };
+            // either ID or position is required
+            let optional = match name {
+                ID => true,
+                CHR | POS => id_col.is_some_and(|v| !v.is_null(idx)),
+                _ => false,
+            };
+            if self.parse_id && optional && filled.is_none() && null {
+                column.push(None);
+                continue;
+            }
+            let value = match &filled {
+                Some(v) => parse_field(name, dtype, Some(v)).map(Value::into_owned),
+                None if null => parse_field(name, dtype, None),
+                None => raw_col.value(name, dtype, idx),
+            };
+            match value.and_then(|v| self.to_beta(name, v)) {
+                Ok(v) => column.push(Some(v)),
+                Err(e) => {
+                    column.push(None);
+                    row_errors.push(e);
+                }
+            }
+        }
+        column
+    }
+
+    /// log transform ratio into beta, ratio should be positive
+    fn to_beta<'a>(&self, name: &str, value: Value<'a>) -> Result<Value<'a>, LineError> {
+        match (value, self.ratio_types.get(name)) {
+            (Value::Float(Some(ratio)), Some(weight_type)) => {
+                match weight_type.transform(ratio as Float) {
+                    Some(beta) => Ok(Value::Float(Some(float_to_f64(beta)))),
+                    None => Err(LineError::NonPositiveRatio {
+                        col: name.to_owned(),
+                        value: ratio as Float,
+                        weight_type: *weight_type,
+                    }),
+                }
+            }
+            (value, _) => Ok(value),
+        }
+    }
+
+    /// check across fields of rows without errors: at least one score and at
+    /// most one genetic model
+    fn check_rows(&self, columns: &[Column], errors: &mut [Vec<LineError>]) {
+        for (idx, row_errors) in errors.iter_mut().enumerate() {
+            if !row_errors.is_empty() {
+                continue;
+            }
+            let mut has_score = false;
+            let mut n_flags = 0;
+            for ((name, _), column) in self.fields.iter().zip(columns) {
+                match column {
+                    Column::Float(v) if v[idx].is_some() && self.score_names.contains(name) => {
+                        has_score = true
+                    }
+                    Column::Bool(v) if v[idx] == Some(true) => n_flags += 1,
+                    _ => (),
+                }
+            }
+            if !has_score {
+                row_errors.push(LineError::MissingField(self.score_names.join(",")));
+            }
+            if n_flags > 1 {
+                row_errors.push(LineError::ConflictingModel);
+            }
+        }
+    }
+
+    /// index of fields in `names`, missing ones are skipped
+    fn field_idx<T: AsRef<str>>(&self, names: &[T]) -> Vec<usize> {
+        names
+            .iter()
+            .filter_map(|name| self.fields.iter().position(|(v, _)| v == name.as_ref()))
+            .collect()
+    }
+
+    /// abort in strict mode, otherwise write rejected rows
+    fn reject(
+        &mut self,
+        raw: &DataFrame,
+        rejected: &[(usize, usize, Vec<LineError>)],
+    ) -> Result<()> {
+        self.n_rejected += rejected.len();
+        if self.strict {
+            let shown: Vec<String> = rejected
+                .iter()
+                .take(SHOW_ERRORS)
+                .map(|(_, line, errors)| format!("line {}: {}", line, join_errors(errors)))
+                .collect();
+            return Err(anyhow!(
+                "Found {} invalid rows in {}:\n{}",
+                rejected.len(),
+                self.source,
+                shown.join("\n")
+            ));
+        }
+
+        if !self.rejected.path.is_empty() {
+            warn!(
+                "{} invalid rows in {} are rejected, see {}",
+                rejected.len(),
+                self.source,
+                self.rejected.path
+            );
+        }
+        let rows: Vec<(usize, usize, String)> = rejected
+            .iter()
+            .map(|(idx, line, errors)| (*idx, *line, join_errors(errors)))
+            .collect();
+        self.rejected.write(raw, &rows)
+    }
+}
+
+/// Raw rows written with line number and reason, the file is created when
+/// the first row is written. Nothing is written if the path is empty.
+struct RowWriter {
+    path: String,
+    writer: Option<BufWriter<File>>,
+}
+
+impl RowWriter {
+    fn new(path: &str) -> RowWriter {
+        if !path.is_empty() {
+            // remove file of previous run
+            let _ = fs::remove_file(path);
+        }
+        RowWriter {
+            path: path.to_owned(),
+            writer: None,
+        }
+    }
+
+    /// write rows of (index in raw, line number, reason)
+    fn write(&mut self, raw: &DataFrame, rows: &[(usize, usize, String)]) -> Result<()> {
+        if self.path.is_empty() {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            let mut writer = BufWriter::new(File::create(&self.path)?);
+            let names: Vec<&str> = raw.get_column_names();
+            writeln!(writer, "LINE\tREASON\t{}", names.join("\t"))?;
+            self.writer = Some(writer);
+        }
+        let writer = match &mut self.writer {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        for (idx, line, reason) in rows {
+            let row = raw
+                .get_columns()
+                .iter()
+                .map(|v| match v.dtype() {
+                    DataType::Utf8 => Ok(v.utf8()?.get(*idx).unwrap_or("").to_owned()),
+                    _ => match v.get(*idx)? {
+                        AnyValue::Null => Ok(String::new()),
+                        value => Ok(value.to_string()),
+                    },
+                })
+                .collect::<Result<Vec<String>>>()?;
+            writeln!(writer, "{}\t{}\t{}", line, reason, row.join("\t"))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Key telling variants apart from fields at `key_idx` and alleles at
+/// `allele_idx`. A pair of alleles is in [canonical_alleles] form, and whether
+/// A1 is swapped is also returned.
+fn variant_key(
+    columns: &[Column],
+    key_idx: &[usize],
+    allele_idx: &[usize],
+    idx: usize,
+) -> (String, bool) {
+    let mut key = String::new();
+    for i in key_idx {
+        columns[*i].push_key(idx, &mut key);
+    }
+    let alleles: Vec<&str> = allele_idx
+        .iter()
+        .filter_map(|i| columns[*i].str(idx))
+        .collect();
+    let (alleles, swapped) = match alleles[..] {
+        [a1, a2] => {
+            let ((a1, a2), swapped) = canonical_alleles(a1, a2);
+            (vec![a1, a2], swapped)
+        }
+        _ => (alleles.into_iter().map(normalize_allele).collect(), false),
+    };
+    for allele in alleles {
+        key.push_str(&allele);
+        key.push(':');
+    }
+    key.pop();
+    (key, swapped)
+}
+
+fn join_errors(errors: &[LineError]) -> String {
+    errors
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+fn is_null(raw: Option<&str>) -> bool {
+    match raw {
+        Some(v) => NULL_VALUES.contains(&v.trim()),
+        None => true,
+    }
+}
+
+/// whether the field is parsed from string into a non-string type
+fn is_typed(dtype: &DataType) -> bool {
+    matches!(
+        dtype,
+        DataType::Int32 | DataType::Boolean | DataType::Float32 | DataType::Float64
+    )
+}
+
+fn raw_is_null(raw: &Series, idx: usize) -> bool {
+    matches!(raw.get(idx), Ok(AnyValue::Null))
+}
+
+/// number of a float field should be finite, `raw` is the value shown in
+/// errors
+fn finite_float<'a>(
+    name: &str,
+    number: f64,
+    raw: impl Fn() -> String,
+) -> Result<Value<'a>, LineError> {
+    if number.is_finite() {
+        Ok(Value::Float(Some(number)))
+    } else if [FREQ, PVALUE].contains(&name) {
+        Err(LineError::BadNumber {
+            col: name.to_owned(),
+            value: raw(),
+        })
+    } else {
+        Err(LineError::NonFinite {
+            col: name.to_owned(),
+            value: raw(),
+        })
+    }
+}
+
+/// allele should be normalized by [normalize_allele]
+fn is_valid_allele(allele: &str) -> bool {
+    matches!(allele, EMPTY_ALLELE | "*" | INSERTION | DELETION)
+        || allele.chars().all(|v| matches!(v, 'A' | 'C' | 'G' | 'T' | 'N'))
+}
+
+/// parse a field into required type. Missing score is allowed, whether there
+/// is any score is checked for the whole row. Missing flag is false.
+fn parse_field<'a>(
+    name: &str,
+    dtype: &DataType,
+    raw: Option<&'a str>,
+) -> Result<Value<'a>, LineError> {
+    let value = raw.map(|v| v.trim()).filter(|v| !NULL_VALUES.contains(v));
+    let value = match (value, dtype) {
+        (Some(v), _) => v,
+        (None, DataType::Boolean) => return Ok(Value::Bool(Some(false))),
+        (None, DataType::Float32 | DataType::Float64) if ![FREQ, PVALUE].contains(&name) => {
+            return Ok(Value::Float(None))
+        }
+        (None, _) => return Err(LineError::MissingField(name.to_owned())),
+    };
+    let bad_number = || LineError::BadNumber {
+        col: name.to_owned(),
+        value: value.to_owned(),
+    };
+    match dtype {
+        DataType::Int32 => Ok(Value::Int(Some(value.parse().map_err(|_| bad_number())?))),
+        DataType::Boolean => {
+            let lower = value.to_ascii_lowercase();
+            if TRUE_VALUES.contains(&lower.as_str()) {
+                Ok(Value::Bool(Some(true)))
+            } else if FALSE_VALUES.contains(&lower.as_str()) {
+                Ok(Value::Bool(Some(false)))
+            } else {
+                Err(LineError::BadFlag {
+                    col: name.to_owned(),
+                    value: value.to_owned(),
+                })
+            }
+        }
+        DataType::Float32 | DataType::Float64 => {
+            // parsed in the precision of the column
+            let number: f64 = match dtype {
+                DataType::Float32 => f64::from(value.parse::<f32>().map_err(|_| bad_number())?),
+                _ => value.parse().map_err(|_| bad_number())?,
+            };
+            finite_float(name, number, || value.to_owned())
+        }
+        _ if name == A1 || name == A2 => {
+            let allele = normalize_allele(value);
+            if !is_valid_allele(&allele) {
+                return Err(LineError::InvalidAllele {
+                    col: name.to_owned(),
+                    value: value.to_owned(),
+                });
+            }
+            Ok(Value::Str(Some(Cow::Owned(allele))))
+        }
+        _ => Ok(Value::Str(Some(Cow::Borrowed(value)))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{DataFrame, DataType, NamedFrom, Series, TakeRandom, TakeRandomUtf8};
+
+    use super::{TypedFields, Validator};
+    use crate::{
+        dedup::DupPolicy, weight_type::WeightType, Float, FloatType, A1, A2, CHR, FLOAT_DTYPE, POS,
+    };
+
+    fn fields() -> TypedFields {
+        vec![
+            (CHR.to_owned(), DataType::Utf8),
+            (POS.to_owned(), DataType::Int32),
+            (A1.to_owned(), DataType::Utf8),
+            ("s1".to_owned(), FLOAT_DTYPE),
+        ]
+    }
+
+    fn raw_beta(pos: [&str; 4], a1: [&str; 4], s1: [&str; 4]) -> DataFrame {
+        DataFrame::new(vec![
+            Series::new(CHR, ["1", "1", "1", "1"]),
+            Series::new(POS, pos),
+            Series::new(A1, a1),
+            Series::new("s1", s1),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate_line_errors() {
+        let score_names = vec!["s1".to_owned()];
+        let path = std::env::temp_dir().join("pgs_validate_test.rejected");
+        let path = path.to_str().unwrap();
+        let dup_path = std::env::temp_dir().join("pgs_validate_test.duplicates");
+        let dup_path = dup_path.to_str().unwrap();
+        let mut validator = Validator::new(
+            fields(),
+            &score_names,
+            &[WeightType::Beta],
+            false,
+            "test",
+            path,
+            2,
+        )
+        .with_dup_policy(DupPolicy::First, dup_path);
+        let raw = raw_beta(
+            ["100", "x", "300", "400"],
+            ["A", "A", "Q", "T"],
+            ["0.1", "0.2", "0.3", ""],
+        );
+        // duplicates are counted across batches by a first pass
+        let next_raw = raw_beta(
+            ["100", "y", "600", "700"],
+            ["A", "C", "G", "T"],
+            ["0.1", "0.2", "0.3", "0.4"],
+        );
+        validator.count(&raw).unwrap();
+        validator.count(&next_raw).unwrap();
+        validator.finish_count();
+        let beta = validator.validate(&raw).unwrap();
+        assert_eq!(beta.height(), 1);
+        assert_eq!(beta.column(A1).unwrap().utf8().unwrap().get(0), Some("A"));
+        assert_eq!(validator.n_rejected, 3);
+
+        let rejected = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = rejected.lines().collect();
+        assert_eq!(lines[0], "LINE\tREASON\tCHR\tPOS\tA1\ts1");
+        assert!(lines[1].starts_with("3\tbad number in POS: \"x\""));
+        assert!(lines[2].starts_with("4\tinvalid allele in A1: \"Q\""));
+        assert!(lines[3].starts_with("5\tmissing required field s1"));
+
+        // line numbers continue in next batch, and duplicates are checked
+        // across batches
+        let beta = validator.validate(&next_raw).unwrap();
+        assert_eq!(beta.height(), 2);
+        assert_eq!(validator.n_duplicates, 1);
+        let rejected = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = rejected.lines().collect();
+        assert!(lines[4].starts_with("7\tbad number in POS: \"y\""));
+        let duplicates = std::fs::read_to_string(dup_path).unwrap();
+        let lines: Vec<&str> = duplicates.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("6\tduplicate of line 2, first one kept"));
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(dup_path).unwrap();
+    }
+
+    #[test]
+    fn test_validate_strict_and_ratio() {
+        let score_names = vec!["s1".to_owned()];
+        let mut validator = Validator::new(
+            fields(),
+            &score_names,
+            &[WeightType::OddsRatio],
+            true,
+            "test",
+            "",
+            2,
+        );
+        let raw = raw_beta(
+            ["100", "200", "300", "400"],
+            ["A", "C", "G", "T"],
+            ["1", "0", "1", "1"],
+        );
+        let err = validator.validate(&raw).unwrap_err().to_string();
+        assert!(err.contains("Found 1 invalid rows in test"));
+        assert!(err.contains("line 3: non-positive"));
+
+        let raw = raw_beta(
+            ["500", "600", "700", "800"],
+            ["A", "C", "G", "T"],
+            ["1", "1", "1", "1"],
+        );
+        let beta = validator.validate(&raw).unwrap();
+        let s1: Vec<Option<Float>> = beta
+            .column("s1")
+            .unwrap()
+            .unpack::<FloatType>()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(s1, vec![Some(0.0); 4]);
+    }
+
+    #[test]
+    fn test_validate_dup_sum() {
+        let score_names = vec!["s1".to_owned()];
+        let mut validator = Validator::new(
+            fields(),
+            &score_names,
+            &[WeightType::Beta],
+            false,
+            "test",
+            "",
+            2,
+        )
+        .with_dup_policy(DupPolicy::Sum, "");
+        // occurrences are counted on the frame without a first pass
+        let raw = raw_beta(
+            ["100", "200", "100", "100"],
+            ["A", "C", "A", "A"],
+            ["1", "2", "3", "4"],
+        );
+        let beta = validator.validate(&raw).unwrap();
+        let s1: Vec<Option<Float>> = beta
+            .column("s1")
+            .unwrap()
+            .unpack::<FloatType>()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(s1, vec![Some(2.0), Some(8.0)]);
+        assert_eq!(validator.n_duplicates, 2);
+    }
+
+    #[test]
+    fn test_validate_dup_swapped() {
+        let mut fields = fields();
+        fields.insert(3, (A2.to_owned(), DataType::Utf8));
+        let score_names = vec!["s1".to_owned()];
+        let mut validator = Validator::new(
+            fields,
+            &score_names,
+            &[WeightType::Beta],
+            false,
+            "test",
+            "",
+            2,
+        )
+        .with_dup_policy(DupPolicy::Sum, "");
+        // the same variant with alleles swapped and on the opposite strand
+        let mut raw = raw_beta(
+            ["100", "100", "200", "100"],
+            ["A", "G", "A", "C"],
+            ["1", "2", "3", "4"],
+        );
+        raw.insert_at_idx(3, Series::new(A2, ["G", "A", "G", "T"]))
+            .unwrap();
+        let beta = validator.validate(&raw).unwrap();
+        let s1: Vec<Option<Float>> = beta
+            .column("s1")
+            .unwrap()
+            .unpack::<FloatType>()
+            .unwrap()
+            .into_iter()
+            .collect();
+        // summed on A1 of the last row, C/T is G/A on the opposite strand
+        assert_eq!(s1, vec![Some(3.0), Some(5.0)]);
+        assert_eq!(validator.n_duplicates, 2);
+    }
+
+    #[test]
+    fn test_validate_typed() {
+        // typed columns of columnar file
+        let raw = DataFrame::new(vec![
+            Series::new(CHR, [1, 2, 3]),
+            Series::new(POS, [100_i64, 200, 300]),
+            Series::new(A1, ["A", "C", "G"]),
+            Series::new("s1", [Some(0.5), Some(f64::NAN), None]),
+        ])
+        .unwrap();
+        let score_names = vec!["s1".to_owned()];
+        let path = std::env::temp_dir().join("pgs_validate_typed_test.rejected");
+        let path = path.to_str().unwrap();
+        let mut validator = Validator::new(
+            fields(),
+            &score_names,
+            &[WeightType::Beta],
+            false,
+            "test",
+            path,
+            1,
+        );
+        let beta = validator.validate(&raw).unwrap();
+        assert_eq!(beta.height(), 1);
+        assert_eq!(beta.column(CHR).unwrap().utf8().unwrap().get(0), Some("1"));
+        assert_eq!(beta.column(POS).unwrap().i32().unwrap().get(0), Some(100));
+
+        let rejected = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = rejected.lines().collect();
+        assert_eq!(lines[1], "2\tnon-finite weight in s1: NaN\t2\t200\tC\tNaN");
+        assert_eq!(lines[2], "3\tmissing required field s1\t3\t300\tG\t");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+//! Type of weight in score columns. Weights reported as odds ratio or hazard
+//! ratio are log transformed into beta before scoring. Weight type is taken
+//! from `--weight-type`, either for all scores (e.g. `OR`) or for a score
+//! column (e.g. `Lassosum=OR`), or from `#weight_type=` in the header of pgs
+//! catalog scoring file. Score specific setting takes precedence over global
+//! setting, which takes precedence over header.
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+
+use crate::Float;
+
+/// key of weight type in pgs catalog header
+pub const WEIGHT_TYPE_KEY: &str = "weight_type";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightType {
+    /// effect size on additive scale, including log(OR) and log(HR)
+    Beta,
+    OddsRatio,
+    HazardRatio,
+}
+
+impl WeightType {
+    pub fn new(weight_type: &str) -> Result<WeightType> {
+        let my_type = match weight_type.to_ascii_lowercase().as_str() {
+            // NR (not reported) in pgs catalog is regarded as beta
+            "beta" | "log(or)" | "logor" | "log_or" | "log(hr)" | "loghr" | "log_hr" | "nr" => {
+                WeightType::Beta
+            }
+            "or" | "odds_ratio" => WeightType::OddsRatio,
+            "hr" | "hazard_ratio" => WeightType::HazardRatio,
+            _ => {
+                return Err(anyhow!(
+                    "Weight type should be one of the following: [ beta, OR, HR, log(OR), log(HR) \
+                     ], got {}",
+                    weight_type
+                ))
+            }
+        };
+        Ok(my_type)
+    }
+
+    /// whether weight is ratio and need log transform
+    pub fn is_ratio(&self) -> bool {
+        !matches!(self, WeightType::Beta)
+    }
+
+    /// Transform weight into beta. Return None if ratio is not positive.
+    pub fn transform(&self, weight: Float) -> Option<Float> {
+        match self {
+            WeightType::Beta => Some(weight),
+            _ if weight > 0.0 => Some(weight.ln()),
+            _ => None,
+        }
+    }
+
+    /// description of the transform applied
+    pub fn transform_name(&self) -> String {
+        match self {
+            WeightType::Beta => "none".to_owned(),
+            _ => format!("ln({})", self),
+        }
+    }
+
+    /// Resolve weight type of each score column from `--weight-type` specs
+    /// and weight type in the header of weight file. Specs for score not in
+    /// `score_names` are ignored, since they may belong to other models.
+    pub fn resolve(
+        specs: &[String],
+        score_names: &[String],
+        header_type: Option<&str>,
+    ) -> Result<Vec<WeightType>> {
+        let mut global = match header_type {
+            Some(v) => WeightType::new(v)?,
+            None => WeightType::Beta,
+        };
+        let mut by_score = vec![];
+        for spec in specs {
+            match spec.split_once('=') {
+                Some((score_name, v)) => by_score.push((score_name, WeightType::new(v)?)),
+                None => global = WeightType::new(spec)?,
+            }
+        }
+        let weight_types = score_names
+            .iter()
+            .map(|score_name| {
+                by_score
+                    .iter()
+                    .rev()
+                    .find(|(v, _)| *v == score_name.as_str())
+                    .map_or(global, |(_, v)| *v)
+            })
+            .collect();
+        Ok(weight_types)
+    }
+}
+
+impl fmt::Display for WeightType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            WeightType::Beta => "beta",
+            WeightType::OddsRatio => "OR",
+            WeightType::HazardRatio => "HR",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WeightType;
+    use crate::Float;
+
+    #[test]
+    fn test_transform() {
+        assert_eq!(WeightType::Beta.transform(-0.5), Some(-0.5));
+        assert_eq!(WeightType::OddsRatio.transform(1.0), Some(0.0));
+        assert_eq!(WeightType::HazardRatio.transform(0.0), None);
+        assert_eq!(WeightType::OddsRatio.transform(-1.0), None);
+        let beta = WeightType::OddsRatio.transform(2.0).unwrap();
+        assert!((beta - Float::ln(2.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let score_names = vec!["s1".to_owned(), "s2".to_owned()];
+        let resolved = WeightType::resolve(&[], &score_names, Some("OR")).unwrap();
+        assert_eq!(resolved, vec![WeightType::OddsRatio; 2]);
+
+        // per score spec overrides the global one, and the later one wins
+        let specs = vec![
+            "s2=HR".to_owned(),
+            "beta".to_owned(),
+            "s2=log(OR)".to_owned(),
+        ];
+        let resolved = WeightType::resolve(&specs, &score_names, Some("OR")).unwrap();
+        assert_eq!(resolved, vec![WeightType::Beta; 2]);
+        let specs = vec!["HR".to_owned(), "s1=OR".to_owned(), "other=beta".to_owned()];
+        let resolved = WeightType::resolve(&specs, &score_names, None).unwrap();
+        assert_eq!(
+            resolved,
+            vec![WeightType::OddsRatio, WeightType::HazardRatio]
+        );
+
+        assert!(WeightType::resolve(&["RR".to_owned()], &score_names, None).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Allele normalization shared by weight and bim readers. Tools write indels
+//! and multi-base alleles differently, such as `I`/`D` codes, `-` for an
+//! empty allele, lower case, or alleles padded with the preceding base (VCF
+//! style). [normalize_allele] folds the representation of a single allele,
+//! while [trim_common] and [resolve_indel_code] resolve the conventions that
+//! depend on the other allele of the pair. [complement] gives the allele on
+//! the opposite strand.
+
+/// allele code for an empty allele after trimming
+pub const EMPTY_ALLELE: &str = "-";
+/// plink codes for insertion and deletion
+pub const INSERTION: &str = "I";
+pub const DELETION: &str = "D";
+
+/// trim whitespace and fold case
+pub fn normalize_allele(allele: &str) -> String {
+    allele.trim().to_ascii_uppercase()
+}
+
+/// Trim common suffix and then common prefix of a pair of alleles, so that
+/// padded forms such as `AT/A` become `T/-`. Alleles of a snp are kept as is.
+pub fn trim_common(a: &str, b: &str) -> (String, String) {
+    if a == b || a.len() == 1 && b.len() == 1 {
+        return (a.to_owned(), b.to_owned());
+    }
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    while let (Some(x), Some(y)) = (a.last(), b.last()) {
+        if x != y {
+            break;
+        }
+        a = &a[..a.len() - 1];
+        b = &b[..b.len() - 1];
+    }
+    while let (Some(x), Some(y)) = (a.first(), b.first()) {
+        if x != y {
+            break;
+        }
+        a = &a[1..];
+        b = &b[1..];
+    }
+    (to_allele(a), to_allele(b))
+}
+
+fn to_allele(bases: &[u8]) -> String {
+    if bases.is_empty() {
+        EMPTY_ALLELE.to_owned()
+    } else {
+        String::from_utf8_lossy(bases).into_owned()
+    }
+}
+
+/// Resolve `I`/`D` code against a pair of alleles (original and trimmed):
+/// insertion is the longer allele and deletion is the shorter one. Other
+/// alleles, or alleles of the same length, are kept as is.
+pub fn resolve_indel_code(allele: &str, pair: (&str, &str), trimmed: (&str, &str)) -> String {
+    let (a, b) = pair;
+    if a.len() == b.len() {
+        return allele.to_owned();
+    }
+    let a_longer = a.len() > b.len();
+    match allele {
+        INSERTION if a_longer => trimmed.0.to_owned(),
+        INSERTION => trimmed.1.to_owned(),
+        DELETION if a_longer => trimmed.1.to_owned(),
+        DELETION => trimmed.0.to_owned(),
+        v => v.to_owned(),
+    }
+}
+
+/// complement of a single base, other characters such as `I`, `D` or `-` are
+/// kept as is.
+fn complement_base(c: char) -> char {
+    match c {
+        'A' => 'T',
+        'T' => 'A',
+        'C' => 'G',
+        'G' => 'C',
+        'a' => 't',
+        't' => 'a',
+        'c' => 'g',
+        'g' => 'c',
+        v => v,
+    }
+}
+
+/// complement of an allele, base by base
+pub fn complement(allele: &str) -> String {
+    allele.chars().map(complement_base).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{complement, normalize_allele, resolve_indel_code, trim_common};
+
+    #[test]
+    fn test_normalize_allele() {
+        assert_eq!(normalize_allele(" at "), "AT");
+        assert_eq!(normalize_allele("i"), "I");
+        assert_eq!(normalize_allele("-"), "-");
+    }
+
+    #[test]
+    fn test_trim_common() {
+        // padded deletion and insertion
+        assert_eq!(trim_common("AT", "A"), ("T".to_owned(), "-".to_owned()));
+        assert_eq!(trim_common("G", "GCC"), ("-".to_owned(), "CC".to_owned()));
+        // common suffix is trimmed before common prefix
+        assert_eq!(trim_common("ACT", "AT"), ("C".to_owned(), "-".to_owned()));
+        // snp and identical alleles are kept
+        assert_eq!(trim_common("A", "G"), ("A".to_owned(), "G".to_owned()));
+        assert_eq!(trim_common("AT", "AT"), ("AT".to_owned(), "AT".to_owned()));
+    }
+
+    #[test]
+    fn test_resolve_indel_code() {
+        let pair = ("AT", "A");
+        let trimmed = ("T", "-");
+        assert_eq!(resolve_indel_code("I", pair, trimmed), "T");
+        assert_eq!(resolve_indel_code("D", pair, trimmed), "-");
+        // insertion is the longer allele on either side
+        assert_eq!(resolve_indel_code("I", ("A", "AT"), ("-", "T")), "T");
+        // alleles of the same length are kept
+        assert_eq!(resolve_indel_code("I", ("A", "G"), ("A", "G")), "I");
+        assert_eq!(resolve_indel_code("A", pair, trimmed), "A");
+    }
+
+    #[test]
+    fn test_complement() {
+        assert_eq!(complement("A"), "T");
+        assert_eq!(complement("acGT"), "tgCA");
+        // indel codes are kept
+        assert_eq!(complement("I"), "I");
+        assert_eq!(complement("-"), "-");
+    }
+}
@@ -0,0 +1,176 @@
+//! Linkage disequilibrium (r²) between snp, used by LD clumping. Genotypes
+//! are kept packed as in bed, 32 samples in each `u64`, and r² is computed
+//! from bit counts over samples not missing in both snp.
+use anyhow::Result;
+
+use crate::{reader::freq_reader::BedSnpReader, BfileSet};
+
+/// lower bit of each 2-bit genotype
+const MASK55: u64 = 0x5555_5555_5555_5555;
+/// number of samples in a `u64`
+const SAMPLES_PER_WORD: usize = 32;
+
+/// Genotype of a snp. Each 2-bit slot holds a sample, and only the lower bit
+/// is used. Missing genotypes are zero in `het_or_hom` and `hom`.
+pub struct LdGenotype {
+    /// genotype is not missing
+    nonmissing: Vec<u64>,
+    /// at least one copy of the counted allele
+    het_or_hom: Vec<u64>,
+    /// two copies of the counted allele
+    hom: Vec<u64>,
+}
+
+impl LdGenotype {
+    /// From packed genotype in bed of `n_ind` samples. Codes are `00` (0
+    /// copy), `10` (1 copy), `11` (2 copies) and `01` (missing).
+    pub fn from_bed_bytes(bytes: &[u8], n_ind: usize) -> LdGenotype {
+        let n_words = n_ind.div_ceil(SAMPLES_PER_WORD);
+        let mut genotype = LdGenotype {
+            nonmissing: Vec::with_capacity(n_words),
+            het_or_hom: Vec::with_capacity(n_words),
+            hom: Vec::with_capacity(n_words),
+        };
+        for (cc, chunk) in bytes.chunks(8).take(n_words).enumerate() {
+            let mut word_bytes = [0_u8; 8];
+            word_bytes[..chunk.len()].copy_from_slice(chunk);
+            let word = u64::from_le_bytes(word_bytes);
+            let lower = word & MASK55;
+            let higher = (word >> 1) & MASK55;
+            // padding samples at the end are regarded as missing
+            let n_valid = (n_ind - cc * SAMPLES_PER_WORD).min(SAMPLES_PER_WORD);
+            let valid = match n_valid {
+                SAMPLES_PER_WORD => MASK55,
+                v => MASK55 & ((1_u64 << (v * 2)) - 1),
+            };
+            genotype.nonmissing.push(!(lower & !higher) & valid);
+            genotype.het_or_hom.push(higher & valid);
+            genotype.hom.push(lower & higher & valid);
+        }
+        genotype
+    }
+
+    /// Squared pearson correlation of allele counts, zero if either snp is
+    /// monomorphic in samples not missing in both.
+    pub fn r2(&self, other: &LdGenotype) -> f64 {
+        let (mut n, mut sx, mut sy, mut sxx, mut syy, mut sxy) = (0, 0, 0, 0, 0, 0);
+        for ww in 0..self.nonmissing.len() {
+            let both = self.nonmissing[ww] & other.nonmissing[ww];
+            let (x1, x2) = (self.het_or_hom[ww] & both, self.hom[ww] & both);
+            let (y1, y2) = (other.het_or_hom[ww] & both, other.hom[ww] & both);
+            n += both.count_ones();
+            // count = het_or_hom + hom, and count² = het_or_hom + 3 * hom
+            sx += x1.count_ones() + x2.count_ones();
+            sy += y1.count_ones() + y2.count_ones();
+            sxx += x1.count_ones() + 3 * x2.count_ones();
+            syy += y1.count_ones() + 3 * y2.count_ones();
+            sxy += (x1 & y1).count_ones()
+                + (x1 & y2).count_ones()
+                + (x2 & y1).count_ones()
+                + (x2 & y2).count_ones();
+        }
+        if n == 0 {
+            return 0.;
+        }
+        let n = n as f64;
+        let cov = sxy as f64 - sx as f64 * sy as f64 / n;
+        let var_x = sxx as f64 - (sx as f64).powi(2) / n;
+        let var_y = syy as f64 - (sy as f64).powi(2) / n;
+        if var_x <= 0. || var_y <= 0. {
+            return 0.;
+        }
+        cov * cov / (var_x * var_y)
+    }
+}
+
+/// Read [LdGenotype] of snp from bfile
+pub struct LdReader {
+    bed_reader: BedSnpReader,
+    n_ind: usize,
+}
+
+impl LdReader {
+    pub fn new(bfile_set: &BfileSet) -> Result<LdReader> {
+        let n_ind = bfile_set.fam.height();
+        Ok(LdReader {
+            bed_reader: BedSnpReader::new(&bfile_set.bed_path, n_ind, bfile_set.sid_count)?,
+            n_ind,
+        })
+    }
+
+    /// genotypes of snp by IDX in bim
+    pub fn read(&mut self, sid_idxs: &[isize]) -> Result<Vec<LdGenotype>> {
+        sid_idxs
+            .iter()
+            .map(|idx| {
+                let bytes = self.bed_reader.read_packed(*idx)?;
+                Ok(LdGenotype::from_bed_bytes(&bytes, self.n_ind))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LdGenotype;
+
+    /// pack allele counts into bed bytes, None is missing
+    fn to_bed_bytes(counts: &[Option<u8>]) -> Vec<u8> {
+        counts
+            .chunks(4)
+            .map(|chunk| {
+                chunk.iter().enumerate().fold(0_u8, |byte, (ii, count)| {
+                    let code = match count {
+                        Some(0) => 0b00,
+                        Some(1) => 0b10,
+                        Some(_) => 0b11,
+                        None => 0b01,
+                    };
+                    byte | code << (ii * 2)
+                })
+            })
+            .collect()
+    }
+
+    fn to_genotype(counts: &[Option<u8>]) -> LdGenotype {
+        LdGenotype::from_bed_bytes(&to_bed_bytes(counts), counts.len())
+    }
+
+    /// r² of samples not missing in both, computed directly
+    fn naive_r2(x: &[Option<u8>], y: &[Option<u8>]) -> f64 {
+        let pairs: Vec<(f64, f64)> = x
+            .iter()
+            .zip(y)
+            .filter_map(|(x, y)| Some((f64::from((*x)?), f64::from((*y)?))))
+            .collect();
+        let n = pairs.len() as f64;
+        let (mx, my) = pairs
+            .iter()
+            .fold((0., 0.), |(sx, sy), (x, y)| (sx + x / n, sy + y / n));
+        let cov: f64 = pairs.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+        let var_x: f64 = pairs.iter().map(|(x, _)| (x - mx).powi(2)).sum();
+        let var_y: f64 = pairs.iter().map(|(_, y)| (y - my).powi(2)).sum();
+        cov * cov / (var_x * var_y)
+    }
+
+    #[test]
+    fn test_r2() {
+        // 37 samples span two words, with padding in the last byte and word
+        let x: Vec<Option<u8>> = (0..37_u8).map(|v| Some(v % 3)).collect();
+        let mut y: Vec<Option<u8>> = (0..37_u8).map(|v| Some((v / 2) % 3)).collect();
+        y[5] = None;
+        y[36] = None;
+        let r2 = to_genotype(&x).r2(&to_genotype(&y));
+        assert!((r2 - naive_r2(&x, &y)).abs() < 1e-12);
+        assert!(r2 > 0. && r2 < 1.);
+
+        // r² of a snp with itself, or with the other allele counted, is 1
+        let flipped: Vec<Option<u8>> = x.iter().map(|v| v.map(|v| 2 - v)).collect();
+        assert!((to_genotype(&x).r2(&to_genotype(&x)) - 1.).abs() < 1e-12);
+        assert!((to_genotype(&x).r2(&to_genotype(&flipped)) - 1.).abs() < 1e-12);
+
+        // monomorphic snp
+        let mono = vec![Some(1); 37];
+        assert_eq!(to_genotype(&x).r2(&to_genotype(&mono)), 0.);
+    }
+}
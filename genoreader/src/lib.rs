@@ -1,6 +1,10 @@
+pub mod allele;
+pub mod ld;
 pub mod meta;
 pub mod reader;
 pub mod test;
+pub mod variant_id;
 
 pub use reader::{read_bed_nolib::BedReaderNoLib, ReadGenotype};
 pub use reader::freq_reader::{BfileSet,FreqBedReader};
+pub use reader::bim_cursor::BimCursor;
@@ -1,4 +1,5 @@
 //pub mod read_bed;
+pub mod bim_cursor;
 pub mod read_bed_nolib;
 pub mod freq_reader;
 mod test;
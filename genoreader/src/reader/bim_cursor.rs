@@ -0,0 +1,326 @@
+//! Streaming cursor on bim sorted by CHR and POS. Instead of keeping the
+//! whole bim in memory, rows are read on demand for the position range of
+//! each weight batch, so that matching only joins against a small window.
+//! Rows at the upper bound of a window are kept for the next window, since
+//! the next batch may start at the same position.
+use std::{collections::VecDeque, vec::IntoIter};
+
+use anyhow::{anyhow, Result};
+use polars::prelude::{read_impl::OwnedBatchedCsvReader, DataFrame, NamedFrom, Series};
+
+use super::read_bed_nolib::read_meta::{batch_read_bim, format_bim};
+use crate::meta::{ALT, CHR, IDX, POS, REF};
+
+/// const for bim column name
+const ID: &str = "ID";
+/// number of bim rows parsed at a time
+const BIM_BATCH_SIZE: usize = 10_000;
+
+/// Sort key of chromosome, numeric chromosomes are ordered by number and
+/// followed by X, Y, XY and MT. `chr` prefix is ignored.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChromKey(u32, String);
+
+impl ChromKey {
+    pub fn new(chrom: &str) -> ChromKey {
+        let chrom = chrom
+            .strip_prefix("chr")
+            .or_else(|| chrom.strip_prefix("CHR"))
+            .unwrap_or(chrom);
+        let rank = match chrom.to_ascii_uppercase().as_str() {
+            "X" => 23,
+            "Y" => 24,
+            "XY" => 25,
+            "M" | "MT" => 26,
+            v => v.parse().unwrap_or(u32::MAX),
+        };
+        ChromKey(rank, chrom.to_owned())
+    }
+}
+
+/// position of a snp in sorted order
+pub type SnpKey = (ChromKey, i32);
+
+struct BimRecord {
+    idx: u32,
+    chrom: String,
+    id: String,
+    pos: i32,
+    alt: String,
+    reference: String,
+    key: SnpKey,
+}
+
+pub struct BimCursor {
+    reader: OwnedBatchedCsvReader,
+    /// rows parsed but not read, with POS and key not checked
+    parsed: IntoIter<(String, String, Option<i32>, String, String)>,
+    /// rows read but not consumed
+    buffer: VecDeque<BimRecord>,
+    /// index of next row in bim
+    next_idx: u32,
+    /// key of last row read, to check that bim is sorted
+    last_key: Option<SnpKey>,
+    /// lower bound of last window, to check that weights are sorted
+    last_from: Option<SnpKey>,
+}
+
+impl BimCursor {
+    pub fn new(bim_path: &str) -> Result<BimCursor> {
+        Ok(BimCursor {
+            reader: batch_read_bim(bim_path, BIM_BATCH_SIZE)?,
+            parsed: Vec::new().into_iter(),
+            buffer: VecDeque::new(),
+            next_idx: 0,
+            last_key: None,
+            last_from: None,
+        })
+    }
+
+    /// parse next batch of bim, return false at the end of bim
+    fn parse_next(&mut self) -> Result<bool> {
+        let bim = match self.reader.next_batches(1)? {
+            Some(batches) if !batches.is_empty() => format_bim(batches[0].clone())?,
+            _ => return Ok(false),
+        };
+        let to_strings = |name: &str| -> Result<Vec<String>> {
+            let values = bim.column(name)?.utf8()?.into_iter();
+            Ok(values.map(|v| v.unwrap_or_default().to_owned()).collect())
+        };
+        let rows: Vec<_> = to_strings(CHR)?
+            .into_iter()
+            .zip(to_strings(ID)?)
+            .zip(bim.column(POS)?.i32()?)
+            .zip(to_strings(ALT)?.into_iter().zip(to_strings(REF)?))
+            .map(|(((chrom, id), pos), (alt, reference))| (chrom, id, pos, alt, reference))
+            .collect();
+        self.parsed = rows.into_iter();
+        Ok(true)
+    }
+
+    /// read next row into buffer, return false at the end of bim
+    fn read_next(&mut self) -> Result<bool> {
+        let (chrom, id, pos, alt, reference) = loop {
+            match self.parsed.next() {
+                Some(v) => break v,
+                None => {
+                    if !self.parse_next()? {
+                        return Ok(false);
+                    }
+                }
+            }
+        };
+        let pos = pos.ok_or_else(|| anyhow!("Malformed bim line {}: no POS", self.next_idx + 1))?;
+        let key = (ChromKey::new(&chrom), pos);
+        if let Some(last_key) = &self.last_key {
+            if &key < last_key {
+                return Err(anyhow!(
+                    "bim is not sorted by CHR and POS at line {}, sort it or match without \
+                     --sorted-match",
+                    self.next_idx + 1
+                ));
+            }
+        }
+        self.last_key = Some(key.clone());
+        self.buffer.push_back(BimRecord {
+            idx: self.next_idx,
+            chrom,
+            id,
+            pos,
+            alt,
+            reference,
+            key,
+        });
+        self.next_idx += 1;
+        Ok(true)
+    }
+
+    /// Get bim rows with `from <= (CHR, POS) <= to`, with the same columns as
+    /// bim in [crate::BfileSet]. Windows should be requested in sorted order.
+    pub fn window(&mut self, from: &SnpKey, to: &SnpKey) -> Result<DataFrame> {
+        if let Some(last_from) = &self.last_from {
+            if from < last_from {
+                return Err(anyhow!(
+                    "weights are not sorted by CHR and POS, sort them or match without \
+                     --sorted-match"
+                ));
+            }
+        }
+        self.last_from = Some(from.clone());
+
+        // skip rows before the window
+        loop {
+            match self.buffer.front() {
+                Some(v) if &v.key < from => {
+                    self.buffer.pop_front();
+                }
+                Some(_) => break,
+                None => {
+                    if !self.read_next()? {
+                        break;
+                    }
+                }
+            }
+        }
+        // read rows until beyond the window
+        loop {
+            match self.buffer.back() {
+                Some(v) if &v.key > to => break,
+                _ => {
+                    if !self.read_next()? {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let records: Vec<&BimRecord> = self.buffer.iter().filter(|v| &v.key <= to).collect();
+        let window = records_to_frame(&records)?;
+
+        // keep rows at the upper bound for the next window
+        while self.buffer.front().is_some_and(|v| &v.key < to) {
+            self.buffer.pop_front();
+        }
+        Ok(window)
+    }
+}
+
+fn records_to_frame(records: &[&BimRecord]) -> Result<DataFrame> {
+    let frame = DataFrame::new(vec![
+        Series::new(IDX, records.iter().map(|v| v.idx).collect::<Vec<u32>>()),
+        Series::new(
+            CHR,
+            records
+                .iter()
+                .map(|v| v.chrom.as_str())
+                .collect::<Vec<&str>>(),
+        ),
+        Series::new(
+            ID,
+            records.iter().map(|v| v.id.as_str()).collect::<Vec<&str>>(),
+        ),
+        Series::new(POS, records.iter().map(|v| v.pos).collect::<Vec<i32>>()),
+        Series::new(
+            REF,
+            records
+                .iter()
+                .map(|v| v.reference.as_str())
+                .collect::<Vec<&str>>(),
+        ),
+        Series::new(
+            ALT,
+            records
+                .iter()
+                .map(|v| v.alt.as_str())
+                .collect::<Vec<&str>>(),
+        ),
+    ])?;
+    Ok(frame)
+}
+
+/// bim without rows, used when bim is read by [BimCursor]
+pub fn empty_bim() -> Result<DataFrame> {
+    records_to_frame(&[])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use polars::prelude::TakeRandom;
+
+    use super::{BimCursor, ChromKey};
+    use crate::meta::{IDX, POS};
+
+    fn write_bim(name: &str, rows: &[(&str, i32)]) -> String {
+        let path = std::env::temp_dir().join(name);
+        let lines: Vec<String> = rows
+            .iter()
+            .enumerate()
+            .map(|(idx, (chrom, pos))| format!("{chrom}\trs{idx}\t0\t{pos}\tA\tG\n"))
+            .collect();
+        fs::write(&path, lines.concat()).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn idx_of(cursor: &mut BimCursor, from: (&str, i32), to: (&str, i32)) -> Vec<u32> {
+        let window = cursor
+            .window(
+                &(ChromKey::new(from.0), from.1),
+                &(ChromKey::new(to.0), to.1),
+            )
+            .unwrap();
+        window
+            .column(IDX)
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_no_null_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_chrom_key() {
+        assert!(ChromKey::new("2") < ChromKey::new("10"));
+        assert!(ChromKey::new("chr22") < ChromKey::new("X"));
+        assert!(ChromKey::new("X") < ChromKey::new("MT"));
+        assert_eq!(ChromKey::new("chr1"), ChromKey::new("1"));
+    }
+
+    #[test]
+    fn test_window_across_chrom() {
+        let rows = [
+            ("1", 100),
+            ("1", 200),
+            ("2", 50),
+            ("2", 300),
+            ("10", 10),
+            ("X", 5),
+        ];
+        let path = write_bim("pgs_bim_cursor_test_chrom.bim", &rows);
+        let mut cursor = BimCursor::new(&path).unwrap();
+        assert_eq!(idx_of(&mut cursor, ("1", 150), ("2", 60)), vec![1, 2]);
+        assert_eq!(idx_of(&mut cursor, ("2", 300), ("10", 10)), vec![3, 4]);
+        let window = cursor
+            .window(&(ChromKey::new("X"), 1), &(ChromKey::new("X"), 9))
+            .unwrap();
+        assert_eq!(window.column(POS).unwrap().i32().unwrap().get(0), Some(5));
+        assert_eq!(idx_of(&mut cursor, ("Y", 1), ("Y", 9)), Vec::<u32>::new());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_window_duplicate_pos() {
+        let rows = [("1", 100), ("1", 200), ("1", 200), ("1", 200), ("1", 300)];
+        let path = write_bim("pgs_bim_cursor_test_dup.bim", &rows);
+        let mut cursor = BimCursor::new(&path).unwrap();
+        // rows at the upper bound are kept for the next batch starting there
+        assert_eq!(
+            idx_of(&mut cursor, ("1", 100), ("1", 200)),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(idx_of(&mut cursor, ("1", 200), ("1", 200)), vec![1, 2, 3]);
+        assert_eq!(
+            idx_of(&mut cursor, ("1", 200), ("1", 300)),
+            vec![1, 2, 3, 4]
+        );
+        // weights going backwards
+        assert!(cursor
+            .window(&(ChromKey::new("1"), 100), &(ChromKey::new("1"), 100))
+            .is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unsorted_bim() {
+        let path = write_bim(
+            "pgs_bim_cursor_test_unsorted.bim",
+            &[("2", 100), ("1", 200)],
+        );
+        let mut cursor = BimCursor::new(&path).unwrap();
+        assert!(cursor
+            .window(&(ChromKey::new("2"), 1), &(ChromKey::new("2"), 500))
+            .is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -2,7 +2,12 @@ mod bit_op;
 mod geno_reader;
 mod utils;
 
-use std::{path::Path, sync::Arc};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::Arc,
+};
 
 use anyhow::{anyhow, Result};
 
@@ -10,14 +15,19 @@ use anyhow::{anyhow, Result};
 use ndarray::Array2;
 use polars::prelude::{DataFrame, ChunkedArray, BooleanType};
 
+use super::bim_cursor::empty_bim;
 use super::read_bed_nolib::read_meta::{read_bim, read_fam};
 pub use geno_reader::BedSnpReader;
 use utils::create_mask_u32;
 
 pub struct BfileSet {
     pub bed_path: String,
+    pub bim_path: String,
     pub fam: DataFrame,
+    /// empty if bim is not loaded, see [BfileSet::new_without_bim]
     pub bim: DataFrame,
+    /// number of snp in bim
+    pub sid_count: usize,
 }
 
 
@@ -33,11 +43,36 @@ impl BfileSet {
         // get fam, bim
         let fam = read_fam(&fam_path)?;
         let bim = read_bim(&bim_path)?;
+        let sid_count = bim.height();
 
         Ok(BfileSet {
             bed_path,
+            bim_path,
             fam,
             bim,
+            sid_count,
+        })
+    }
+
+    /// Load fam only, and count snp in bim. It is used with [crate::BimCursor],
+    /// which reads bim in a streaming manner.
+    pub fn new_without_bim(bfile_path: &str) -> Result<BfileSet> {
+        let bed_path = format!("{}.bed", bfile_path);
+        let fam_path = format!("{}.fam", bfile_path);
+        let bim_path = format!("{}.bim", bfile_path);
+        if !Path::new(&bed_path).exists() {
+            return Err(anyhow!("path {} not exists", bfile_path));
+        }
+        let fam = read_fam(&fam_path)?;
+        let sid_count = BufReader::new(File::open(&bim_path)?).lines().count();
+        let bim = empty_bim()?;
+
+        Ok(BfileSet {
+            bed_path,
+            bim_path,
+            fam,
+            bim,
+            sid_count,
         })
     }
 
@@ -71,7 +106,7 @@ impl FreqBedReader {
     pub fn new(bfile_set: Arc<BfileSet>) -> Result<FreqBedReader> {
 
         let bed_reader: BedSnpReader = BedSnpReader::new(    
-            &bfile_set.bed_path, bfile_set.fam.height(), bfile_set.bim.height()
+            &bfile_set.bed_path, bfile_set.fam.height(), bfile_set.sid_count
         )?;
 
         Ok(FreqBedReader {
@@ -96,6 +131,22 @@ impl FreqBedReader {
         };
     }
 
+    /// number of missing genotypes of each snp in the last [Self::read_snp]
+    pub fn last_missing(&self) -> &[u32] {
+        &self.bed_reader.missing_vec
+    }
+
+    /// samples with missing genotype of each snp in the last
+    /// [Self::read_snp]
+    pub fn last_missing_iid(&self) -> &[Vec<u32>] {
+        &self.bed_reader.missing_iid
+    }
+
+    /// mean dosage of the counted allele (ALT) for each snp
+    pub fn read_freq(&mut self, snp_idx: &[isize]) -> Result<Vec<f32>> {
+        self.bed_reader.read_to_freq(snp_idx)
+    }
+
 
 }
 
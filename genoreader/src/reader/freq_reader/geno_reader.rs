@@ -27,6 +27,10 @@ pub struct BedSnpReader {
     in_sid_count: usize,
     iid_idx: Vec<usize>,
     bit_map: [f32; 4],
+    /// number of missing genotypes of each snp in the last read into array
+    pub missing_vec: Vec<u32>,
+    /// samples with missing genotype of each snp in the last read into array
+    pub missing_iid: Vec<Vec<u32>>,
 }
 
 impl BedSnpReader {
@@ -67,6 +71,8 @@ impl BedSnpReader {
             in_sid_count,
             iid_idx,
             bit_map,
+            missing_vec: vec![],
+            missing_iid: vec![],
         };
         Ok(bed_snp_reder)
     }
@@ -94,6 +100,8 @@ impl BedSnpReader {
         let total_iid = self.in_iid_count_div4 * 4;
         let mut val = Array::<f32, Ix2>::default((total_iid, sid_idxs.len()));
         let mut freq_vec: Vec<f32> = vec![];
+        self.missing_vec.clear();
+        self.missing_iid.clear();
         // read by each snp
         sid_idxs
             .iter()
@@ -103,9 +111,12 @@ impl BedSnpReader {
                 // read
                 let byte_vec: Vec<u8> = self.read_snp(*idx as u64)?;
                 // calculate freq
-                let freq = byte_vec_to_freq(&byte_vec);
+                let freq = byte_vec_to_freq(&byte_vec, self.in_iid_count);
                 self.bit_map[1] = freq;
                 freq_vec.push(freq);
+                let missing_iid = byte_vec_to_missing_iid(&byte_vec);
+                self.missing_vec.push(missing_iid.len() as u32);
+                self.missing_iid.push(missing_iid);
                 // into array
                 byte_vec_to_arr(byte_vec, *swap_flag, &self.iid_idx, col, &self.bit_map);
                 Ok(())
@@ -124,6 +135,8 @@ impl BedSnpReader {
     ) -> Result<Array<f32, Ix2>> {
         let total_iid = self.in_iid_count_div4 * 4;
         let mut val = Array::<f32, Ix2>::default((total_iid, sid_idxs.len()));
+        self.missing_vec.clear();
+        self.missing_iid.clear();
 
         // read by each snp
         sid_idxs
@@ -136,6 +149,9 @@ impl BedSnpReader {
                 let byte_vec: Vec<u8> = self.read_snp(*idx as u64)?;
                 // calculate freq
                 self.bit_map[1] = *freq;
+                let missing_iid = byte_vec_to_missing_iid(&byte_vec);
+                self.missing_vec.push(missing_iid.len() as u32);
+                self.missing_iid.push(missing_iid);
                 // into array
                 byte_vec_to_arr(byte_vec, *swap_flag, &self.iid_idx, col, &self.bit_map);
                 Ok(())
@@ -146,6 +162,23 @@ impl BedSnpReader {
         Ok(val)
     }
 
+    /// Read mean dosage of the counted allele for each snp without decoding
+    /// genotype into array.
+    pub fn read_to_freq(&mut self, sid_idxs: &[isize]) -> Result<Vec<f32>> {
+        sid_idxs
+            .iter()
+            .map(|idx| -> Result<f32> {
+                let byte_vec: Vec<u8> = self.read_snp(*idx as u64)?;
+                Ok(byte_vec_to_freq(&byte_vec, self.in_iid_count))
+            })
+            .collect()
+    }
+
+    /// Read packed genotype of a snp as in bed, 4 samples in each byte
+    pub fn read_packed(&mut self, sid_idx: isize) -> Result<Vec<u8>> {
+        self.read_snp(sid_idx as u64)
+    }
+
     pub fn read_to_ndarray_ind(
         &mut self,
         sid_idxs: &[isize],
@@ -189,11 +222,21 @@ impl BedSnpReader {
 }
 
 
-fn byte_vec_to_freq(byte_vec: &[u8]) -> f32 {
-    let (nonmissing_count, ones_count) = byte_vec.iter().fold(
+/// mean dosage of a snp of `iid_count` samples, padding genotypes (0b00) of
+/// the last byte are masked out
+fn byte_vec_to_freq(byte_vec: &[u8], iid_count: usize) -> f32 {
+    let last = byte_vec.len().saturating_sub(1);
+    let padding_mask = match iid_count % 4 {
+        0 => u8::MAX,
+        n => (1 << (n * 2)) - 1,
+    };
+    let (nonmissing_count, ones_count) = byte_vec.iter().enumerate().fold(
         (0, 0),
-        |(mut nonmissing_count, mut ones_count), byte| -> (u32, u32) {
-            let nonmissing_mask = nonmissing_mask_u8(*byte);
+        |(mut nonmissing_count, mut ones_count), (i, byte)| -> (u32, u32) {
+            let mut nonmissing_mask = nonmissing_mask_u8(*byte);
+            if i == last {
+                nonmissing_mask &= padding_mask;
+            }
             nonmissing_count += nonmissing_mask.count_ones();
             ones_count += (*byte & nonmissing_mask).count_ones();
             (nonmissing_count, ones_count)
@@ -204,6 +247,20 @@ fn byte_vec_to_freq(byte_vec: &[u8]) -> f32 {
 }
 
 
+/// samples with missing genotype (0b01) of a snp, padding of the last byte is
+/// 0b00
+fn byte_vec_to_missing_iid(byte_vec: &[u8]) -> Vec<u32> {
+    let mut missing_iid = vec![];
+    for (i, byte) in byte_vec.iter().enumerate() {
+        for j in 0..4 {
+            if (byte >> (j * 2)) & 3 == 1 {
+                missing_iid.push((i * 4 + j) as u32);
+            }
+        }
+    }
+    missing_iid
+}
+
 fn byte_vec_to_arr(byte_vec: Vec<u8>, 
     swap_flag: bool, iid_idx: &[usize], mut col: ArrayBase<ViewRepr<&mut f32>, Dim<[usize; 1]>>, 
     bit_map: &[f32]){
@@ -230,3 +287,19 @@ fn byte_vec_to_arr(byte_vec: Vec<u8>,
             });
         }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::byte_vec_to_freq;
+
+    #[test]
+    fn test_byte_vec_to_freq() {
+        // 0b11 is 0, 0b10 is 1, 0b00 is 2 copies of the counted allele
+        let byte_vec = [0b00_10_11_11, 0b00_00_01_10];
+        assert_eq!(byte_vec_to_freq(&byte_vec[..1], 4), 0.75);
+        // a missing genotype and two padding genotypes in the last byte
+        assert!((byte_vec_to_freq(&byte_vec, 6) - 0.8).abs() < 1e-6);
+        // without padding, the last two are genotypes of 2 copies
+        assert!((byte_vec_to_freq(&byte_vec, 8) - 8. / 7.).abs() < 1e-6);
+    }
+}
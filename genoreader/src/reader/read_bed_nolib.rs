@@ -13,7 +13,7 @@ use polars::{
 use read_meta::{read_bim, read_fam};
 
 // codes are copy from https://github.com/fastlmm/bed-reader/blob/master/src/lib.rs
-use super::ReadGenotype;
+use super::{freq_reader::BedSnpReader, ReadGenotype};
 
 #[derive(Clone, Debug)]
 pub struct BedReaderNoLib {
@@ -58,6 +58,13 @@ impl BedReaderNoLib {
             sid_idx: sid_all,
         })
     }
+
+    /// Mean dosage of the counted allele of each snp over all samples, in a
+    /// pass over snp that does not depend on batches of samples.
+    pub fn read_freq(&self, sid_idx: &[isize]) -> Result<Vec<f32>> {
+        let mut reader = BedSnpReader::new(&self.bed_path, self.iid_count, self.sid_count)?;
+        reader.read_to_freq(sid_idx)
+    }
 }
 
 impl ReadGenotype for BedReaderNoLib {
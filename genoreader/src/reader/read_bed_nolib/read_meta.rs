@@ -1,7 +1,12 @@
-use std::sync::Arc;
+use std::{fs::File, sync::Arc};
 
 use anyhow::Result;
-use polars::prelude::{CsvReader, DataFrame, DataType, Field, Schema, SerReader};
+use polars::{
+    io::mmap::MmapBytesReader,
+    prelude::{
+        read_impl::OwnedBatchedCsvReader, CsvReader, DataFrame, DataType, Field, Schema, SerReader,
+    },
+};
 
 const FAM_FID: &str = "FID";
 const FAM_IID: &str = "IID";
@@ -21,15 +26,30 @@ pub fn read_fam(fam_path: &str) -> Result<DataFrame> {
 }
 
 pub fn read_bim(bim_path: &str) -> Result<DataFrame> {
-    let my_schmema = get_bim_schema();
-    let mut bim = CsvReader::from_path(bim_path)?
+    let bim = bim_reader(CsvReader::from_path(bim_path)?).finish()?;
+    let bim = format_bim(bim)?;
+    Ok(bim.with_row_count("IDX", None)?)
+}
+
+/// Read bim in batches of rows, see [format_bim] for the columns of batches
+pub fn batch_read_bim(bim_path: &str, batch_size: usize) -> Result<OwnedBatchedCsvReader> {
+    let file: Box<dyn MmapBytesReader> = Box::new(File::open(bim_path)?);
+    let reader = bim_reader(CsvReader::new(file))
+        .with_chunk_size(batch_size)
+        .batched_read(Some(Arc::new(get_bim_schema())))?;
+    Ok(reader)
+}
+
+fn bim_reader<'a, R: MmapBytesReader + 'a>(reader: CsvReader<'a, R>) -> CsvReader<'a, R> {
+    reader
         .with_delimiter(b'\t')
-        .with_schema(Arc::new(my_schmema))
+        .with_schema(Arc::new(get_bim_schema()))
         .has_header(false)
-        .finish()?;
-    bim = bim.select(["CHR", "ID", "POS", "REF", "ALT"])?;
-    bim = bim.with_row_count("IDX", None)?;
-    Ok(bim)
+}
+
+/// select columns of bim, without IDX
+pub fn format_bim(bim: DataFrame) -> Result<DataFrame> {
+    Ok(bim.select(["CHR", "ID", "POS", "REF", "ALT"])?)
 }
 
 fn get_fam_schema() -> Schema {
@@ -0,0 +1,134 @@
+//! Structured variant ID, such as `chr1:12345:A:G` or `1_12345_A_G`. Weights
+//! often mix rsID with structured ID, and bim may lack rsID (`.`), so
+//! structured ID are parsed into position and alleles, and written in a
+//! canonical form to be compared regardless of `chr` prefix, separator and
+//! order of alleles.
+use crate::allele::normalize_allele;
+
+/// ID of missing variant name in bim
+pub const MISSING_ID: &str = ".";
+
+/// position and alleles parsed from a structured ID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantId {
+    pub chrom: String,
+    pub pos: i32,
+    /// alleles in the order of ID, usually REF and ALT
+    pub alleles: Option<(String, String)>,
+}
+
+impl VariantId {
+    /// Parse `CHR:POS[:A:B]`, separated by `:`, `_` or `-`. Fields are split
+    /// only by the first of these separators found in the ID, so that `-` is
+    /// the empty allele in `1:12345:-:A`. Return None for other ID such as
+    /// rsID.
+    pub fn parse(id: &str) -> Option<VariantId> {
+        let id = id.trim();
+        let sep = [':', '_', '-'].into_iter().find(|v| id.contains(*v))?;
+        let fields: Vec<&str> = id.split(sep).collect();
+        let alleles = match fields.len() {
+            2 => None,
+            4 => Some((normalize_allele(fields[2]), normalize_allele(fields[3]))),
+            _ => return None,
+        };
+        if fields.iter().any(|v| v.is_empty()) {
+            return None;
+        }
+        let pos = fields[1].parse().ok()?;
+        Some(VariantId {
+            chrom: strip_chr(fields[0]).to_owned(),
+            pos,
+            alleles,
+        })
+    }
+
+    /// canonical form, `CHR:POS:A:B` with alleles sorted
+    pub fn canonical(&self) -> String {
+        match &self.alleles {
+            Some((a, b)) => canonical_id(&self.chrom, self.pos, a, b),
+            None => format!("{}:{}", self.chrom, self.pos),
+        }
+    }
+}
+
+/// Canonical ID from position and alleles, which is the same whatever the
+/// order of alleles is.
+pub fn canonical_id(chrom: &str, pos: i32, a: &str, b: &str) -> String {
+    let (a, b) = if a <= b { (a, b) } else { (b, a) };
+    format!("{}:{}:{}:{}", strip_chr(chrom), pos, a, b)
+}
+
+fn strip_chr(chrom: &str) -> &str {
+    chrom
+        .strip_prefix("chr")
+        .or_else(|| chrom.strip_prefix("CHR"))
+        .unwrap_or(chrom)
+}
+
+/// Key to match ID: canonical form for structured ID, otherwise ID as is
+pub fn id_key(id: &str) -> String {
+    match VariantId::parse(id) {
+        Some(v) => v.canonical(),
+        None => id.trim().to_owned(),
+    }
+}
+
+/// Key to match ID of a bim row. ID is synthesized from position and alleles
+/// if it is missing or structured, otherwise rsID is kept.
+pub fn bim_id_key(id: Option<&str>, chrom: &str, pos: i32, reference: &str, alt: &str) -> String {
+    match id.map(|v| v.trim()) {
+        Some(id) if !id.is_empty() && id != MISSING_ID && VariantId::parse(id).is_none() => {
+            id.to_owned()
+        }
+        _ => canonical_id(chrom, pos, reference, alt),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bim_id_key, id_key, VariantId};
+
+    #[test]
+    fn test_parse() {
+        let expected = VariantId {
+            chrom: "1".to_owned(),
+            pos: 12345,
+            alleles: Some(("A".to_owned(), "G".to_owned())),
+        };
+        for id in [
+            "chr1:12345:A:G",
+            "1_12345_a_g",
+            "1-12345-A-G",
+            " 1:12345:A:G ",
+        ] {
+            assert_eq!(VariantId::parse(id), Some(expected.clone()));
+        }
+        // `-` is the empty allele when it is not the separator
+        let v = VariantId::parse("1:12345:-:AT").unwrap();
+        assert_eq!(v.alleles, Some(("-".to_owned(), "AT".to_owned())));
+        let v = VariantId::parse("1_12345_AT_-").unwrap();
+        assert_eq!(v.alleles, Some(("AT".to_owned(), "-".to_owned())));
+        // position only
+        assert_eq!(VariantId::parse("X:100").unwrap().alleles, None);
+        for id in ["rs123", "1:12345:A", "1:pos:A:G", "1::A:G", "1:12345-A:G"] {
+            assert_eq!(VariantId::parse(id), None, "{id}");
+        }
+    }
+
+    #[test]
+    fn test_id_key() {
+        // canonical regardless of prefix, separator and order of alleles
+        assert_eq!(id_key("chr1:12345:G:A"), "1:12345:A:G");
+        assert_eq!(id_key("1_12345_A_G"), "1:12345:A:G");
+        assert_eq!(id_key(" rs123 "), "rs123");
+        assert_eq!(bim_id_key(Some("rs123"), "1", 12345, "A", "G"), "rs123");
+        assert_eq!(
+            bim_id_key(Some("."), "chr1", 12345, "G", "A"),
+            "1:12345:A:G"
+        );
+        assert_eq!(
+            bim_id_key(Some("1:12345:G:A"), "1", 12345, "G", "A"),
+            "1:12345:A:G"
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use std::{fs::File, io::{BufReader, BufRead}, sync::Arc};
+use std::sync::Arc;
 
+use betareader::compress::{open_mmap_reader, read_first_line};
 use polars::{prelude::{DataFrame, Schema, DataType, Field, CsvReader, CsvEncoding, SerReader}, series::Series};
 use anyhow::Result;
 use std::str;
@@ -17,7 +18,7 @@ pub struct CovFrame {
 impl CovFrame {
     pub fn read_cov(cov_path: &str) -> Result<CovFrame>{
         let (my_schmema, cov_names, separater) = get_shema(cov_path)?;
-        let cov_frame: DataFrame = CsvReader::from_path(cov_path)?
+        let cov_frame: DataFrame = CsvReader::new(open_mmap_reader(cov_path)?)
             .with_delimiter(separater)
             .with_encoding(CsvEncoding::LossyUtf8)
             .with_schema(Arc::new(my_schmema))
@@ -44,10 +45,7 @@ fn get_separater(cov_path: &str) -> u8 {
 
 fn get_shema(cov_path: &str) -> Result<(Schema, Vec<String>, u8)>{
     let sep = get_separater(cov_path);
-    let file = File::open(cov_path)?;
-    let mut first_line = "".to_string();
-    BufReader::new(file).read_line(&mut first_line)?;
-    first_line = first_line.replace(['\n', '\r'], "");
+    let first_line = read_first_line(cov_path)?;
 
     let mut fid_iid_count =0; 
     let mut field_vec = vec![];
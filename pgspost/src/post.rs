@@ -6,6 +6,7 @@ use std::{
 };
 use polars::prelude::{CsvReader, CsvWriter, DataFrame, DataType, Field, Schema, SerReader, SerWriter};
 use anyhow::Result;
+use betareader::compress::open_mmap_reader;
 use log::info;
 
 use predictor::{meta::RANK, metrics};
@@ -82,7 +83,7 @@ impl PgsPost<'_> {
             schema_vec.push(Field::new(i, DataType::Float32))
         }
         let my_schema = Arc::from(Schema::from_iter(schema_vec));
-        let ref_rank: DataFrame = CsvReader::from_path(rank_path)?
+        let ref_rank: DataFrame = CsvReader::new(open_mmap_reader(rank_path)?)
             .with_schema(my_schema)
             .has_header(true)
             .finish()?;
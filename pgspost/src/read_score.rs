@@ -1,5 +1,6 @@
-use std::{fs::File, io::{BufReader, BufRead}, sync::Arc};
+use std::sync::Arc;
 
+use betareader::compress::{open_mmap_reader, read_first_line};
 use genoreader::meta::PHENO;
 use polars::prelude::{DataFrame, Schema, DataType, Field, CsvReader, CsvEncoding, SerReader};
 use anyhow::Result;
@@ -19,7 +20,7 @@ impl PgsScores <'_>{
     
     pub fn read_score <'a> (score_path: &str, score_names: &'a Vec<String>) -> Result<PgsScores<'a>>{
         let (my_schmema, has_pheno) = PgsScores::get_shema(score_path, score_names)?;
-        let score: DataFrame = CsvReader::from_path(score_path)?
+        let score: DataFrame = CsvReader::new(open_mmap_reader(score_path)?)
             .with_delimiter(b',')
             .with_encoding(CsvEncoding::LossyUtf8)
             .with_schema(Arc::new(my_schmema))
@@ -32,10 +33,7 @@ impl PgsScores <'_>{
     }
 
     fn get_shema(score_path: &str, score_names: &[String]) -> Result<(Schema, bool)>{
-        let file = File::open(score_path)?;
-        let mut first_line = "".to_string();
-        BufReader::new(file).read_line(&mut first_line)?;
-        first_line = first_line.replace(['\n', '\r'], "");
+        let first_line = read_first_line(score_path)?;
     
         let mut fid_iid_count =0; 
         let mut field_vec = vec![];
@@ -1,10 +1,24 @@
 use anyhow::{Result, anyhow};
-use betareader::{BetaArg, A1, CHR, FREQ, ID, POS, PVALUE};
+use betareader::{
+    beta_set::{BetaModel, BetaSet},
+    dedup::DupPolicy,
+    manifest::ModelSpec,
+    weight_type::WeightType,
+    BetaArg, A1, CHR, FREQ, ID, POS, PVALUE,
+};
 use clap::{Args, Parser};
-use log::{debug, warn, info};
+use log::{debug, info};
 use predictor::{
-    join::betahandler::QRange,
-    meta::{MetaArg, MissingStrategy, QrangeOrScorenames},
+    join::{
+        betahandler::{log_grid, parse_thresholds, QRange},
+        clump::ClumpArg,
+        guard::MatchGuard,
+    },
+    meta::{
+        AmbiguousPolicy, GenotypeScale, MatchMode, MetaArg, MissingStrategy, QrangeOrScorenames,
+        ScoreMode,
+    },
+    select::{SelectArg, SelectMetric, Split},
 };
 
 /// Command line argument
@@ -20,7 +34,9 @@ pub struct MyArgs {
     /// analysis mode, one of ["Validate", "Predict", "Run", "CandT"]
     pub mode: String,
 
-    /// weight path, should be a tsv file
+    /// weight path, should be a tsv file. Multiple weight files sharing the
+    /// same columns can be separated by comma. If --manifest is set, it is
+    /// the path to the model manifest.
     pub weight_path: String,
 
     /// path to plink bed files
@@ -42,14 +58,29 @@ pub struct MyArgs {
     #[arg(short = 'B', long, default_value_t = 10000)]
     pub batch_size: usize,
 
-    /// whether to match by id instead of match by pos and chrom
+    /// whether to match by id instead of match by pos and chrom, the same as
+    /// `--match-mode Id`
     #[arg(long, default_value_t = false)]
     pub match_id_flag: bool,
 
+    /// How to match snp between weights and bfile. Should be one of the
+    /// following: Pos, Id and IdPos. IdPos match by ID first, where structured
+    /// ID such as chr1:12345:A:G are compared with ID synthesized from bim,
+    /// and then fallback to CHR and POS.
+    #[arg(long, default_value = "Pos")]
+    pub match_mode: String,
+
     /// whether to show log, use -v -vv -vvv to present increase log level
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
+    /// whether weights and bim are both sorted by CHR and POS. If set, they
+    /// are matched by a sorted merge with bim read in a streaming manner,
+    /// which saves memory and time for large bim. Only for batching by snp
+    /// with a single weight file, and not for --match-id-flag.
+    #[arg(long, default_value_t = false)]
+    pub sorted_match: bool,
+
     /// whether to batch by ind, default is batch by snp
     #[arg(long, default_value_t = false)]
     pub batch_ind: bool,
@@ -59,24 +90,174 @@ pub struct MyArgs {
     pub beta_col: BetaCol,
 
     /// Strategy to deal with missing value in genotype. Should be one of the
-    /// following: Freq, Impute and Zero
+    /// following: Freq, Impute, Zero and Rescale
     #[arg(short = 'M', long, default_value = "Impute")]
     pub missing_strategy: String,
 
+    /// How scores are reported, as plink2 --score. Should be one of the
+    /// following: Sum and Avg. Avg divides the sum by the number of alleles
+    /// of each sample, excluding missing genotypes with --missing-strategy Zero
+    /// or Rescale
+    #[arg(long, default_value = "Sum")]
+    pub score_mode: String,
+
+    /// Scale of genotype before scoring, as the center and
+    /// variance-standardize modifiers of plink2 --score. Should be one of the
+    /// following: Raw, Center and Standardize
+    #[arg(long, default_value = "Raw")]
+    pub genotype_scale: String,
+
+    /// whether to write the partial score of each chromosome to
+    /// *.chrom_score.csv, in long format with a row for each sample and
+    /// chromosome
+    #[arg(long, default_value_t = false)]
+    pub score_per_chrom: bool,
+
+    /// Strategy to deal with ambiguous snp (A/T or C/G). Should be one of the
+    /// following: Drop, Keep and Freq. Freq compare FREQ in weight file with
+    /// frequency in bfile to decide the strand, and require the freq column.
+    #[arg(long, default_value = "Keep")]
+    pub ambiguous: String,
+
     /// whether to write matched snp and related information to *.beta.csv
     #[arg(long, default_value_t = false)]
     pub write_beta: bool,
 
+    /// whether to write the disposition of each weight row (matched, swapped,
+    /// not in bim, allele mismatch and so on) with the bim snp it matched to
+    /// *.match.tsv
+    #[arg(long, default_value_t = false)]
+    pub match_report: bool,
+
     /// q range file, a headerless tsv file consisted of three columns:
     /// **name**, **from** and **to**, used in filtering p value for
-    /// weights.
+    /// weights by `from <= P < to`. The range can also be given in the
+    /// second column as an interval on P, -log10(P) or another numeric
+    /// column, such as `[0, 5e-8]`, `-log10(P) (7.3, inf)` or `INFO [0.8, 1]`.
     #[arg(short = 'Q', long)]
     pub q_ranges: Option<String>,
 
+    /// P thresholds to generate Q ranges instead of --q-ranges, comma
+    /// separated such as `5e-8,1e-5,1e-3,0.05,1`
+    #[arg(long)]
+    pub p_thresholds: Option<String>,
+
+    /// log spaced P thresholds to generate Q ranges, given as FROM,TO,N such
+    /// as `1e-8,1,9`
+    #[arg(long)]
+    pub p_grid: Option<String>,
+
+    /// whether Q ranges of --p-thresholds or --p-grid are disjoint bins
+    /// `(t_prev, t]`, instead of nested cumulative ranges `[0, t]`
+    #[arg(long, default_value_t = false)]
+    pub threshold_bins: bool,
+
+    /// whether to drop snp with P out of all Q ranges when reading weights.
+    /// For Parquet and Arrow IPC weight file, the filter is pushed down to
+    /// the scan. Note that the unfiltered scores then only include these snp.
+    #[arg(long, default_value_t = false)]
+    pub prune_pvalue: bool,
+
+    /// whether to clump snp by LD before Q ranges are applied, for CandT.
+    /// Index snp are picked in order of P, and snp within --clump-kb of an
+    /// index snp with r² above --clump-r2 are removed. Index snp are written
+    /// to *.clumps.tsv.
+    #[arg(long, default_value_t = false)]
+    pub clump: bool,
+
+    /// r² threshold for clumping
+    #[arg(long, default_value_t = 0.1)]
+    pub clump_r2: f64,
+
+    /// window size in kb around an index snp for clumping
+    #[arg(long, default_value_t = 250)]
+    pub clump_kb: u32,
+
+    /// only snp with P not larger than it can be index snp, others are
+    /// removed by clumping
+    #[arg(long, default_value_t = 1.)]
+    pub clump_p: f32,
+
+    /// bfile prefix of LD reference panel for clumping, default to BED_PATH
+    #[arg(long)]
+    pub clump_ref: Option<String>,
+
     /// whether to calculate correlation between PHENO and score, for CandT only
     #[arg(short = 'E', long, default_value_t = false)]
     pub eval_flag: bool,
-    
+
+    /// whether to select the best Q range of each score by cross validation,
+    /// for CandT only. The selected Q range with its out-of-fold metric is
+    /// written to *.select.csv, and its weights to *.{score}_{range}.model.tsv.
+    #[arg(long, default_value_t = false)]
+    pub select_threshold: bool,
+
+    /// metric to select Q range, one of Pearson, Spearman, R2 and AUC
+    #[arg(long, default_value = "R2")]
+    pub select_metric: String,
+
+    /// number of folds of cross validation for --select-threshold
+    #[arg(long, default_value_t = 5)]
+    pub cv_folds: usize,
+
+    /// fraction of samples held out to evaluate --select-threshold, instead
+    /// of k-fold cross validation. Q range is then selected on the rest.
+    #[arg(long)]
+    pub cv_holdout: Option<f64>,
+
+    /// seed to split samples for --select-threshold
+    #[arg(long, default_value_t = 42)]
+    pub cv_seed: u64,
+
+    /// weight type of score columns, one of beta, OR and HR. Odds ratio (OR)
+    /// and hazard ratio (HR) are log transformed. Either for all scores (e.g.
+    /// `--weight-type OR`) or for a score column (e.g. `--weight-type
+    /// Lassosum=OR`). Default to `#weight_type=` in the header of pgs catalog
+    /// scoring file, or beta if not found.
+    #[arg(long)]
+    pub weight_type: Vec<String>,
+
+    /// minimum fraction of snp matched, for all scores (e.g. `0.8`) or for a
+    /// score column (e.g. `Lassosum=0.8`). Checked after all snp are
+    /// matched, see --guard-action.
+    #[arg(long)]
+    pub min_match_rate: Vec<String>,
+
+    /// minimum fraction of |weight| matched, for all scores or for a score
+    /// column as --min-match-rate
+    #[arg(long)]
+    pub min_weight_coverage: Vec<String>,
+
+    /// What to do when a score fails --min-match-rate or
+    /// --min-weight-coverage. Should be one of the following: Fail and Flag.
+    /// Fail exits with error after writing check.json, and Flag keeps the
+    /// score and marks it as flagged in check.json.
+    #[arg(long, default_value = "Fail")]
+    pub guard_action: String,
+
+    /// whether to abort on malformed rows in weight file, such as bad number,
+    /// missing field or invalid allele. By default they are written to
+    /// {out_prefix}.rejected.tsv with reasons and skipped.
+    #[arg(long, default_value_t = false)]
+    pub strict: bool,
+
+    /// What to do with duplicated variants in weight file. Should be one of
+    /// the following: First, Last, Sum, Error and Drop. First and Last keep
+    /// the first or last occurrence, Sum keeps one row with weights summed,
+    /// Error aborts and Drop drops all occurrences. Dropped rows are written
+    /// to {out_prefix}.duplicates.tsv.
+    #[arg(long, default_value = "First")]
+    pub dup_policy: String,
+
+    /// whether WEIGHT_PATH is a manifest, a tsv with columns name, path and
+    /// score_names (comma separated), and optional column names chrom, pos,
+    /// a1, a2, snp_id, freq and pvalue for each model.
+    #[arg(long, default_value_t = false)]
+    pub manifest: bool,
+
+    /// models parsed from manifest or multiple weight paths
+    #[arg(skip)]
+    pub models: Vec<ModelSpec>,
 }
 
 #[derive(Args, Debug)]
@@ -97,6 +278,11 @@ pub struct BetaCol {
     #[arg(long, default_value = A1)]
     pub a1: String,
 
+    /// other allele column for weight file, optional. If specified, it should
+    /// match the other allele in bim as well.
+    #[arg(long)]
+    pub a2: Option<String>,
+
     /// freq column for weight file
     #[arg(long, default_value = FREQ)]
     pub freq: String,
@@ -141,7 +327,7 @@ impl MyArgs {
         info!("Got mode {:?}, check arg accordingly", mode);
         match mode {
             ModeEnum::Validate => {
-                assert!(self.q_ranges.is_none(), "--q-ranges (-Q) should be None in Validate mode");
+                assert!(!self.has_q_ranges(), "--q-ranges (-Q) should be None in Validate mode");
                 self.write_beta = true;
                 self.missing_strategy = "Impute".to_owned();
                 self.eval_flag = true;
@@ -150,7 +336,7 @@ impl MyArgs {
                 debug!("--missing-strategy is set to {}", self.missing_strategy);
             },
             ModeEnum::CandT => {
-                assert!(self.q_ranges.is_some(), "--q-ranges (-Q) should be specified in CandT mode");
+                assert!(self.has_q_ranges(), "--q-ranges (-Q) should be specified in CandT mode");
                 self.write_beta = true;
                 self.missing_strategy = "Impute".to_owned();
                 self.eval_flag = true;
@@ -159,7 +345,7 @@ impl MyArgs {
                 debug!("--missing-strategy is set to {}", self.missing_strategy);
             }
             ModeEnum::Predict => {
-                assert!(self.q_ranges.is_none(), "--q-ranges (-Q) should be None in Predict mode");
+                assert!(!self.has_q_ranges(), "--q-ranges (-Q) should be None in Predict mode");
                 self.write_beta = false;
                 self.missing_strategy = "Freq".to_owned();
                 self.eval_flag = false;
@@ -169,54 +355,311 @@ impl MyArgs {
             },
             ModeEnum::Run => (),
         }
+        self.load_models()?;
+        self.check_weight_type()?;
+        self.check_match_guard()?;
+        let match_mode = self.get_match_mode()?;
+        DupPolicy::new(&self.dup_policy)?;
+        ScoreMode::new(&self.score_mode)?;
+        GenotypeScale::new(&self.genotype_scale)?;
+        self.check_clump()?;
+        self.get_select_arg()?;
+        if self.sorted_match
+            && (match_mode != MatchMode::Pos || self.batch_ind || !self.models.is_empty())
+        {
+            return Err(anyhow!(
+                "--sorted-match is only supported with --match-mode Pos, and not with \
+                 --batch-ind or multiple models"
+            ));
+        }
         Ok(())
     }
 
-    /// Convert [MyArgs] into [BetaArg] and [MetaArg]
-    /// [BetaArg] is for reading of beta from [betareader]
-    pub fn get_structs(&self) -> Result<(BetaArg, MetaArg)> {
-        // some check
-        let missing_strategy = MissingStrategy::new(&self.missing_strategy)?;
-        if matches!(missing_strategy, MissingStrategy::Impute) && self.batch_ind {
-            warn!(
-                "It is recommended to specify --batch-snp with --missing-strategy \"Impute\". \
-                 Since batching on sample cannot calculate complete freq informations. Or you can \
-                 use batch larger then sample size."
-            )
+    /// whether Q ranges are given by --q-ranges, --p-thresholds or --p-grid
+    fn has_q_ranges(&self) -> bool {
+        self.q_ranges.is_some() || self.p_thresholds.is_some() || self.p_grid.is_some()
+    }
+
+    /// Q ranges from --q-ranges, or generated from --p-thresholds or --p-grid
+    fn get_q_range(&self) -> Result<Option<QRange<'_>>> {
+        let thresholds = match (&self.p_thresholds, &self.p_grid) {
+            (Some(v), None) => Some(parse_thresholds(v)?),
+            (None, Some(v)) => Some(log_grid(v)?),
+            (None, None) => None,
+            _ => return Err(anyhow!("--p-thresholds conflicts with --p-grid")),
+        };
+        let q_range = match (&self.q_ranges, thresholds) {
+            (Some(v), None) => Some(QRange::new(v, &self.score_names, &self.beta_col.pvalue)?),
+            (None, Some(v)) => Some(QRange::from_thresholds(
+                &v,
+                !self.threshold_bins,
+                &self.score_names,
+            )),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow!(
+                    "--q-ranges (-Q) conflicts with --p-thresholds and --p-grid"
+                ))
+            }
+        };
+        Ok(q_range)
+    }
+
+    /// clumping needs P, and thresholds should be valid
+    fn check_clump(&self) -> Result<()> {
+        if !self.clump {
+            return Ok(());
         }
-        debug!("Model: {}", &self.weight_path);
-        debug!("Bfile: {}", &self.bed_path);
+        if !self.has_q_ranges() {
+            return Err(anyhow!("--clump is only supported with --q-ranges (-Q)"));
+        }
+        if !(self.clump_r2 > 0. && self.clump_r2 <= 1.) {
+            return Err(anyhow!("--clump-r2 should be in (0, 1], got {}", self.clump_r2));
+        }
+        if !(self.clump_p > 0. && self.clump_p <= 1.) {
+            return Err(anyhow!("--clump-p should be in (0, 1], got {}", self.clump_p));
+        }
+        Ok(())
+    }
+
+    /// [SelectArg] of --select-threshold, none if it is not set
+    pub fn get_select_arg(&self) -> Result<Option<SelectArg>> {
+        if !self.select_threshold {
+            return Ok(None);
+        }
+        if !self.has_q_ranges() {
+            return Err(anyhow!("--select-threshold is only supported with --q-ranges (-Q)"));
+        }
+        let split = match self.cv_holdout {
+            Some(v) if v > 0. && v < 1. => Split::Holdout(v),
+            Some(v) => return Err(anyhow!("--cv-holdout should be in (0, 1), got {}", v)),
+            None if self.cv_folds >= 2 => Split::KFold(self.cv_folds),
+            None => return Err(anyhow!("--cv-folds should be at least 2, got {}", self.cv_folds)),
+        };
+        Ok(Some(SelectArg {
+            metric: SelectMetric::new(&self.select_metric)?,
+            split,
+            seed: self.cv_seed,
+        }))
+    }
+
+    /// Load models from manifest or multiple weight paths. Score names are
+    /// replaced by output score names of all models.
+    fn load_models(&mut self) -> Result<()> {
+        if self.manifest {
+            self.models = ModelSpec::from_manifest(&self.weight_path)?;
+        } else if self.weight_path.contains(',') {
+            let paths: Vec<&str> = self.weight_path.split(',').collect();
+            self.models = ModelSpec::from_paths(&paths, &self.score_names)?;
+        } else {
+            return Ok(());
+        }
+        if self.has_q_ranges() {
+            return Err(anyhow!("--q-ranges (-Q) is not supported with multiple models"));
+        }
+        self.score_names = self
+            .models
+            .iter()
+            .flat_map(|v| v.out_names.iter().cloned())
+            .collect();
+        info!(
+            "Got {} models with scores: {:?}",
+            self.models.len(),
+            self.score_names
+        );
+        Ok(())
+    }
+
+    /// weight type of a score should be valid and refer to an existing score
+    fn check_weight_type(&self) -> Result<()> {
+        for spec in &self.weight_type {
+            let weight_type = match spec.split_once('=') {
+                Some((score_name, v)) => {
+                    let found = if self.models.is_empty() {
+                        self.score_names.iter().any(|v| v == score_name)
+                    } else {
+                        self.models
+                            .iter()
+                            .any(|model| model.score_names.iter().any(|v| v == score_name))
+                    };
+                    if !found {
+                        return Err(anyhow!(
+                            "Score {} in --weight-type is not found in score names",
+                            score_name
+                        ));
+                    }
+                    v
+                }
+                None => spec.as_str(),
+            };
+            WeightType::new(weight_type)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_match_guard(&self) -> Result<MatchGuard> {
+        MatchGuard::new(
+            &self.min_match_rate,
+            &self.min_weight_coverage,
+            &self.guard_action,
+        )
+    }
+
+    /// thresholds should be valid and refer to an existing score, which may
+    /// be expanded by Q ranges
+    fn check_match_guard(&self) -> Result<()> {
+        let match_guard = self.get_match_guard()?;
+        for score_name in match_guard.score_names() {
+            let found = self.score_names.iter().any(|v| {
+                v == score_name
+                    || self.has_q_ranges()
+                        && score_name
+                            .strip_prefix(v.as_str())
+                            .is_some_and(|v| v.starts_with('_'))
+            });
+            if !found {
+                return Err(anyhow!(
+                    "Score {} in --min-match-rate or --min-weight-coverage is not found in score \
+                     names",
+                    score_name
+                ));
+            }
+        }
+        Ok(())
+    }
 
-        let beta_arg = BetaArg {
+    /// `--match-id-flag` is a shortcut of `--match-mode Id`
+    pub fn get_match_mode(&self) -> Result<MatchMode> {
+        match (self.match_id_flag, MatchMode::new(&self.match_mode)?) {
+            (false, v) => Ok(v),
+            (true, MatchMode::Pos | MatchMode::Id) => Ok(MatchMode::Id),
+            (true, _) => Err(anyhow!(
+                "--match-id-flag conflicts with --match-mode {}",
+                self.match_mode
+            )),
+        }
+    }
+
+    fn get_beta_arg<'a>(
+        &'a self,
+        spec: Option<&'a ModelSpec>,
+        need_freq: bool,
+        q_range: Option<&QRange>,
+        match_mode: MatchMode,
+        dup_policy: DupPolicy,
+    ) -> BetaArg<'a> {
+        let col = &self.beta_col;
+        let pick = |v: Option<&'a Option<String>>, default: &'a str| -> &'a str {
+            v.and_then(|v| v.as_deref()).unwrap_or(default)
+        };
+        BetaArg {
             // col
-            chrom: &self.beta_col.chrom,
-            pos: &self.beta_col.pos,
-            a1: &self.beta_col.a1,
-            freq: &self.beta_col.freq,
-            snp_id: &self.beta_col.snp_id,
-            pvalue: &self.beta_col.pvalue,
+            chrom: pick(spec.map(|v| &v.chrom), &col.chrom),
+            pos: pick(spec.map(|v| &v.pos), &col.pos),
+            a1: pick(spec.map(|v| &v.a1), &col.a1),
+            a2: spec
+                .and_then(|v| v.a2.as_deref())
+                .or(col.a2.as_deref()),
+            freq: pick(spec.map(|v| &v.freq), &col.freq),
+            snp_id: pick(spec.map(|v| &v.snp_id), &col.snp_id),
+            pvalue: pick(spec.map(|v| &v.pvalue), &col.pvalue),
             // misc
-            score_names: &self.score_names,
-            weight_path: &self.weight_path,
+            score_names: spec.map_or(&self.score_names, |v| &v.score_names),
+            weight_types: &self.weight_type,
+            weight_path: spec.map_or(&self.weight_path, |v| &v.path),
+            extra_cols: q_range.map_or(vec![], |v| v.columns()),
+            rejected_path: match spec {
+                Some(v) => format!("{}.{}.rejected.tsv", self.out_prefix, v.name),
+                None => format!("{}.rejected.tsv", self.out_prefix),
+            },
+            duplicates_path: match spec {
+                Some(v) => format!("{}.{}.duplicates.tsv", self.out_prefix, v.name),
+                None => format!("{}.duplicates.tsv", self.out_prefix),
+            },
+            dup_policy,
             // flag
-            need_freq: matches!(missing_strategy, MissingStrategy::Freq),
-            need_id: self.match_id_flag,
-            need_pvalue: self.q_ranges.is_some(),
-        };
-        let qragne_or_score = match &self.q_ranges {
-            Some(v) => QrangeOrScorenames::QRange(QRange::new(v, &self.score_names)?),
+            strict: self.strict,
+            pvalue_range: q_range.filter(|_| self.prune_pvalue).and_then(|v| v.bounds()),
+            need_freq,
+            need_id: match_mode != MatchMode::Pos,
+            parse_id: match_mode == MatchMode::IdPos,
+            need_pvalue: q_range.is_some_and(|v| v.uses_pvalue()) || self.clump,
+        }
+    }
+
+    /// Convert [MyArgs] into [BetaSet] and [MetaArg]
+    /// [BetaSet] is for reading of beta of each model from [betareader]
+    pub fn get_structs(&self) -> Result<(BetaSet<'_>, MetaArg<'_>)> {
+        // some check
+        let missing_strategy = MissingStrategy::new(&self.missing_strategy)?;
+        let ambiguous_policy = AmbiguousPolicy::new(&self.ambiguous)?;
+        let match_mode = self.get_match_mode()?;
+        let dup_policy = DupPolicy::new(&self.dup_policy)?;
+        debug!("Model: {}", &self.weight_path);
+        debug!("Bfile: {}", &self.bed_path);
+
+        let need_freq = matches!(missing_strategy, MissingStrategy::Freq)
+            || matches!(ambiguous_policy, AmbiguousPolicy::Freq);
+        let qragne_or_score = match self.get_q_range()? {
+            Some(v) => QrangeOrScorenames::QRange(v),
             None => QrangeOrScorenames::ScoreNameRaws(&self.score_names),
         };
+        let q_range = match &qragne_or_score {
+            QrangeOrScorenames::QRange(v) => Some(v),
+            _ => None,
+        };
+        let models = if self.models.is_empty() {
+            vec![BetaModel {
+                beta_arg: self.get_beta_arg(
+                    None,
+                    need_freq,
+                    q_range,
+                    match_mode,
+                    dup_policy,
+                ),
+                out_names: &self.score_names,
+            }]
+        } else {
+            self.models
+                .iter()
+                .map(|v| BetaModel {
+                    beta_arg: self.get_beta_arg(
+                        Some(v),
+                        need_freq,
+                        q_range,
+                        match_mode,
+                        dup_policy,
+                    ),
+                    out_names: &v.out_names,
+                })
+                .collect()
+        };
+        let beta_set = BetaSet { models };
         let meta_arg = MetaArg {
             batch_size: self.batch_size,
             thread_num: self.thread_num,
-            match_id_flag: self.match_id_flag,
+            match_mode,
+            sorted_match: self.sorted_match,
+            match_report: self.match_report,
             missing_strategy,
+            score_mode: ScoreMode::new(&self.score_mode)?,
+            genotype_scale: GenotypeScale::new(&self.genotype_scale)?,
+            ambiguous_policy,
             out_prefix: &self.out_prefix,
             q_range_enum: qragne_or_score,
+            clump_arg: self.clump.then(|| ClumpArg {
+                bfile_path: self
+                    .clump_ref
+                    .clone()
+                    .unwrap_or_else(|| self.bed_path.clone()),
+                r2: self.clump_r2,
+                kb: self.clump_kb,
+                pvalue: self.clump_p,
+            }),
+            score_per_chrom: self.score_per_chrom,
         };
         // bed_path and out_path are still only in self, they should not belong to meta
         // and they should only be access in main
-        Ok((beta_arg, meta_arg))
+        Ok((beta_set, meta_arg))
     }
 }
@@ -4,13 +4,17 @@ mod args;
 mod runner;
 mod utils;
 
+use std::process;
 
 use args::MyArgs;
 use clap::Parser;
 use genoreader::{BedReaderNoLib, BfileSet};
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use polars::prelude::DataFrame;
-use predictor::join::MatchStatus;
+use predictor::{
+    join::{guard::GuardAction, with_bim_id_key, MatchStatus},
+    meta::MatchMode,
+};
 
 use crate::{
     runner::{post::PgsResult, Runner},
@@ -27,19 +31,32 @@ fn main_fn() {
     // parse to Runner obj
     let runner = Runner::from_args(&cli).unwrap();
     print_run_config(&cli);
+    // ID key of bim is computed once for all batches
+    let match_mode = cli.get_match_mode().unwrap();
 
     // batch by snp or ind
     let mut scores: DataFrame;
-    let match_status: MatchStatus;
+    let mut match_status: MatchStatus;
     if !cli.batch_ind {
-        let bfileset = BfileSet::new(&cli.bed_path).unwrap();
+        // bim is read in a streaming manner for sorted match
+        let mut bfileset = if cli.sorted_match {
+            BfileSet::new_without_bim(&cli.bed_path).unwrap()
+        } else {
+            BfileSet::new(&cli.bed_path).unwrap()
+        };
+        if match_mode == MatchMode::IdPos {
+            bfileset.bim = with_bim_id_key(bfileset.bim).unwrap();
+        }
         debug!(
             "Successfully load bfile with {} snp and {} ind",
-            &bfileset.bim.height(), &bfileset.fam.height()
+            &bfileset.sid_count, &bfileset.fam.height()
         );
         (scores, match_status) = runner.run_batch_snp(bfileset).unwrap();
     } else {
-        let bed = BedReaderNoLib::new(&cli.bed_path).unwrap();
+        let mut bed = BedReaderNoLib::new(&cli.bed_path).unwrap();
+        if match_mode == MatchMode::IdPos {
+            bed.bim = with_bim_id_key(bed.bim).unwrap();
+        }
         debug!(
             "Successfully load bfile with {} snp and {} ind",
             &bed.sid_count, &bed.iid_count
@@ -50,8 +67,25 @@ fn main_fn() {
         "There are {} snps matched between bfile ({} snp) and beta ({} snp)",
         match_status.match_snp, match_status.bfile_snp, match_status.model_snp
     );
+    for (score_name, score_status) in &match_status.scores {
+        info!(
+            "Score {}: {} of {} snps matched",
+            score_name, score_status.match_snp, score_status.model_snp
+        );
+    }
     debug!("{}", scores);
 
+    // check match rate and weight coverage
+    let match_guard = cli.get_match_guard().unwrap();
+    match_status.finalize();
+    let failed = match_guard.check(&mut match_status);
+    if !failed.is_empty() {
+        warn!(
+            "Scores {:?} fail --min-match-rate or --min-weight-coverage, see {}.check.json",
+            failed, cli.out_prefix
+        );
+    }
+
     // write
     let mut pgs_score = PgsResult::new(
         &mut scores,
@@ -60,7 +94,16 @@ fn main_fn() {
         &cli.out_prefix,
         cli.eval_flag
     );
+    if !failed.is_empty() && match_guard.action == GuardAction::Fail {
+        // only write the reason
+        pgs_score.write_status().unwrap();
+        error!("Abort since scores fail match guard rails, use --guard-action Flag to keep them");
+        process::exit(1);
+    }
     pgs_score.write_output().unwrap();
+    if let Some(select_arg) = cli.get_select_arg().unwrap() {
+        runner.select_threshold(&scores, &select_arg).unwrap();
+    }
     info!("Complete pgs-predictor!");
 }
 
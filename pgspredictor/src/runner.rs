@@ -6,37 +6,47 @@ mod ind_batch;
 pub mod post;
 mod snp_batch;
 
-use anyhow::Result;
-use betareader::BetaArg;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use betareader::{batch::BetaBatchReader, beta_set::BetaSet};
 use genoreader::{BedReaderNoLib, BfileSet};
 use ind_batch::{cal_score_batch_ind_par, cal_score_batch_ind_single};
-use log::info;
+use log::{debug, info};
 use polars::prelude::DataFrame;
 use predictor::{
-    join::{match_snp, MatchStatus},
-    meta::MetaArg,
+    join::{betahandler::Range, clump::clump, match_rows, report::match_report, MatchStatus},
+    meta::{MetaArg, QrangeOrScorenames},
+    select::{select_best, SelectArg},
 };
 use snp_batch::{cal_score_batch_snp_par, cal_score_batch_snp_single};
 
-use crate::{args::MyArgs, runner::post::write_beta};
+use crate::{
+    args::MyArgs,
+    runner::post::{write_beta, write_clumps, write_match_report, write_model, write_selection},
+};
 
-/// The [Runner] struct. Basically from [Args]. [BetaArg] is for argument to
-/// load weights. [MetaArg] is runner argument such as batch_size.
+/// The [Runner] struct. Basically from [Args]. [BetaSet] is for argument to
+/// load weights of each model. [MetaArg] is runner argument such as
+/// batch_size.
 pub struct Runner<'a> {
-    beta_arg: BetaArg<'a>,
+    beta_set: BetaSet<'a>,
     meta_arg: MetaArg<'a>,
     write_match: bool,
+    /// beta after clumping, kept to write the model of selected Q range
+    clumped_beta: Mutex<Option<DataFrame>>,
 }
 
 impl Runner<'_> {
     /// Init from [Args]
     pub fn from_args(cli: &MyArgs) -> Result<Runner> {
-        let (beta_arg, meta_arg) = cli.get_structs()?;
+        let (beta_set, meta_arg) = cli.get_structs()?;
 
         Ok(Runner {
-            beta_arg,
+            beta_set,
             meta_arg,
             write_match: cli.write_beta,
+            clumped_beta: Mutex::new(None),
         })
     }
 
@@ -44,24 +54,36 @@ impl Runner<'_> {
     /// [cal_score_batch_ind_single]. For multithread ->
     /// [cal_score_batch_ind_par]
     pub fn run_batch_ind(&self, bed: BedReaderNoLib) -> Result<(DataFrame, MatchStatus)> {
-        let (beta, cols) = self.beta_arg.read()?;
-        let (weights, match_status, mut match_beta) =
-            match_snp(&self.meta_arg, &cols, &bed.bim, beta)?;
+        let (beta, cols) = self.read_beta()?;
+        let rows = match_rows(&self.meta_arg, &cols, &bed.bim, beta)?;
+        if self.meta_arg.match_report {
+            let mut report = match_report(&self.meta_arg, &bed.bim, &rows)?;
+            write_match_report(&mut report, self.meta_arg.out_prefix, false)?;
+        }
+        let (mut weights, mut match_status, mut match_beta) =
+            rows.into_weights(&self.meta_arg, &bed.bim)?;
         info!(
             "Successful load model. Match {}/{} of snp",
             match_status.match_snp, match_status.model_snp,
         );
+        // first pass for mean dosage of the whole cohort, so that scores do
+        // not depend on the batch size
+        if weights.needs_dosage() {
+            weights.cohort_dosage = Some(bed.read_freq(&weights.sid_idx)?);
+            debug!("Read mean dosage of {} snp in all samples", weights.sid_idx.len());
+        }
 
         // run
         let score_frame = if self.meta_arg.thread_num == 1 {
-            cal_score_batch_ind_single(&self.meta_arg, weights, bed)?
+            cal_score_batch_ind_single(&self.meta_arg, weights, bed, &mut match_status)?
         } else {
-            cal_score_batch_ind_par(&self.meta_arg, weights, bed)?
+            cal_score_batch_ind_par(&self.meta_arg, weights, bed, &mut match_status)?
         };
         // save beta
         if self.write_match {
             write_beta(&mut match_beta, self.meta_arg.out_prefix, false)?;
         }
+        self.record_weight_types(&mut match_status)?;
         Ok((score_frame, match_status))
     }
 
@@ -69,9 +91,16 @@ impl Runner<'_> {
     /// [cal_score_batch_snp_single]. For multithread ->
     /// [cal_score_batch_snp_par]
     pub fn run_batch_snp(&self, bfileset: BfileSet) -> Result<(DataFrame, MatchStatus)> {
-        let (beta_batch_reader, cols) = self.beta_arg.batch_read(self.meta_arg.batch_size)?;
+        let (beta_batch_reader, cols) = match self.meta_arg.clump_arg {
+            // clumping needs all snp at once
+            Some(_) => {
+                let (beta, cols) = self.read_beta()?;
+                (BetaBatchReader::from_frame(beta, self.meta_arg.batch_size), cols)
+            }
+            None => self.beta_set.batch_read(self.meta_arg.batch_size)?,
+        };
 
-        let (score_frame, match_status) = if self.meta_arg.thread_num == 1 {
+        let (score_frame, mut match_status) = if self.meta_arg.thread_num == 1 {
             cal_score_batch_snp_single(
                 &self.meta_arg,
                 cols,
@@ -88,6 +117,87 @@ impl Runner<'_> {
                 self.write_match,
             )?
         };
+        self.record_weight_types(&mut match_status)?;
         Ok((score_frame, match_status))
     }
+
+    /// Read all beta, which is clumped if required
+    fn read_beta(&self) -> Result<(DataFrame, Vec<String>)> {
+        let (beta, cols) = self.beta_set.read()?;
+        match &self.meta_arg.clump_arg {
+            Some(clump_arg) => {
+                let (beta, mut clumps) = clump(&self.meta_arg, &cols, clump_arg, beta)?;
+                write_clumps(&mut clumps, self.meta_arg.out_prefix)?;
+                *self.clumped_beta.lock().unwrap() = Some(beta.clone());
+                Ok((beta, cols))
+            }
+            None => Ok((beta, cols)),
+        }
+    }
+
+    /// Select the best Q range of each score by [SelectArg], and write the
+    /// selection to `{out_prefix}.select.csv` and weights of each selected Q
+    /// range as a model file
+    pub fn select_threshold(&self, scores: &DataFrame, select_arg: &SelectArg) -> Result<()> {
+        let q_range = match &self.meta_arg.q_range_enum {
+            QrangeOrScorenames::QRange(v) => v,
+            QrangeOrScorenames::ScoreNameRaws(_) => {
+                return Err(anyhow!("--select-threshold is only supported in CandT"))
+            }
+        };
+        let score_ranges: Vec<(&String, (&String, Option<&Range>))> = self
+            .meta_arg
+            .get_score_names(false)
+            .iter()
+            .zip(q_range.score_ranges())
+            .collect();
+        let mut selections = vec![];
+        for score_name in q_range.score_names_raw {
+            let candidates: Vec<String> = score_ranges
+                .iter()
+                .filter(|(_, (raw_name, range))| *raw_name == score_name && range.is_some())
+                .map(|(name, _)| name.to_string())
+                .collect();
+            let selection = select_best(scores, score_name, &candidates, select_arg)?;
+            info!(
+                "Selected {} for {} with out-of-fold {} {:?}",
+                selection.selected,
+                score_name,
+                select_arg.metric.name(),
+                selection.out_of_fold
+            );
+            selections.push(selection);
+        }
+        write_selection(&selections, select_arg.metric.name(), self.meta_arg.out_prefix)?;
+
+        let beta = match self.clumped_beta.lock().unwrap().clone() {
+            Some(v) => v,
+            None => self.beta_set.read()?.0,
+        };
+        for selection in &selections {
+            let range = score_ranges
+                .iter()
+                .find(|(name, _)| **name == selection.selected)
+                .and_then(|(_, (_, range))| *range)
+                .ok_or_else(|| anyhow!("Q range of {} is not found", selection.selected))?;
+            write_model(
+                &beta,
+                &selection.score_name,
+                range.expr(),
+                &selection.selected,
+                self.meta_arg.out_prefix,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// record weight type and the applied transform of each score
+    fn record_weight_types(&self, match_status: &mut MatchStatus) -> Result<()> {
+        for (score_name, weight_type) in self.beta_set.weight_types()? {
+            let score_status = match_status.scores.entry(score_name).or_default();
+            score_status.weight_type = Some(weight_type.to_string());
+            score_status.transform = Some(weight_type.transform_name());
+        }
+        Ok(())
+    }
 }
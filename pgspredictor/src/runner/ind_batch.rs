@@ -6,15 +6,18 @@ use genoreader::BedReaderNoLib;
 use log::debug;
 use polars::prelude::DataFrame;
 use predictor::{
-    join::weight::Weights,
-    meta::MetaArg,
+    join::{weight::Weights, MatchStatus},
+    meta::{MetaArg, ScoreMode},
     predict::{cal_scores, get_empty_score},
 };
 
+use crate::runner::post::write_chrom_scores;
+
 pub fn cal_score_batch_ind_single(
     meta_arg: &MetaArg,
     weights: Weights,
     bed: BedReaderNoLib,
+    match_status: &mut MatchStatus,
 ) -> Result<DataFrame> {
     let mut num_batches = bed.iid_count / meta_arg.batch_size;
     if bed.iid_count % meta_arg.batch_size > 0 {
@@ -22,17 +25,26 @@ pub fn cal_score_batch_ind_single(
     }
 
     let mut result = get_empty_score(meta_arg.get_score_names(false))?;
+    let mut chrom_result: Option<DataFrame> = None;
+    let mut n_missing = vec![0; weights.sid_idx.len()];
     for i in 0..num_batches {
-        let score = cal_scores(
+        let (score, chrom_score, batch_missing) = cal_scores(
             &weights,
             i,
             meta_arg.batch_size,
             &bed,
             meta_arg.get_score_names(false),
+            meta_arg.score_mode,
         )?;
         result = result.vstack(&score)?;
+        chrom_result = vstack_chrom_score(chrom_result, chrom_score)?;
+        add_missing(&mut n_missing, &batch_missing);
         debug!("Complete {}/{} batch", i + 1, num_batches);
     }
+    match_status.add_imputed(&weights, &n_missing, bed.iid_count);
+    if let Some(mut chrom_result) = chrom_result {
+        write_chrom_scores(&mut chrom_result, meta_arg.out_prefix)?;
+    }
     Ok(result)
 }
 
@@ -45,21 +57,25 @@ struct ThreadWorkerBatchInd {
     pub weights: Arc<Weights>,
     // recieve from main string, file path
     pub score_names: Arc<Vec<String>>,
+    // sum or average score
+    pub score_mode: ScoreMode,
     // send from main
     pub receiver: Receiver<Option<usize>>,
-    // send to main
-    pub sender: Sender<DataFrame>,
+    // send to main, with partial scores by chromosome and number of
+    // missing genotypes of each snp
+    pub sender: Sender<(DataFrame, Option<DataFrame>, Vec<u32>)>,
 }
 
 impl ThreadWorkerBatchInd {
     fn run(&mut self) -> Result<()> {
         while let Some(idx) = self.receiver.recv()? {
-            let score: DataFrame = cal_scores(
+            let score = cal_scores(
                 &self.weights,
                 idx,
                 self.batch_size,
                 &self.bed,
                 &self.score_names,
+                self.score_mode,
             )?;
             self.sender.send(score).unwrap();
         }
@@ -71,6 +87,7 @@ pub fn cal_score_batch_ind_par(
     meta_arg: &MetaArg,
     weights: Weights,
     bed: BedReaderNoLib,
+    match_status: &mut MatchStatus,
 ) -> Result<DataFrame> {
     let (input_sender, input_receiver) = unbounded();
     let (output_sender, output_receiver) = unbounded();
@@ -86,6 +103,7 @@ pub fn cal_score_batch_ind_par(
             bed: bed.clone(),
             weights: weights.clone(),
             score_names: score_names.clone(),
+            score_mode: meta_arg.score_mode,
             receiver: input_receiver.clone(),
             sender: output_sender.clone(),
         };
@@ -111,15 +129,41 @@ pub fn cal_score_batch_ind_par(
     // collect result
     let mut init_flag = true;
     let mut result = get_empty_score(&score_names)?;
-    for (i, score) in output_receiver.into_iter().enumerate() {
+    let mut chrom_result: Option<DataFrame> = None;
+    let mut n_missing = vec![0; weights.sid_idx.len()];
+    for (i, (score, chrom_score, batch_missing)) in output_receiver.into_iter().enumerate() {
+        add_missing(&mut n_missing, &batch_missing);
         if init_flag {
             result = score;
             init_flag = false;
         } else {
             result = result.vstack(&score)?;
         }
+        chrom_result = vstack_chrom_score(chrom_result, chrom_score)?;
         debug!("Complete {}/{} batch", i + 1, num_batches);
     }
+    match_status.add_imputed(&weights, &n_missing, bed.iid_count);
+    if let Some(mut chrom_result) = chrom_result {
+        write_chrom_scores(&mut chrom_result, meta_arg.out_prefix)?;
+    }
 
     Ok(result)
 }
+
+/// sum number of missing genotypes of each snp over ind batches
+fn add_missing(n_missing: &mut [u32], batch_missing: &[u32]) {
+    for (v, batch_v) in n_missing.iter_mut().zip(batch_missing) {
+        *v += batch_v;
+    }
+}
+
+/// append partial scores by chromosome of a batch of samples
+fn vstack_chrom_score(
+    chrom_result: Option<DataFrame>,
+    chrom_score: Option<DataFrame>,
+) -> Result<Option<DataFrame>> {
+    match (chrom_result, chrom_score) {
+        (Some(v), Some(chrom_score)) => Ok(Some(v.vstack(&chrom_score)?)),
+        (chrom_result, chrom_score) => Ok(chrom_result.or(chrom_score)),
+    }
+}
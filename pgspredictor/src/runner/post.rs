@@ -1,10 +1,12 @@
 use std::fs::{File, OpenOptions};
 use anyhow::Result;
-use log::info;
-use polars::prelude::{
-    CsvWriter, DataFrame,  SerWriter,
+use betareader::{Float, A1, A2, CHR, FREQ, ID, POS};
+use log::{debug, info};
+use polars::{
+    lazy::dsl::{col, cols, lit, Expr},
+    prelude::{CsvWriter, DataFrame, IntoLazy, NamedFrom, SerWriter, Series},
 };
-use predictor::{join::MatchStatus, metrics};
+use predictor::{join::MatchStatus, metrics, select::Selection};
 pub struct PgsResult<'a> {
     scores: &'a mut DataFrame,
     match_status: MatchStatus,
@@ -50,9 +52,11 @@ impl PgsResult<'_> {
         Ok(())
     }
 
-    fn write_status(&self) -> Result<()> {
+    pub fn write_status(&self) -> Result<()> {
         let out_path = self.out_prefix.to_owned() + ".check.json";
-        let json_value = serde_json::to_value(&self.match_status)?;
+        let mut match_status = self.match_status.clone();
+        match_status.finalize();
+        let json_value = serde_json::to_value(&match_status)?;
         let mut file = File::create(&out_path)?;
         serde_json::to_writer_pretty(&mut file, &json_value)?;
         info!("Output check status to {}", out_path);
@@ -94,3 +98,98 @@ pub fn write_beta(beta: &mut DataFrame, out_prefix: &str, append_flag: bool) ->
     info!("Output beta to {}", &out_path);
     Ok(())
 }
+
+/// write disposition of weight rows to `{out_prefix}.match.tsv`, header is
+/// only written for the first batch
+pub fn write_match_report(
+    report: &mut DataFrame,
+    out_prefix: &str,
+    append_flag: bool,
+) -> Result<()> {
+    let out_path = out_prefix.to_owned() + ".match.tsv";
+    let out_file = if append_flag {
+        OpenOptions::new().append(true).open(&out_path)?
+    } else {
+        File::create(&out_path)?
+    };
+    CsvWriter::new(out_file)
+        .has_header(!append_flag)
+        .with_delimiter(b'\t')
+        .finish(report)?;
+    debug!("Output match report to {}", &out_path);
+    Ok(())
+}
+
+/// write index snp of clumping to `{out_prefix}.clumps.tsv`
+pub fn write_clumps(clumps: &mut DataFrame, out_prefix: &str) -> Result<()> {
+    let out_path = out_prefix.to_owned() + ".clumps.tsv";
+    CsvWriter::new(File::create(&out_path)?)
+        .has_header(true)
+        .with_delimiter(b'\t')
+        .finish(clumps)?;
+    info!("Output index snp of clumping to {}", &out_path);
+    Ok(())
+}
+
+/// write partial scores by chromosome to `{out_prefix}.chrom_score.csv`, a
+/// row for each sample and chromosome
+pub fn write_chrom_scores(chrom_scores: &mut DataFrame, out_prefix: &str) -> Result<()> {
+    let out_path = out_prefix.to_owned() + ".chrom_score.csv";
+    CsvWriter::new(File::create(&out_path)?)
+        .has_header(true)
+        .finish(chrom_scores)?;
+    info!("Output partial scores by chromosome to {}", &out_path);
+    Ok(())
+}
+
+/// write selected Q range of each score to `{out_prefix}.select.csv`
+pub fn write_selection(selections: &[Selection], metric: &str, out_prefix: &str) -> Result<()> {
+    let out_path = out_prefix.to_owned() + ".select.csv";
+    let names: Vec<&str> = selections.iter().map(|v| v.score_name.as_str()).collect();
+    let selected: Vec<&str> = selections.iter().map(|v| v.selected.as_str()).collect();
+    let in_sample: Vec<Option<f64>> = selections.iter().map(|v| v.in_sample).collect();
+    let out_of_fold: Vec<Option<f64>> = selections.iter().map(|v| v.out_of_fold).collect();
+    let out_of_fold_sd: Vec<Option<f64>> = selections.iter().map(|v| v.out_of_fold_sd).collect();
+    let mut select_res = DataFrame::new(vec![
+        Series::new("Name", names),
+        Series::new("Selected", selected),
+        Series::new("metric", vec![metric; selections.len()]),
+        Series::new("in_sample", in_sample),
+        Series::new("out_of_fold", out_of_fold),
+        Series::new("out_of_fold_sd", out_of_fold_sd),
+    ])?;
+    CsvWriter::new(File::create(&out_path)?)
+        .has_header(true)
+        .finish(&mut select_res)?;
+    info!("Output selected Q ranges to {}", &out_path);
+    Ok(())
+}
+
+/// Write snp of beta in the selected Q range with non-zero weight to
+/// `{out_prefix}.{selected}.model.tsv`, with weight of `score_name` as column
+/// `selected`, which can be used as a weight file.
+pub fn write_model(
+    beta: &DataFrame,
+    score_name: &str,
+    range_expr: Expr,
+    selected: &str,
+    out_prefix: &str,
+) -> Result<()> {
+    let out_path = format!("{}.{}.model.tsv", out_prefix, selected);
+    let snp_cols: Vec<&str> = [CHR, POS, ID, A1, A2, FREQ]
+        .into_iter()
+        .filter(|v| beta.column(v).is_ok())
+        .collect();
+    let mut model = beta
+        .clone()
+        .lazy()
+        .filter(range_expr.and(col(score_name).neq(lit(0 as Float))))
+        .select([cols(snp_cols), col(score_name).alias(selected)])
+        .collect()?;
+    CsvWriter::new(File::create(&out_path)?)
+        .has_header(true)
+        .with_delimiter(b'\t')
+        .finish(&mut model)?;
+    info!("Output {} snp of {} to {}", model.height(), selected, &out_path);
+    Ok(())
+}
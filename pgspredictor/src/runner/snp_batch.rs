@@ -2,24 +2,27 @@ use std::{sync::Arc, thread};
 
 use anyhow::{anyhow, Result};
 use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
-use genoreader::{BfileSet, FreqBedReader};
+use genoreader::{BfileSet, BimCursor, FreqBedReader};
 use log::debug;
-use ndarray::Array2;
 //use ndarray::prelude::*;
-use polars::{prelude::{read_impl::OwnedBatchedCsvReader, DataFrame, NamedFrom, IntoLazy, Literal}, series::Series};
+use betareader::batch::BetaBatchReader;
+use polars::{prelude::{DataFrame, NamedFrom, IntoLazy, Literal}, series::Series};
 use predictor::{
-    join::{match_snp, weight::Weights, MatchStatus},
+    join::{
+        match_rows, report::match_report, sorted::sorted_bim_window, weight::Weights, MatchStatus,
+        NoSnpMatched,
+    },
     meta::MetaArg,
-    predict::{cal_score_array_freq_reader, score_to_frame},
+    predict::{cal_score_array_freq_reader, BatchScore},
 };
 
-use crate::runner::post::write_beta;
+use crate::runner::post::{write_beta, write_chrom_scores, write_match_report};
 
 #[allow(unused_variables)]
 pub fn cal_score_batch_snp_single(
     meta_arg: &MetaArg,
     cols: Vec<String>,
-    mut beta_batch_reader: OwnedBatchedCsvReader,
+    mut beta_batch_reader: BetaBatchReader,
     bfileset: BfileSet,
     write_match: bool,
 ) -> Result<(DataFrame, MatchStatus)> {
@@ -31,31 +34,51 @@ pub fn cal_score_batch_snp_single(
     let score_names: Vec<String>;
     // init
     let mut match_status = MatchStatus::new_empty();
-    let mut score_sum: Option<Array2<f32>> = None;
+    let mut score_sum: Option<BatchScore> = None;
     let mut i = 0;
+    let mut n_report = 0;
 
+    let mut bim_cursor = get_bim_cursor(meta_arg, &bfileset)?;
     let mut geno_reader = FreqBedReader::new(Arc::new(bfileset))?;
     loop {
         // get beta
-        beta = match beta_batch_reader.next_batches(1)? {
-            Some(v) => v[0].to_owned(),
+        beta = match beta_batch_reader.next_batch()? {
+            Some(v) => v,
             None => break,
         };
         beta = beta.select(&cols)?;
+        // bim window for sorted match, otherwise the whole bim
+        let bim_window = match &mut bim_cursor {
+            Some(cursor) => Some(sorted_bim_window(cursor, &beta)?),
+            None => None,
+        };
+        let bim = bim_window.as_ref().unwrap_or(&geno_reader.bfile_set.bim);
         // match snp
-        (weights, new_match_status, matched_beta) = match match_snp(meta_arg, &cols, &geno_reader.bfile_set.bim, beta)
-        {
+        let rows = match_rows(meta_arg, &cols, bim, beta)?;
+        if meta_arg.match_report {
+            let mut report = match_report(meta_arg, bim, &rows)?;
+            write_match_report(&mut report, meta_arg.out_prefix, n_report > 0)?;
+            n_report += 1;
+        }
+        (weights, new_match_status, matched_beta) = match rows.into_weights(meta_arg, bim) {
             Ok(v) => v,
-            // TODO -> Classify Error
+            // snp of the batch are still counted
             Err(e) => {
-                debug!("{}", e);
+                let NoSnpMatched(new_match_status) = e.downcast()?;
+                debug!("No snp matched in a batch");
+                match_status = match_status + new_match_status;
                 continue;
             }
         };
-        // add match_status
-        match_status = match_status + new_match_status;
         // cal score
         let (score, freq_vec) = cal_score_array_freq_reader(&mut geno_reader, &weights)?;
+        // add match_status
+        new_match_status.add_imputed(
+            &weights,
+            geno_reader.last_missing(),
+            geno_reader.bfile_set.fam.height(),
+        );
+        match_status = match_status + new_match_status;
         score_sum = match score_sum {
             Some(v) => Some(v + score),
             None => Some(score),
@@ -73,6 +96,8 @@ pub fn cal_score_batch_snp_single(
         debug!("Complete {} batch", i + 1);
         i += 1;
     }
+    // bim may only be partly read
+    match_status.bfile_snp = geno_reader.bfile_set.sid_count;
     // unwrap score
     let score_sum = match score_sum {
         Some(v) => v,
@@ -80,7 +105,7 @@ pub fn cal_score_batch_snp_single(
     };
     // score for frame
     let batch_fam = geno_reader.bfile_set.get_ind(None, false)?;
-    let score_frame = score_to_frame(&batch_fam, score_sum, meta_arg.get_score_names(false))?;
+    let score_frame = finish_score_frame(meta_arg, score_sum, &batch_fam)?;
 
     Ok((score_frame, match_status))
 }
@@ -95,12 +120,15 @@ pub struct ThreadWorkerBatchSnp<'a> {
     pub cols: Arc<Vec<String>>,
     // recieve from main string, file path
     pub meta_arg: Arc<&'a MetaArg<'a>>,
-    // get from main
-    pub receiver: Receiver<Option<DataFrame>>,
-    // send to main
-    pub sender: Sender<(Array2<f32>, MatchStatus, DataFrame)>,
+    // get beta and bim window (for sorted match) from main
+    pub receiver: Receiver<Option<(DataFrame, Option<DataFrame>)>>,
+    // send to main, score is None if no snp matched in the batch
+    pub sender: Sender<BatchResult>,
 }
 
+/// score, match status, matched beta and match report of a batch
+type BatchResult = (Option<BatchScore>, MatchStatus, DataFrame, Option<DataFrame>);
+
 impl ThreadWorkerBatchSnp<'_> {
     fn run(&mut self) -> Result<()> {
         let mut beta: DataFrame;
@@ -108,33 +136,46 @@ impl ThreadWorkerBatchSnp<'_> {
         let mut matched_beta: DataFrame;
         let mut match_status: MatchStatus;
         let mut weights: Weights;
-        let mut score: Array2<f32>;
+        let mut score: BatchScore;
         let mut freq_vec: Option<Vec<f32>>;
     
         let mut geno_reader = FreqBedReader::new(self.bfileset.clone())?;
         loop {
-            beta = match self.receiver.recv()? {
+            let bim_window: Option<DataFrame>;
+            (beta, bim_window) = match self.receiver.recv()? {
                 Some(v) => v,
                 None => break,
             };
             beta = beta.select(&*self.cols)?;
             // match snp
-            (weights, match_status, matched_beta) = match match_snp(&self.meta_arg, &self.cols, &geno_reader.bfile_set.bim, beta)
-            {
+            let bim = bim_window.as_ref().unwrap_or(&geno_reader.bfile_set.bim);
+            let rows = match_rows(&self.meta_arg, &self.cols, bim, beta)?;
+            let report = match self.meta_arg.match_report {
+                true => Some(match_report(&self.meta_arg, bim, &rows)?),
+                false => None,
+            };
+            (weights, match_status, matched_beta) = match rows.into_weights(&self.meta_arg, bim) {
                 Ok(v) => v,
+                // snp of the batch are still counted, and report is still written
                 Err(e) => {
-                    debug!("{}", e);
+                    let NoSnpMatched(status) = e.downcast()?;
+                    self.sender.send((None, status, DataFrame::default(), report)).unwrap();
                     continue;
                 }
             };
             // cal score
             (score, freq_vec) = cal_score_array_freq_reader(&mut geno_reader, &weights)?;
+            match_status.add_imputed(
+                &weights,
+                geno_reader.last_missing(),
+                geno_reader.bfile_set.fam.height(),
+            );
             if freq_vec.is_some(){
                 let c = Series::new("FREQ", freq_vec.unwrap());
                 matched_beta = matched_beta.lazy().with_column(c.lit()).collect()?;
             }
             self.sender
-                .send((score, match_status, matched_beta))
+                .send((Some(score), match_status, matched_beta, report))
                 .unwrap();
             debug!("Complete {} batch", i + 1);
             i += 1;
@@ -148,7 +189,7 @@ type ThreadResVec<'a> = Vec<thread::ScopedJoinHandle<'a, Result<()>>>;
 pub fn cal_score_batch_snp_par(
     meta_arg: &MetaArg,
     cols: Vec<String>,
-    mut beta_batch_reader: OwnedBatchedCsvReader,
+    mut beta_batch_reader: BetaBatchReader,
     bfileset: BfileSet,
     write_match: bool,
 ) -> Result<(DataFrame, MatchStatus)> {
@@ -160,7 +201,7 @@ pub fn cal_score_batch_snp_par(
     let meta_arg: Arc<&MetaArg<'_>> = Arc::new(meta_arg.clone());
     let bfileset = Arc::new(bfileset);
 
-    let (score_sum, match_status) = thread::scope(|scope| -> Result<(Array2<f32>, MatchStatus)> {
+    let (score_sum, mut match_status) = thread::scope(|scope| -> Result<ScoreSum> {
         let mut thread_vec: ThreadResVec = vec![];
         for _ in 0..meta_arg.thread_num {
             let mut my_worker = ThreadWorkerBatchSnp {
@@ -178,14 +219,19 @@ pub fn cal_score_batch_snp_par(
         drop(input_receiver);
         drop(output_sender);
 
-        // send to worker
+        // send to worker, bim cursor should be walked in order
         let mut beta: DataFrame;
+        let mut bim_cursor = get_bim_cursor(*meta_arg, &bfileset)?;
         loop {
-            beta = match beta_batch_reader.next_batches(1)? {
-                Some(v) => v[0].to_owned(),
+            beta = match beta_batch_reader.next_batch()? {
+                Some(v) => v,
                 None => break,
             };
-            input_sender.send(Some(beta)).unwrap();
+            let bim_window = match &mut bim_cursor {
+                Some(cursor) => Some(sorted_bim_window(cursor, &beta.select(&*cols)?)?),
+                None => None,
+            };
+            input_sender.send(Some((beta, bim_window))).unwrap();
         }
         // end the input_sender, which will terminate the output_sender and threads
         for _ in 0..meta_arg.thread_num {
@@ -200,24 +246,55 @@ pub fn cal_score_batch_snp_par(
         Ok((score_sum, match_status))
     })?;
 
+    // bim may only be partly read
+    match_status.bfile_snp = bfileset.sid_count;
     // score to dataframe
     let batch_fam = bfileset.get_ind(None, false)?;
-    let score_frame = score_to_frame(&batch_fam, score_sum, meta_arg.get_score_names(false))?;
+    let score_frame = finish_score_frame(&meta_arg, score_sum, &batch_fam)?;
 
     Ok((score_frame, match_status))
 }
 
+/// Rescale or average score summed over all batches, and write partial
+/// scores by chromosome if any
+fn finish_score_frame(
+    meta_arg: &MetaArg,
+    mut score_sum: BatchScore,
+    fam: &DataFrame,
+) -> Result<DataFrame> {
+    score_sum.finish(meta_arg.score_mode, meta_arg.missing_strategy);
+    let (score_frame, chrom_frame) = score_sum.to_frames(fam, meta_arg.get_score_names(false))?;
+    if let Some(mut chrom_frame) = chrom_frame {
+        write_chrom_scores(&mut chrom_frame, meta_arg.out_prefix)?;
+    }
+    Ok(score_frame)
+}
+
+/// score and match status summed over batches
+type ScoreSum = (BatchScore, MatchStatus);
+
 fn join_threads_collect_result(
-    output_receiver: Receiver<(Array2<f32>, MatchStatus, DataFrame)>,
+    output_receiver: Receiver<BatchResult>,
     out_prefix: &str,
     write_match: bool,
-) -> Result<(Array2<f32>, MatchStatus)> {
+) -> Result<ScoreSum> {
     let mut match_status = MatchStatus::new_empty();
-    let mut score_sum: Option<Array2<f32>> = None;
-    for (cc, (score, new_match_status, mut matched_beta)) in output_receiver.into_iter().enumerate()
-    {
+    let mut score_sum: Option<BatchScore> = None;
+    let mut cc = 0;
+    let mut n_report = 0;
+    for (score, new_match_status, mut matched_beta, report) in output_receiver {
+        // report of batches in order of completion
+        if let Some(mut report) = report {
+            write_match_report(&mut report, out_prefix, n_report > 0)?;
+            n_report += 1;
+        }
         // add match_status
         match_status = match_status + new_match_status;
+        // batch without matched snp
+        let score = match score {
+            Some(v) => v,
+            None => continue,
+        };
         // cal score
         score_sum = match score_sum {
             Some(v) => Some(v + score),
@@ -231,6 +308,7 @@ fn join_threads_collect_result(
                 write_beta(&mut matched_beta, out_prefix, true)?;
             }
         }
+        cc += 1;
     }
     // unwrap score
     let score_sum = match score_sum {
@@ -246,3 +324,12 @@ fn join_thread_vec(thread_vec: ThreadResVec) -> Result<()> {
     }
     Ok(())
 }
+
+/// bim cursor for sorted match
+fn get_bim_cursor(meta_arg: &MetaArg, bfileset: &BfileSet) -> Result<Option<BimCursor>> {
+    if meta_arg.sorted_match {
+        Ok(Some(BimCursor::new(&bfileset.bim_path)?))
+    } else {
+        Ok(None)
+    }
+}
@@ -9,137 +9,640 @@
 //! corresponding to 1 bfile snp, we just get unique combination of POS, CHR and
 //! A1. This is fine since two bfile snp both got the A1 allele and they should
 //! be identical in the distribution of A1 allele.
+//!
+//! If the other allele (A2) is provided in beta, it should also match the
+//! other allele in bim. Alleles reported on the opposite strand are matched
+//! after complement (strand flip). Palindromic snp (A/T, C/G) are ambiguous
+//! and handled by [AmbiguousPolicy].
+pub mod allele;
+pub mod beta_matrix;
 pub mod betahandler;
+pub mod clump;
+pub mod guard;
+pub mod qbins;
+pub mod report;
+pub mod sorted;
+pub mod status;
 pub mod weight;
-use std::ops::Add;
+use std::collections::HashSet;
 
-use anyhow::{anyhow, Result};
+use allele::{flip_name, with_complement};
+use anyhow::Result;
 use betahandler::handle_beta;
-use betareader::{A1, CHR, ID, POS};
-use genoreader::meta::{ALT, IDX, REF};
+use betareader::{Float, A1, A2, CHR, ID, POS};
+use genoreader::{
+    allele::{normalize_allele, resolve_indel_code, trim_common},
+    meta::{ALT, IDX, REF},
+    variant_id::{bim_id_key, id_key},
+};
 use polars::{
-    lazy::dsl::{col, lit, when},
-    prelude::{DataFrame, DataFrameJoinOps, IntoLazy, UniqueKeepStrategy},
+    lazy::dsl::{col, cols, lit, when, Expr},
+    prelude::{
+        BooleanChunked, DataFrame, DataFrameJoinOps, IntoLazy, NamedFrom, Series,
+        UniqueKeepStrategy,
+    },
 };
-use serde::Serialize;
+use qbins::Q_BIN;
+pub use status::{MatchStatus, NoSnpMatched, ScoreStatus};
 use weight::Weights;
 
-use crate::meta::{MetaArg, STATUS};
+use crate::meta::{AmbiguousPolicy, MatchMode, MetaArg, QrangeOrScorenames, MATCH_RULE, STATUS};
 /// constant for SNP match status.
 /// [GOOD] indicate that `A1 == ALT`
 /// [SWAP] indicate that `A1 == REF`, and genotype need to be swap
-/// [NO_MATCH] indicate that `A1 != ALT` && `A1 != REF`, and the snp should be
-/// filtered out
+/// [FLIP] indicate that `A1` is on the other strand and `flip(A1) == ALT`
+/// [FLIP_SWAP] indicate that `flip(A1) == REF`, and genotype need to be swap
+/// [AMBIGUOUS] indicate that bim snp is palindromic (A/T or C/G), whether to
+/// swap is decided by frequency
+/// [NO_MATCH] indicate that none of the above is satisfied, and the snp should
+/// be filtered out
 pub const GOOD: &str = "Good";
 pub const SWAP: &str = "Swap";
+pub const FLIP: &str = "Flip";
+pub const FLIP_SWAP: &str = "FlipSwap";
+pub const AMBIGUOUS: &str = "Ambiguous";
 pub const NO_MATCH: &str = "NoMatch";
+/// rules recorded in [MATCH_RULE], by which snp are matched
+pub const RULE_ID: &str = "ID";
+pub const RULE_POS: &str = "POS";
+/// key column to match ID, see [genoreader::variant_id]
+pub const ID_KEY: &str = "ID_KEY";
+/// row number of beta, to trace each snp through matching
+const BETA_ROW: &str = "BETA_ROW";
+
+/// whether genotype should be swapped for the status. [AMBIGUOUS] is not
+/// included since it need frequency to be resolved.
+pub fn is_swap(status: &str) -> bool {
+    status == SWAP || status == FLIP_SWAP
+}
+
+/// `allele == target`, and `A2 == other` if A2 is provided and not missing
+fn allele_eq(a1: &str, a2: Option<&str>, target: &str, other: &str) -> Expr {
+    let a1_eq = col(a1).eq(col(target));
+    match a2 {
+        Some(a2) => a1_eq.and(col(a2).is_null().or(col(a2).eq(col(other)))),
+        None => a1_eq,
+    }
+}
+
+/// Classify each pair of beta and bim snp into match status. Ambiguous snp
+/// are checked first since their flipped alleles are always matched.
+fn get_status_expr(has_a2: bool) -> Expr {
+    let a2 = if has_a2 { Some(A2) } else { None };
+    let a1_flip = flip_name(A1);
+    let a2_flip = flip_name(A2);
+    let a2_flip = if has_a2 { Some(a2_flip.as_str()) } else { None };
+    // A2 of ambiguous snp should still be the other allele
+    let ambiguous = col(ALT)
+        .eq(col(&flip_name(REF)))
+        .and(allele_eq(A1, a2, ALT, REF).or(allele_eq(A1, a2, REF, ALT)));
 
-/// Match status, result of the join between bfile and beta.
-/// ```rust
-/// use crate::join::MatchStatus;
-/// let aa = MatchStatus::new_empty();
-/// let bb = MatchStatus::new(1234, 234, 198);
-/// aa = aa + bb;
-/// println("{}", aa);
-/// ```
-#[derive(Debug, Serialize, Clone)]
-pub struct MatchStatus {
-    pub bfile_snp: usize,
-    pub model_snp: usize,
-    pub match_snp: usize,
-}
-
-/// init an empty one
-#[allow(dead_code)]
-impl MatchStatus {
-    pub fn new(bfile_snp: usize, model_snp: usize, match_snp: usize) -> MatchStatus {
-        MatchStatus {
-            bfile_snp,
-            model_snp,
-            match_snp,
+    when(ambiguous)
+        .then(lit(AMBIGUOUS))
+        .when(allele_eq(A1, a2, ALT, REF))
+        .then(lit(GOOD))
+        .when(allele_eq(A1, a2, REF, ALT))
+        .then(lit(SWAP))
+        .when(allele_eq(&a1_flip, a2_flip, ALT, REF))
+        .then(lit(FLIP))
+        .when(allele_eq(&a1_flip, a2_flip, REF, ALT))
+        .then(lit(FLIP_SWAP))
+        .otherwise(lit(NO_MATCH))
+        .alias(STATUS)
+}
+
+/// Apply [AmbiguousPolicy]. For [AmbiguousPolicy::Keep], ambiguous snp are
+/// matched by alleles as is. For [AmbiguousPolicy::Freq], they are kept as
+/// [AMBIGUOUS] and resolved when genotype is read.
+fn apply_ambiguous_policy(matched_beta: DataFrame, policy: AmbiguousPolicy) -> Result<DataFrame> {
+    let is_ambiguous = col(STATUS).eq(lit(AMBIGUOUS));
+    let matched_beta = match policy {
+        AmbiguousPolicy::Drop => matched_beta.lazy().filter(is_ambiguous.not()).collect()?,
+        AmbiguousPolicy::Keep => matched_beta
+            .lazy()
+            .with_column(
+                when(is_ambiguous.and(col(A1).eq(col(ALT))))
+                    .then(lit(GOOD))
+                    .when(col(STATUS).eq(lit(AMBIGUOUS)))
+                    .then(lit(SWAP))
+                    .otherwise(col(STATUS))
+                    .alias(STATUS),
+            )
+            .collect()?,
+        AmbiguousPolicy::Freq => matched_beta,
+    };
+    Ok(matched_beta)
+}
+
+/// Normalize alleles of a pair of beta and bim snp. Bim alleles are case
+/// folded and padded ones are trimmed, and A1 (and A2) are mapped to the
+/// trimmed bim alleles. `I` and `D` code in beta are resolved to the longer
+/// and shorter bim allele.
+fn normalize_pair(
+    alt: &str,
+    reference: &str,
+    a1: &str,
+    a2: Option<&str>,
+) -> (String, String, String, Option<String>) {
+    let (alt, reference) = (normalize_allele(alt), normalize_allele(reference));
+    let (alt, reference) = (alt.as_str(), reference.as_str());
+    let (alt_trim, ref_trim) = trim_common(alt, reference);
+    let pair = (alt, reference);
+    let trimmed = (alt_trim.as_str(), ref_trim.as_str());
+    let a1_code = resolve_indel_code(a1, pair, trimmed);
+    let (a1, a2) = match a2 {
+        Some(a2) => {
+            let a2_code = resolve_indel_code(a2, pair, trimmed);
+            let (a1, a2) = trim_common(&a1_code, &a2_code);
+            (a1, Some(a2))
         }
+        None if a1_code == alt => (alt_trim.clone(), None),
+        None if a1_code == reference => (ref_trim.clone(), None),
+        None => (a1_code, None),
+    };
+    (alt_trim, ref_trim, a1, a2)
+}
+
+/// Match [NO_MATCH] snp again after [normalize_pair]. Return all rows with
+/// the updated status, and the number of snp matched by normalization. Only
+/// the status is taken from the normalized alleles, while the alleles in
+/// beta and bim are kept as they are reported.
+fn rematch_normalized(
+    matched_beta: DataFrame,
+    flip_cols: &[&str],
+    has_a2: bool,
+) -> Result<(DataFrame, usize)> {
+    let is_no_match = col(STATUS).eq(lit(NO_MATCH));
+    let no_match = matched_beta
+        .clone()
+        .lazy()
+        .filter(is_no_match.clone())
+        .collect()?;
+    if no_match.shape().0 == 0 {
+        return Ok((matched_beta, 0));
     }
+    let matched = matched_beta.lazy().filter(is_no_match.not()).collect()?;
 
-    pub fn new_empty() -> MatchStatus {
-        MatchStatus {
-            bfile_snp: 0,
-            model_snp: 0,
-            match_snp: 0,
+    let alt = no_match.column(ALT)?.utf8()?;
+    let reference = no_match.column(REF)?.utf8()?;
+    let a1 = no_match.column(A1)?.utf8()?;
+    let a2: Vec<Option<&str>> = match has_a2 {
+        true => no_match.column(A2)?.utf8()?.into_iter().collect(),
+        false => vec![None; no_match.shape().0],
+    };
+    let (mut alt_norm, mut ref_norm, mut a1_norm, mut a2_norm) = (vec![], vec![], vec![], vec![]);
+    for (((alt, reference), a1), a2) in alt.into_iter().zip(reference).zip(a1).zip(a2) {
+        match (alt, reference, a1) {
+            (Some(alt), Some(reference), Some(a1)) => {
+                let (alt, reference, a1, a2) = normalize_pair(alt, reference, a1, a2);
+                alt_norm.push(Some(alt));
+                ref_norm.push(Some(reference));
+                a1_norm.push(Some(a1));
+                a2_norm.push(a2);
+            }
+            _ => {
+                alt_norm.push(alt.map(|v| v.to_owned()));
+                ref_norm.push(reference.map(|v| v.to_owned()));
+                a1_norm.push(a1.map(|v| v.to_owned()));
+                a2_norm.push(a2.map(|v| v.to_owned()));
+            }
         }
     }
+    let mut normalized = no_match.drop(STATUS)?;
+    normalized.replace(ALT, Series::new(ALT, alt_norm))?;
+    normalized.replace(REF, Series::new(REF, ref_norm))?;
+    normalized.replace(A1, Series::new(A1, a1_norm))?;
+    if has_a2 {
+        normalized.replace(A2, Series::new(A2, a2_norm))?;
+    }
+
+    let status = with_complement(normalized, flip_cols)?
+        .lazy()
+        .select([get_status_expr(has_a2)])
+        .collect()?;
+    let mut rematched = no_match;
+    rematched.replace(STATUS, status.column(STATUS)?.clone())?;
+    let rematched = rematched.select(matched.get_column_names())?;
+    let normalized_snp = rematched.shape().0 - count_status(&rematched, NO_MATCH)?;
+    Ok((matched.vstack(&rematched)?, normalized_snp))
 }
 
-/// provide add function
-impl Add for MatchStatus {
-    type Output = MatchStatus;
-    fn add(self, another: MatchStatus) -> MatchStatus {
-        MatchStatus {
-            bfile_snp: another.bfile_snp,
-            model_snp: self.model_snp + another.model_snp,
-            match_snp: self.match_snp + another.match_snp,
-        }
+/// Classify joined beta and bim snp by match status. [NO_MATCH] snp are
+/// matched again after allele normalization. Return the classified rows and
+/// the number of snp matched by normalization.
+fn classify(joined: DataFrame, has_a2: bool) -> Result<(DataFrame, usize)> {
+    let mut flip_cols = vec![A1, REF];
+    if has_a2 {
+        flip_cols.push(A2);
     }
+    let joined = with_complement(joined, &flip_cols)?
+        .lazy()
+        .with_column(get_status_expr(has_a2))
+        .collect()?;
+    let drop_cols: Vec<String> = flip_cols.iter().map(|v| flip_name(v)).collect();
+    rematch_normalized(joined.drop_many(&drop_cols), &flip_cols, has_a2)
+}
+
+/// record the rule by which snp are matched
+fn with_rule(matched_beta: DataFrame, rule: &str) -> Result<DataFrame> {
+    Ok(matched_beta
+        .lazy()
+        .with_column(lit(rule).alias(MATCH_RULE))
+        .collect()?)
+}
+
+/// Add [ID_KEY] to bim for [MatchMode::IdPos], where ID is synthesized from
+/// position and alleles if it is missing or structured. It is computed for
+/// the whole bim, so should be added once before matching.
+pub fn with_bim_id_key(mut bim: DataFrame) -> Result<DataFrame> {
+    if bim.column(ID_KEY).is_ok() {
+        return Ok(bim);
+    }
+    let keys: Vec<Option<String>> = {
+        let ids = bim.column(ID)?.utf8()?;
+        let chrom = bim.column(CHR)?.utf8()?;
+        let pos = bim.column(POS)?.i32()?;
+        let reference = bim.column(REF)?.utf8()?;
+        let alt = bim.column(ALT)?.utf8()?;
+        ids.into_iter()
+            .zip(chrom)
+            .zip(pos)
+            .zip(reference)
+            .zip(alt)
+            .map(|((((id, chrom), pos), reference), alt)| match (chrom, pos, reference, alt) {
+                (Some(chrom), Some(pos), Some(reference), Some(alt)) => {
+                    Some(bim_id_key(id, chrom, pos, reference, alt))
+                }
+                _ => id.map(|v| v.to_owned()),
+            })
+            .collect()
+    };
+    bim.with_column(Series::new(ID_KEY, keys))?;
+    Ok(bim)
+}
+
+/// Match by ID first and then by CHR and POS for the rest of beta. ID of
+/// beta is compared in canonical form, so that `chr1:12345:G:A` matches ID
+/// `1:12345:A:G` or a bim row without ID at the position. Snp matched by ID
+/// take CHR and POS from bim, since they may be missing in beta.
+fn match_by_id_pos(bim: &DataFrame, beta: &DataFrame, has_a2: bool) -> Result<(DataFrame, usize)> {
+    let bim = with_bim_id_key(bim.clone())?;
+    let keys: Vec<Option<String>> = beta
+        .column(ID)?
+        .utf8()?
+        .into_iter()
+        .map(|v| v.map(id_key))
+        .collect();
+    let mut keyed_beta = beta.drop_many(&[CHR, POS]);
+    keyed_beta.with_column(Series::new(ID_KEY, keys))?;
+
+    // match by id
+    let joined = bim
+        .select([IDX, ID_KEY, CHR, POS, ALT, REF])?
+        .inner_join(&keyed_beta, [ID_KEY], [ID_KEY])?;
+    let (by_id, id_normalized) = classify(joined, has_a2)?;
+    let by_id = by_id
+        .lazy()
+        .filter(col(STATUS).eq(lit(NO_MATCH)).not())
+        .collect()?;
+    let matched_rows: HashSet<u32> = by_id.column(BETA_ROW)?.u32()?.into_iter().flatten().collect();
+
+    // fallback to position for the rest
+    let mask: BooleanChunked = beta
+        .column(BETA_ROW)?
+        .u32()?
+        .into_iter()
+        .map(|v| v.is_some_and(|v| !matched_rows.contains(&v)))
+        .collect();
+    let joined = bim
+        .select([IDX, CHR, POS, ALT, REF])?
+        .inner_join(&beta.filter(&mask)?, [CHR, POS], [CHR, POS])?;
+    let (by_pos, pos_normalized) = classify(joined, has_a2)?;
+    let by_pos = with_rule(by_pos, RULE_POS)?;
+
+    let by_id = with_rule(by_id.drop(ID_KEY)?, RULE_ID)?
+        .select(by_pos.get_column_names())?;
+    Ok((by_id.vstack(&by_pos)?, id_normalized + pos_normalized))
+}
+
+/// count number of rows with the status
+fn count_status(matched_beta: &DataFrame, status: &str) -> Result<usize> {
+    let count = matched_beta
+        .column(STATUS)?
+        .utf8()?
+        .into_iter()
+        .filter(|v| *v == Some(status))
+        .count();
+    Ok(count)
 }
 
 /// match snp function. It do the following
 /// 1. Filter Beta by column needed and not null
-/// 2. Join Beta and Bim
-/// 3. Check swap and keep uniq CHR POS A1 paired
+/// 2. Join Beta and Bim by [MatchMode]
+/// 3. Check swap, strand flip and ambiguous snp, match the rest again after
+///    allele normalization, and keep uniq CHR POS A1 paired
 /// 4. Get match status
 /// 5. Convert to Weight object for prediction
 pub fn match_snp(
     meta_arg: &MetaArg,
-    my_cols: &Vec<String>,
+    my_cols: &[String],
     bim: &DataFrame,
     beta: DataFrame,
 ) -> Result<(Weights, MatchStatus, DataFrame)> {
+    match_rows(meta_arg, my_cols, bim, beta)?.into_weights(meta_arg, bim)
+}
+
+/// Rows of beta at each step of [match_snp], all with [BETA_ROW] which is
+/// the row number in beta before filtering. The match report is built from
+/// it by [report::match_report], so that beta is joined only once.
+pub struct MatchedRows {
+    /// beta before filtering
+    input: DataFrame,
+    /// beta after filtering and binning or expanding by Q ranges
+    beta: DataFrame,
+    /// joined pairs of beta and bim snp with status, including [NO_MATCH]
+    classified: DataFrame,
+    /// matched snp without duplicates, after [AmbiguousPolicy] is applied
+    matched: DataFrame,
+    /// status without the number of matched snp and scores
+    match_status: MatchStatus,
+}
+
+/// step 1-3 of [match_snp]
+pub fn match_rows(
+    meta_arg: &MetaArg,
+    my_cols: &[String],
+    bim: &DataFrame,
+    beta: DataFrame,
+) -> Result<MatchedRows> {
     // filter beta
     // https://stackoverflow.com/questions/76437931/rust-polars-selecting-columns-after-applying-filter-on-rows-of-a-dataframe
-    let beta = handle_beta(beta, &meta_arg.q_range_enum, my_cols)?;
+    let nullable_cols = match meta_arg.match_mode {
+        MatchMode::IdPos => [ID, CHR, POS].to_vec(),
+        _ => vec![],
+    };
+    let mut row_cols = my_cols.to_vec();
+    row_cols.push(BETA_ROW.to_owned());
+    let input = beta;
+    let beta = handle_beta(
+        input.clone().with_row_count(BETA_ROW, None)?,
+        &meta_arg.q_range_enum,
+        &row_cols,
+        &nullable_cols,
+    )?;
     // match by id or chr pos
-    let mut matched_beta: DataFrame;
-    let identifier_cols: Vec<String>;
-    if !meta_arg.match_id_flag {
-        matched_beta =
-            bim.select([IDX, CHR, POS, ALT, REF])?
+    let has_a2 = beta.column(A2).is_ok();
+    let (classified, normalized_snp) = match meta_arg.match_mode {
+        MatchMode::Pos => {
+            let joined = bim
+                .select([IDX, CHR, POS, ALT, REF])?
                 .inner_join(&beta, [CHR, POS], [CHR, POS])?;
-        identifier_cols = vec![CHR.to_string(), POS.to_string(), A1.to_string()];
-    } else {
-        matched_beta = bim
-            .select([IDX, ID, ALT, REF])?
-            .inner_join(&beta, [ID], [ID])?;
-        identifier_cols = vec![ID.to_string(), A1.to_string()];
-    }
+            let (classified, normalized_snp) = classify(joined, has_a2)?;
+            (with_rule(classified, RULE_POS)?, normalized_snp)
+        }
+        MatchMode::Id => {
+            let joined = bim
+                .select([IDX, ID, ALT, REF])?
+                .inner_join(&beta, [ID], [ID])?;
+            let (classified, normalized_snp) = classify(joined, has_a2)?;
+            (with_rule(classified, RULE_ID)?, normalized_snp)
+        }
+        MatchMode::IdPos => match_by_id_pos(bim, &beta, has_a2)?,
+    };
 
-    // filter weights
-    matched_beta = matched_beta
+    // filter weights. Duplicated variants in weights have been resolved by
+    // DupPolicy when reading, here multiple bim snp matching a weight row
+    // are reduced to the first one. Rows of weights are kept apart by
+    // BETA_ROW, as rows sharing A1 may differ in A2, e.g. multi-allelic snp
+    let mismatch_snp = count_status(&classified, NO_MATCH)?;
+    let matched = classified
+        .clone()
         .lazy()
-        .with_column(
-            when(col(A1).eq(col(ALT)))
-                .then(lit(GOOD))
-                .when(col(A1).eq(col(REF)))
-                .then(lit(SWAP))
-                .otherwise(lit(NO_MATCH))
-                .alias(STATUS),
-        )
         .filter(col(STATUS).eq(lit(NO_MATCH)).not())
-        .unique(Some(identifier_cols), UniqueKeepStrategy::First)
+        .unique_stable(Some(vec![BETA_ROW.to_owned()]), UniqueKeepStrategy::First)
         .collect()?;
+    // count status before ambiguous snp are relabeled or dropped
+    let match_status = MatchStatus {
+        good_snp: count_status(&matched, GOOD)?,
+        swap_snp: count_status(&matched, SWAP)?,
+        flip_snp: count_status(&matched, FLIP)?,
+        flip_swap_snp: count_status(&matched, FLIP_SWAP)?,
+        ambiguous_snp: count_status(&matched, AMBIGUOUS)?,
+        mismatch_snp,
+        normalized_snp,
+        ..MatchStatus::new(bim.shape().0, beta.shape().0, 0)
+    };
+    let matched = apply_ambiguous_policy(matched, meta_arg.ambiguous_policy)?;
+    Ok(MatchedRows {
+        input,
+        beta,
+        classified,
+        matched,
+        match_status,
+    })
+}
+
+impl MatchedRows {
+    /// step 4-5 of [match_snp]
+    pub fn into_weights(
+        self,
+        meta_arg: &MetaArg,
+        bim: &DataFrame,
+    ) -> Result<(Weights, MatchStatus, DataFrame)> {
+        let MatchedRows {
+            beta,
+            matched: matched_beta,
+            mut match_status,
+            ..
+        } = self;
 
-    // record match status
-    if matched_beta.shape().0 == 0 {
-        return Err(anyhow!("No snp matched between models and bfile!"));
+        // record match status
+        match_status.match_snp = matched_beta.shape().0;
+        let (score_ranges, q_bins) = match &meta_arg.q_range_enum {
+            QrangeOrScorenames::QRange(v) => (v.score_ranges(), v.bins()),
+            QrangeOrScorenames::ScoreNameRaws(v) => (v.iter().map(|v| (v, None)).collect(), None),
+        };
+        let score_names = meta_arg.get_score_names(false);
+        for (score_name, (raw_name, range)) in score_names.iter().zip(score_ranges) {
+            // score of a Q range is counted on snp of the raw score in range,
+            // since weights may be binned instead of expanded
+            let score_status = match range {
+                Some(range) => {
+                    let in_range =
+                        |frame: &DataFrame| frame.clone().lazy().filter(range.expr()).collect();
+                    ScoreStatus::new(&in_range(&beta)?, &in_range(&matched_beta)?, raw_name)?
+                }
+                None => ScoreStatus::new(&beta, &matched_beta, raw_name)?,
+            };
+            match_status.scores.insert(score_name.to_owned(), score_status);
+        }
+        if matched_beta.shape().0 == 0 {
+            return Err(NoSnpMatched(match_status).into());
+        }
+        // snp not in a model has null score, which is zero weight
+        let matched_beta = matched_beta
+            .drop(BETA_ROW)?
+            .lazy()
+            .with_columns([cols(meta_arg.get_score_names(true)).fill_null(lit(0 as Float))])
+            .collect()?;
+        // create weight object
+        let has_bins = q_bins.is_some();
+        let mut weights_obj = Weights::new(
+            matched_beta.clone(),
+            meta_arg.get_score_names(false).to_vec(),
+            meta_arg.missing_strategy,
+            meta_arg.genotype_scale,
+            q_bins,
+        )?;
+        if meta_arg.score_per_chrom {
+            weights_obj = weights_obj.with_chroms(&matched_beta, bim)?;
+        }
+        let matched_beta = match has_bins {
+            true => matched_beta.drop(Q_BIN)?,
+            false => matched_beta,
+        };
+        Ok((weights_obj, match_status, matched_beta))
     }
-    let match_status = MatchStatus {
-        bfile_snp: bim.shape().0,
-        model_snp: beta.shape().0,
-        match_snp: matched_beta.shape().0,
+}
+
+#[cfg(test)]
+mod tests {
+    use betareader::{A1, A2, CHR, ID, POS};
+    use genoreader::meta::{ALT, IDX, REF};
+    use polars::prelude::{DataFrame, IntoLazy, NamedFrom, Series};
+
+    use super::{
+        allele::with_complement, get_status_expr, match_by_id_pos, rematch_normalized, BETA_ROW,
+        RULE_ID, RULE_POS,
     };
-    // create weight object
-    let weights_obj = Weights::new(
-        matched_beta.clone(),
-        meta_arg.get_score_names(false).to_vec(),
-        meta_arg.missing_strategy,
-    )?;
-    Ok((weights_obj, match_status, matched_beta))
+    use crate::meta::{MATCH_RULE, STATUS};
+
+    fn utf8_values(frame: &DataFrame, name: &str) -> Vec<Option<String>> {
+        frame
+            .column(name)
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .map(|v| v.map(|v| v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_get_status_expr() {
+        let frame = DataFrame::new(vec![
+            Series::new(A1, ["A", "C", "T", "G", "A", "A", "A"]),
+            Series::new(
+                A2,
+                [Some("C"), Some("A"), Some("G"), Some("T"), Some("T"), Some("G"), None],
+            ),
+            Series::new(ALT, ["A", "A", "A", "A", "A", "A", "A"]),
+            Series::new(REF, ["C", "C", "C", "C", "T", "T", "T"]),
+        ])
+        .unwrap();
+        let frame = with_complement(frame, &[A1, A2, REF]).unwrap();
+        let status: Vec<Option<String>> = frame
+            .lazy()
+            .select([get_status_expr(true)])
+            .collect()
+            .unwrap()
+            .column(STATUS)
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .map(|v| v.map(|v| v.to_owned()))
+            .collect();
+        let expected = [
+            "Good",
+            "Swap",
+            "Flip",
+            "FlipSwap",
+            "Ambiguous",
+            // palindromic in bim, but A2 is not the other allele
+            "NoMatch",
+            // A2 is missing
+            "Ambiguous",
+        ];
+        assert_eq!(status, expected.map(|v| Some(v.to_owned())).to_vec());
+    }
+
+    #[test]
+    fn test_rematch_normalized() {
+        let frame = DataFrame::new(vec![
+            Series::new(A1, ["A", "I", "G", "C"]),
+            Series::new(ALT, ["A", "AT", "a", "A"]),
+            Series::new(REF, ["C", "A", "g", "T"]),
+            Series::new(STATUS, ["Good", "NoMatch", "NoMatch", "NoMatch"]),
+        ])
+        .unwrap();
+        let (frame, normalized_snp) = rematch_normalized(frame, &[A1, REF], false).unwrap();
+        // insertion code matched to padded ALT, and lower case bim alleles
+        assert_eq!(normalized_snp, 2);
+        let expected = ["Good", "Good", "Swap", "NoMatch"];
+        assert_eq!(
+            utf8_values(&frame, STATUS),
+            expected.map(|v| Some(v.to_owned())).to_vec()
+        );
+        // reported alleles are kept
+        let expected = ["A", "AT", "a", "A"];
+        assert_eq!(
+            utf8_values(&frame, ALT),
+            expected.map(|v| Some(v.to_owned())).to_vec()
+        );
+        assert_eq!(utf8_values(&frame, A1)[1], Some("I".to_owned()));
+    }
+
+    #[test]
+    fn test_match_by_id_pos() {
+        let bim = DataFrame::new(vec![
+            Series::new(CHR, ["1", "1", "2"]),
+            Series::new(ID, ["rs1", ".", "rs3"]),
+            Series::new(POS, [100, 200, 300]),
+            Series::new(REF, ["C", "G", "A"]),
+            Series::new(ALT, ["A", "T", "G"]),
+        ])
+        .unwrap()
+        .with_row_count(IDX, None)
+        .unwrap();
+        let beta = DataFrame::new(vec![
+            Series::new(ID, ["rs1", "chr1:200:T:G", "rs999", "rs998"]),
+            Series::new(CHR, [None, None, Some("2"), Some("3")]),
+            Series::new(POS, [None, None, Some(300), Some(400)]),
+            Series::new(A1, ["A", "T", "G", "A"]),
+        ])
+        .unwrap()
+        .with_row_count(BETA_ROW, None)
+        .unwrap();
+        let (matched, normalized_snp) = match_by_id_pos(&bim, &beta, false).unwrap();
+        assert_eq!(normalized_snp, 0);
+        // rsID, structured ID matched to bim without ID, and position fallback
+        assert_eq!(
+            utf8_values(&matched, ID),
+            vec![
+                Some("rs1".to_owned()),
+                Some("chr1:200:T:G".to_owned()),
+                Some("rs999".to_owned())
+            ]
+        );
+        let expected = [RULE_ID, RULE_ID, RULE_POS];
+        assert_eq!(
+            utf8_values(&matched, MATCH_RULE),
+            expected.map(|v| Some(v.to_owned())).to_vec()
+        );
+        // position is taken from bim for snp matched by ID
+        let pos: Vec<Option<i32>> = matched
+            .column(POS)
+            .unwrap()
+            .i32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(pos, vec![Some(100), Some(200), Some(300)]);
+        let expected = ["Good", "Good", "Good"];
+        assert_eq!(
+            utf8_values(&matched, STATUS),
+            expected.map(|v| Some(v.to_owned())).to_vec()
+        );
+    }
 }
@@ -0,0 +1,65 @@
+//! Allele helpers for strand flip and palindromic (ambiguous) snp. A strand
+//! flip replace each base by its complement (A <-> T, C <-> G), so that A1
+//! reported on the opposite strand can still be matched to bim alleles.
+use anyhow::Result;
+use genoreader::allele::complement;
+use polars::prelude::{DataFrame, IntoSeries, Utf8Chunked};
+
+/// Add complemented column `{col_name}_FLIP` for each column in `col_names`
+pub fn with_complement(mut frame: DataFrame, col_names: &[&str]) -> Result<DataFrame> {
+    for col_name in col_names {
+        let flipped: Utf8Chunked = frame
+            .column(col_name)?
+            .utf8()?
+            .into_iter()
+            .map(|v| v.map(complement))
+            .collect();
+        let mut flipped = flipped.into_series();
+        flipped.rename(&flip_name(col_name));
+        frame.with_column(flipped)?;
+    }
+    Ok(frame)
+}
+
+/// name of the complemented column
+pub fn flip_name(col_name: &str) -> String {
+    format!("{col_name}_FLIP")
+}
+
+/// Decide whether an ambiguous snp should be swapped by frequency. `freq` is
+/// the A1 frequency from beta, and `dosage` is the observed mean dosage of ALT
+/// in bfile. If ALT frequency is closer to `1 - freq` than to `freq`, A1 is
+/// regarded as REF and the genotype is swapped.
+pub fn resolve_ambiguous_swap(freq: f32, dosage: f32) -> bool {
+    let alt_freq = dosage / 2.;
+    (alt_freq - freq).abs() > (alt_freq - (1. - freq)).abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use super::{flip_name, resolve_ambiguous_swap, with_complement};
+
+    #[test]
+    fn test_with_complement() {
+        let frame = DataFrame::new(vec![Series::new("A1", [Some("A"), None, Some("CT")])]).unwrap();
+        let frame = with_complement(frame, &["A1"]).unwrap();
+        let flipped: Vec<Option<&str>> = frame
+            .column(&flip_name("A1"))
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(flipped, vec![Some("T"), None, Some("GA")]);
+    }
+
+    #[test]
+    fn test_resolve_ambiguous_swap() {
+        // A1 freq 0.2, ALT freq 0.2 in bfile, A1 is ALT
+        assert!(!resolve_ambiguous_swap(0.2, 0.4));
+        // ALT freq 0.8, A1 is REF
+        assert!(resolve_ambiguous_swap(0.2, 1.6));
+    }
+}
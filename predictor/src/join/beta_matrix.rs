@@ -0,0 +1,290 @@
+//! Weight matrix of snp x score. Weights binned by Q ranges or merged from
+//! many models are mostly zero, so the matrix is stored in compressed sparse
+//! column (CSC) format when its density is low, and scoring then scales with
+//! the number of non-zero weights instead of the matrix size.
+use betareader::Float;
+use ndarray::{Array2, ArrayView2, Axis};
+
+/// Matrix with density lower than this is stored as sparse. The sparse
+/// kernel costs about 4x the dense gemm per multiply-add; scoring 2000 ind
+/// on 5000 snp x 20 scores took 34ms/81ms (sparse/dense) at 1% density,
+/// 81ms/87ms at 5% and 177ms/88ms at 10%.
+const SPARSE_DENSITY: f32 = 0.05;
+
+/// Number of ind of genotype converted at a time for double precision
+#[cfg(feature = "f64")]
+const IND_BLOCK: usize = 1024;
+
+/// Compressed sparse column matrix
+#[derive(Clone, Debug)]
+pub struct CscMatrix {
+    n_rows: usize,
+    n_cols: usize,
+    /// start of each column in `row_idx` and `values`, with length n_cols + 1
+    col_ptr: Vec<usize>,
+    row_idx: Vec<usize>,
+    values: Vec<Float>,
+}
+
+impl CscMatrix {
+    /// from (row, value) of non-zero values of each column, in order of rows
+    pub fn from_columns(n_rows: usize, columns: Vec<Vec<(usize, Float)>>) -> CscMatrix {
+        let n_cols = columns.len();
+        let mut col_ptr = Vec::with_capacity(n_cols + 1);
+        let mut row_idx = vec![];
+        let mut values = vec![];
+        col_ptr.push(0);
+        for column in columns {
+            for (row, value) in column {
+                row_idx.push(row);
+                values.push(value);
+            }
+            col_ptr.push(values.len());
+        }
+        CscMatrix {
+            n_rows,
+            n_cols,
+            col_ptr,
+            row_idx,
+            values,
+        }
+    }
+
+    /// number of non-zero values
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// `gt.dot(self)`, where gt is a dense ind x snp matrix. Each score is the
+    /// sum of genotypes of its non-zero snp scaled by weight, gathered from
+    /// the row of each ind, which is contiguous in genotype of standard
+    /// layout. Genotypes are converted into [Float] one by one.
+    pub fn dense_dot(&self, gt: &Array2<f32>) -> Array2<Float> {
+        assert_eq!(
+            gt.ncols(),
+            self.n_rows,
+            "genotype and weights are not aligned"
+        );
+        let mut score = Array2::<Float>::zeros((gt.nrows(), self.n_cols));
+        if !gt.is_standard_layout() && gt.t().is_standard_layout() {
+            // columns are contiguous in genotype of Fortran layout
+            for (cc, mut score_col) in score.axis_iter_mut(Axis(1)).enumerate() {
+                for k in self.col_ptr[cc]..self.col_ptr[cc + 1] {
+                    let weight = self.values[k];
+                    score_col.zip_mut_with(&gt.column(self.row_idx[k]), |v, x| {
+                        *v += Float::from(*x) * weight
+                    });
+                }
+            }
+            return score;
+        }
+        for (gt_row, mut score_row) in gt.outer_iter().zip(score.outer_iter_mut()) {
+            for (cc, value) in score_row.iter_mut().enumerate() {
+                let (start, end) = (self.col_ptr[cc], self.col_ptr[cc + 1]);
+                *value = self.row_idx[start..end]
+                    .iter()
+                    .zip(&self.values[start..end])
+                    .map(|(row, weight)| Float::from(gt_row[*row]) * weight)
+                    .sum();
+            }
+        }
+        score
+    }
+
+    /// [CscMatrix::dense_dot] on some rows (snp), as if weights of other rows
+    /// are zero
+    pub fn dense_dot_rows(&self, gt: &Array2<f32>, rows: &[usize]) -> Array2<Float> {
+        let mut in_rows = vec![false; self.n_rows];
+        for row in rows {
+            in_rows[*row] = true;
+        }
+        let columns = (0..self.n_cols)
+            .map(|cc| {
+                (self.col_ptr[cc]..self.col_ptr[cc + 1])
+                    .filter(|k| in_rows[self.row_idx[*k]])
+                    .map(|k| (self.row_idx[k], self.values[k]))
+                    .collect()
+            })
+            .collect();
+        CscMatrix::from_columns(self.n_rows, columns).dense_dot(gt)
+    }
+}
+
+/// Weight matrix, dense or sparse
+#[derive(Clone, Debug)]
+pub enum BetaMatrix {
+    Dense(Array2<Float>),
+    Sparse(CscMatrix),
+}
+
+impl BetaMatrix {
+    /// Pick representation by density of non-zero weights, from (row, value)
+    /// of non-zero weights of each column, in order of rows. The dense matrix
+    /// is only built if it is dense enough.
+    pub fn from_columns(n_rows: usize, columns: Vec<Vec<(usize, Float)>>) -> BetaMatrix {
+        let nnz: usize = columns.iter().map(|v| v.len()).sum();
+        let density = nnz as f32 / (n_rows * columns.len()).max(1) as f32;
+        if density < SPARSE_DENSITY {
+            return BetaMatrix::Sparse(CscMatrix::from_columns(n_rows, columns));
+        }
+        let mut dense = Array2::<Float>::zeros((n_rows, columns.len()));
+        for (cc, column) in columns.into_iter().enumerate() {
+            for (row, value) in column {
+                dense[[row, cc]] = value;
+            }
+        }
+        BetaMatrix::Dense(dense)
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self, BetaMatrix::Sparse(_))
+    }
+
+    /// number of non-zero weights
+    pub fn nnz(&self) -> usize {
+        match self {
+            BetaMatrix::Dense(v) => v.iter().filter(|v| **v != 0.).count(),
+            BetaMatrix::Sparse(v) => v.nnz(),
+        }
+    }
+
+    /// rows (snp) with non-zero weight in a column (score)
+    pub fn nonzero_rows(&self, col: usize) -> Vec<usize> {
+        match self {
+            BetaMatrix::Dense(v) => v
+                .column(col)
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| **v != 0.)
+                .map(|(row, _)| row)
+                .collect(),
+            BetaMatrix::Sparse(v) => v.row_idx[v.col_ptr[col]..v.col_ptr[col + 1]].to_vec(),
+        }
+    }
+
+    /// score of each ind, `gt.dot(beta)`
+    pub fn score(&self, gt: &Array2<f32>) -> Array2<Float> {
+        match self {
+            BetaMatrix::Dense(v) => dense_score(gt.view(), v.view()),
+            BetaMatrix::Sparse(v) => v.dense_dot(gt),
+        }
+    }
+
+    /// score of each ind on some rows (snp) only
+    pub fn score_rows(&self, gt: &Array2<f32>, rows: &[usize]) -> Array2<Float> {
+        match self {
+            BetaMatrix::Dense(v) => dense_score(
+                gt.select(Axis(1), rows).view(),
+                v.select(Axis(0), rows).view(),
+            ),
+            BetaMatrix::Sparse(v) => v.dense_dot_rows(gt, rows),
+        }
+    }
+}
+
+/// `gt.dot(beta)` of genotype decoded in f32. For double precision, blocks
+/// of ind are converted in turn rather than the whole genotype matrix.
+fn dense_score(gt: ArrayView2<f32>, beta: ArrayView2<Float>) -> Array2<Float> {
+    #[cfg(not(feature = "f64"))]
+    let score = gt.dot(&beta);
+    #[cfg(feature = "f64")]
+    let score = {
+        let mut score = Array2::<Float>::zeros((gt.nrows(), beta.ncols()));
+        for (gt_block, mut score_block) in gt
+            .axis_chunks_iter(Axis(0), IND_BLOCK)
+            .zip(score.axis_chunks_iter_mut(Axis(0), IND_BLOCK))
+        {
+            score_block.assign(&gt_block.mapv(Float::from).dot(&beta));
+        }
+        score
+    };
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use betareader::Float;
+    use ndarray::{array, Array2, Axis, ShapeBuilder};
+
+    use super::{BetaMatrix, CscMatrix};
+
+    fn columns() -> Vec<Vec<(usize, Float)>> {
+        vec![
+            vec![(0, 1.0), (3, -2.0)],
+            vec![],
+            vec![(1, 0.5), (2, 1.5), (3, 1.0)],
+        ]
+    }
+
+    fn weights() -> Array2<Float> {
+        array![
+            [1.0, 0.0, 0.0],
+            [0.0, 0.0, 0.5],
+            [0.0, 0.0, 1.5],
+            [-2.0, 0.0, 1.0]
+        ]
+    }
+
+    fn genotype() -> Array2<f32> {
+        array![
+            [0.0, 1.0, 2.0, 1.0],
+            [2.0, 2.0, 0.0, 1.0],
+            [1.0, 0.0, 1.0, 2.0]
+        ]
+    }
+
+    #[test]
+    fn test_dense_dot() {
+        let sparse = CscMatrix::from_columns(4, columns());
+        assert_eq!(sparse.nnz(), 5);
+        let gt = genotype();
+        let expected = gt.mapv(Float::from).dot(&weights());
+        assert_eq!(sparse.dense_dot(&gt), expected);
+        // genotype of Fortran layout
+        let mut gt_f = Array2::<f32>::zeros((3, 4).f());
+        gt_f.assign(&gt);
+        assert_eq!(sparse.dense_dot(&gt_f), expected);
+    }
+
+    #[test]
+    fn test_dense_dot_rows() {
+        let sparse = CscMatrix::from_columns(4, columns());
+        let gt = genotype();
+        let rows = [1, 3];
+        let expected = gt
+            .mapv(Float::from)
+            .select(Axis(1), &rows)
+            .dot(&weights().select(Axis(0), &rows));
+        assert_eq!(sparse.dense_dot_rows(&gt, &rows), expected);
+    }
+
+    #[test]
+    fn test_from_columns() {
+        // 5 of 12 non-zero is dense, 5 of 300 is sparse
+        let dense = BetaMatrix::from_columns(4, columns());
+        assert!(!dense.is_sparse());
+        assert_eq!(dense.nnz(), 5);
+        let sparse = BetaMatrix::from_columns(100, columns());
+        assert!(sparse.is_sparse());
+        let expected = genotype().mapv(Float::from).dot(&weights());
+        assert_eq!(dense.score(&genotype()), expected);
+        assert_eq!(
+            dense.score_rows(&genotype(), &[0]),
+            array![[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [1.0, 0.0, 0.0]]
+        );
+    }
+
+    #[cfg(feature = "f64")]
+    #[test]
+    fn test_score_f64() {
+        // lost in f32, where the precision of 1 is about 1e-7
+        let columns = vec![vec![(0, 1.0), (1, 1e-9)]; 20];
+        let dense = BetaMatrix::from_columns(2, columns);
+        assert!(!dense.is_sparse());
+        // more ind than a block
+        let gt = Array2::<f32>::ones((2500, 2));
+        let score = dense.score(&gt);
+        assert_eq!(score.dim(), (2500, 20));
+        assert!(score.iter().all(|v| *v == 1.0 + 1e-9));
+    }
+}
@@ -4,81 +4,333 @@ use std::{
 };
 
 use anyhow::{anyhow, Result};
+use betareader::{Float, PVALUE};
 use polars::{
-    lazy::dsl::{all_horizontal, col, lit, when},
+    lazy::dsl::{all_horizontal, any_horizontal, col, cols, lit, when, Expr},
     prelude::{DataFrame, IntoLazy},
 };
 
+use super::qbins::QBins;
 use crate::meta::QrangeOrScorenames;
 
-/// preprocess beta by select import cols, filter NaN and expand score column
-/// according to q-ranges.
+/// preprocess beta by select import cols, filter NaN and bin snp by Q ranges,
+/// or expand score column according to Q ranges on different columns, see
+/// [QBins]. Score may be null for snp not in the model when
+/// multiple models are merged, so only snp with all scores being null are
+/// removed. Columns in `nullable_cols` may also be null, such as ID or
+/// position when matching by ID with fallback to position, and so do columns
+/// of Q ranges other than P, which are out of range if null.
 pub fn handle_beta(
     mut beta: DataFrame,
     q_range: &QrangeOrScorenames,
     my_cols: &Vec<String>,
+    nullable_cols: &[&str],
 ) -> Result<DataFrame> {
+    let (score_names, range_cols) = match q_range {
+        QrangeOrScorenames::QRange(v) => (v.score_names_raw, v.columns()),
+        QrangeOrScorenames::ScoreNameRaws(v) => (*v, vec![]),
+    };
+    let other_cols: Vec<&str> = my_cols
+        .iter()
+        .filter(|v| !score_names.contains(v) && !range_cols.contains(v))
+        .map(|v| v.as_str())
+        .filter(|v| !nullable_cols.contains(v))
+        .collect();
     // filter beta
     beta = beta
         .select(my_cols)?
         .lazy()
-        .filter(all_horizontal([col("*").is_not_null()]))
+        .filter(
+            all_horizontal([cols(other_cols).is_not_null()])
+                .and(any_horizontal([cols(score_names).is_not_null()])),
+        )
         .collect()?;
 
     // get new beta from q range and get new score_names
     beta = match q_range {
-        QrangeOrScorenames::QRange(v) => v.expand_beta(beta)?,
+        QrangeOrScorenames::QRange(v) => match v.bins() {
+            Some(q_bins) => q_bins.with_bin(beta)?,
+            None => v.expand_beta(beta)?,
+        },
         QrangeOrScorenames::ScoreNameRaws(_) => beta,
     };
     Ok(beta)
 }
 
+/// Range of a numeric column of weight file, on which the score of a Q range
+/// is calculated. Ranges on `-log10(P)` are converted into ranges on P.
+#[derive(Clone, Debug)]
+pub struct Range {
+    pub name: String,
+    /// [PVALUE] or another numeric column of weight file
+    pub column: String,
+    pub from: f32,
+    pub to: f32,
+    pub from_inclusive: bool,
+    pub to_inclusive: bool,
+}
+
+impl Range {
+    /// `[from, to)` on P, as in the three column q range file
+    fn half_open(name: &str, from: f32, to: f32) -> Range {
+        Range {
+            name: name.to_owned(),
+            column: PVALUE.to_owned(),
+            from,
+            to,
+            from_inclusive: true,
+            to_inclusive: false,
+        }
+    }
+
+    /// Parse range such as `[0, 5e-8]`, `-log10(P) (7.3, inf)` or
+    /// `INFO [0.8, 1]`. The column is P if omitted, and `pvalue_col` is also
+    /// regarded as P.
+    fn parse(name: &str, spec: &str, pvalue_col: &str) -> Result<Range> {
+        let bad_range = || anyhow!("Bad range of {} in q-ranges file: {:?}", name, spec);
+        let spec = spec.trim();
+        let start = spec.rfind(['[', '(']).ok_or_else(bad_range)?;
+        let (column, interval) = spec.split_at(start);
+        let to_inclusive = match interval.chars().last() {
+            Some(']') => true,
+            Some(')') => false,
+            _ => return Err(bad_range()),
+        };
+        let (from, to) = interval[1..interval.len() - 1]
+            .split_once(',')
+            .ok_or_else(bad_range)?;
+        let parse_bound = |v: &str| -> Result<f32> {
+            match v.trim() {
+                "inf" | "+inf" => Ok(f32::INFINITY),
+                "-inf" => Ok(f32::NEG_INFINITY),
+                v => v.parse().map_err(|_| bad_range()),
+            }
+        };
+        let mut range = Range {
+            name: name.to_owned(),
+            column: column.trim().to_owned(),
+            from: parse_bound(from)?,
+            to: parse_bound(to)?,
+            from_inclusive: interval.starts_with('['),
+            to_inclusive,
+        };
+        // -log10(P) in [from, to] is P in [10^-to, 10^-from]
+        if let Some(v) = range
+            .column
+            .strip_prefix("-log10(")
+            .and_then(|v| v.strip_suffix(')'))
+        {
+            range = Range {
+                column: v.trim().to_owned(),
+                from: 10_f32.powf(-range.to),
+                to: 10_f32.powf(-range.from),
+                from_inclusive: range.to_inclusive,
+                to_inclusive: range.from_inclusive,
+                ..range
+            };
+        }
+        if range.column.is_empty() || range.column == pvalue_col {
+            range.column = PVALUE.to_owned();
+        }
+        if range.from > range.to {
+            return Err(bad_range());
+        }
+        Ok(range)
+    }
+
+    /// whether value of the column is in range
+    pub fn expr(&self) -> Expr {
+        let lower = match self.from_inclusive {
+            true => col(&self.column).gt_eq(lit(self.from)),
+            false => col(&self.column).gt(lit(self.from)),
+        };
+        let upper = match self.to_inclusive {
+            true => col(&self.column).lt_eq(lit(self.to)),
+            false => col(&self.column).lt(lit(self.to)),
+        };
+        lower.and(upper)
+    }
+}
+
+/// Parse comma separated P thresholds, such as `5e-8,1e-5,0.05`
+pub fn parse_thresholds(spec: &str) -> Result<Vec<f32>> {
+    let mut thresholds = spec
+        .split(',')
+        .map(|v| {
+            v.trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|v| *v > 0.)
+                .ok_or_else(|| anyhow!("Bad P threshold {:?} in {}", v, spec))
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    thresholds.sort_by(f32::total_cmp);
+    thresholds.dedup();
+    Ok(thresholds)
+}
+
+/// `n` log spaced P thresholds from `from` to `to`, given as `from,to,n`
+pub fn log_grid(spec: &str) -> Result<Vec<f32>> {
+    let bad_grid = || {
+        anyhow!(
+            "P grid should be FROM,TO,N with 0 < FROM < TO and N > 1, got {}",
+            spec
+        )
+    };
+    let fields: Vec<&str> = spec.split(',').map(|v| v.trim()).collect();
+    let (from, to, n) = match fields[..] {
+        [from, to, n] => (
+            from.parse::<f64>().map_err(|_| bad_grid())?,
+            to.parse::<f64>().map_err(|_| bad_grid())?,
+            n.parse::<usize>().map_err(|_| bad_grid())?,
+        ),
+        _ => return Err(bad_grid()),
+    };
+    if !(from > 0. && from < to && n > 1) {
+        return Err(bad_grid());
+    }
+    let step = (to.log10() - from.log10()) / (n - 1) as f64;
+    Ok((0..n)
+        .map(|i| 10_f64.powf(from.log10() + step * i as f64) as f32)
+        .collect())
+}
+
 #[derive(Clone, Debug)]
 pub struct QRange<'a> {
-    name: Vec<String>,
-    from: Vec<f32>,
-    to: Vec<f32>,
+    ranges: Vec<Range>,
     pub score_names: Vec<String>,
     pub score_names_raw: &'a Vec<String>,
 }
 
 /// pub fun
 impl QRange<'_> {
-    pub fn new<'b>(q_ranges_path: &'b str, score_names: &'b Vec<String>) -> Result<QRange<'b>> {
-        // create empty
-        let mut q_range: QRange = QRange::new_empty(score_names);
-        // read
+    /// Read q range file of lines `name from to` for `[from, to)` on P, or
+    /// `name range` with range parsed by [Range::parse]. Fields are
+    /// separated by tab.
+    pub fn new<'b>(
+        q_ranges_path: &'b str,
+        score_names: &'b Vec<String>,
+        pvalue_col: &str,
+    ) -> Result<QRange<'b>> {
+        let mut ranges = vec![];
         let file = File::open(q_ranges_path)?;
         for line in BufReader::new(file).lines() {
             let line = line?.replace(['\n', '\r'], "");
-            q_range.add_one_line(&line)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let range = match fields[..] {
+                [name, from, to] => Range::half_open(name, from.parse()?, to.parse()?),
+                [name, spec] => Range::parse(name, spec, pvalue_col)?,
+                _ => {
+                    return Err(anyhow!(
+                        "There should be two or three columns in q-ranges file, or the \
+                         deliminator is not tab "
+                    ))
+                }
+            };
+            ranges.push(range);
         }
-        // get score_names
-        for score_name in score_names {
-            q_range.score_names.push(score_name.to_owned());
-            for name in &q_range.name {
-                let new_name = format!("{score_name}_{name}");
-                q_range.score_names.push(new_name);
+        Ok(QRange::from_ranges(ranges, score_names))
+    }
+
+    /// Q ranges from P thresholds in ascending order, either nested
+    /// cumulative ranges `[0, t]` or disjoint bins `(t_prev, t]`. Ranges are
+    /// named by their thresholds.
+    pub fn from_thresholds<'b>(
+        thresholds: &[f32],
+        cumulative: bool,
+        score_names: &'b Vec<String>,
+    ) -> QRange<'b> {
+        let ranges = thresholds
+            .iter()
+            .enumerate()
+            .map(|(cc, to)| {
+                let from = match cc {
+                    0 => 0.,
+                    _ if cumulative => 0.,
+                    _ => thresholds[cc - 1],
+                };
+                Range {
+                    name: format!("{:e}", to),
+                    column: PVALUE.to_owned(),
+                    from,
+                    to: *to,
+                    from_inclusive: from == 0.,
+                    to_inclusive: true,
+                }
+            })
+            .collect();
+        QRange::from_ranges(ranges, score_names)
+    }
+
+    /// columns other than P used by Q ranges
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns: Vec<String> = vec![];
+        for range in &self.ranges {
+            if range.column != PVALUE && !columns.contains(&range.column) {
+                columns.push(range.column.to_owned());
             }
         }
-        Ok(q_range)
+        columns
+    }
+
+    /// whether any Q range is on P
+    pub fn uses_pvalue(&self) -> bool {
+        self.ranges.iter().any(|v| v.column == PVALUE)
+    }
+
+    /// Lowest `from` and highest `to` of all Q ranges, regarded as inclusive.
+    /// None if any range is on other columns, since snp out of the P bounds
+    /// may still be in that range.
+    pub fn bounds(&self) -> Option<(f32, f32)> {
+        if self.ranges.iter().any(|v| v.column != PVALUE) {
+            return None;
+        }
+        let from = self.ranges.iter().map(|v| v.from).reduce(f32::min)?;
+        let to = self.ranges.iter().map(|v| v.to).reduce(f32::max)?;
+        Some((from, to))
+    }
+
+    /// whether a snp is in any Q range
+    pub fn any_expr(&self) -> Expr {
+        self.ranges
+            .iter()
+            .map(|v| v.expr())
+            .reduce(|a, b| a.or(b))
+            .unwrap_or(lit(false))
+    }
+
+    /// [QBins] of Q ranges, none if they are on different columns
+    pub fn bins(&self) -> Option<QBins> {
+        QBins::new(&self.ranges, self.score_names_raw)
+    }
+
+    /// raw score and Q range of each score in `score_names`, none for the
+    /// raw score itself
+    pub fn score_ranges(&self) -> Vec<(&String, Option<&Range>)> {
+        self.score_names_raw
+            .iter()
+            .flat_map(|score_name| {
+                std::iter::once((score_name, None))
+                    .chain(self.ranges.iter().map(move |v| (score_name, Some(v))))
+            })
+            .collect()
     }
 
+    /// add a score column for each score and Q range, zero out of range
     pub fn expand_beta(&self, mut beta: DataFrame) -> Result<DataFrame> {
-        let mut name: &str;
-        let mut from: f32;
-        let mut to: f32;
         // loop by qrange
-        for i in 0..self.len() {
-            (name, from, to) = self.get(i)?;
+        for range in &self.ranges {
             // loop by score name, add new score one by one
             for score_name in self.score_names_raw {
-                let new_name = format!("{score_name}_{name}");
+                let new_name = format!("{score_name}_{}", range.name);
                 beta = beta
                     .lazy()
-                    .with_columns([when(col("P").gt_eq(lit(from)).and(col("P").lt(lit(to))))
-                        .then(col(score_name))
-                        .otherwise(lit(0.))
+                    .with_columns([when(range.expr())
+                        .then(col(score_name).fill_null(lit(0 as Float)))
+                        .otherwise(lit(0 as Float))
                         .alias(&new_name)])
                     .collect()?;
             }
@@ -89,44 +341,115 @@ impl QRange<'_> {
 
 /// private
 impl<'a> QRange<'a> {
-    fn new_empty(score_names: &Vec<String>) -> QRange {
-        QRange {
-            name: vec![],
-            from: vec![],
-            to: vec![],
+    fn from_ranges(ranges: Vec<Range>, score_names: &Vec<String>) -> QRange<'_> {
+        let mut q_range = QRange {
+            ranges,
             score_names: vec![],
             score_names_raw: score_names,
+        };
+        for score_name in score_names {
+            q_range.score_names.push(score_name.to_owned());
+            for range in &q_range.ranges {
+                q_range
+                    .score_names
+                    .push(format!("{score_name}_{}", range.name));
+            }
         }
+        q_range
     }
+}
 
-    fn add_one_line(&mut self, line: &str) -> Result<()> {
-        for (cc, i) in (0_u32..).zip(line.split('\t')) {
-            match cc {
-                0 => self.name.push(i.to_string()),
-                1 => self.from.push(i.parse::<f32>()?),
-                2 => self.to.push(i.parse::<f32>()?),
-                _ => {
-                    return Err(anyhow!(
-                        "There are more then three columns in q-ranges file, or the deliminator \
-                         is not tab "
-                    ))
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use betareader::PVALUE;
+    use polars::prelude::{DataFrame, IntoLazy, NamedFrom, Series};
+
+    use super::{log_grid, parse_thresholds, Range};
+
+    /// bounds of the range as `(column, from, to, from_inclusive, to_inclusive)`
+    fn bounds(spec: &str) -> (String, f32, f32, bool, bool) {
+        let v = Range::parse("q", spec, "Pval").unwrap();
+        (v.column, v.from, v.to, v.from_inclusive, v.to_inclusive)
+    }
+
+    #[test]
+    fn test_range_parse() {
+        assert_eq!(
+            bounds("[0, 5e-8]"),
+            (PVALUE.to_owned(), 0., 5e-8, true, true)
+        );
+        assert_eq!(
+            bounds("Pval [0, 1)"),
+            (PVALUE.to_owned(), 0., 1., true, false)
+        );
+        assert_eq!(
+            bounds("INFO (0.8, inf]"),
+            ("INFO".to_owned(), 0.8, f32::INFINITY, false, true)
+        );
+        // -log10(P) in (7.3, inf) is P in (0, 10^-7.3), with bounds swapped
+        assert_eq!(
+            bounds("-log10(P) (7.3, inf)"),
+            (PVALUE.to_owned(), 0., 10_f32.powf(-7.3), false, false)
+        );
+        assert_eq!(
+            bounds("-log10(P) [2, 3)"),
+            (PVALUE.to_owned(), 1e-3, 1e-2, false, true)
+        );
+        for spec in ["[1, 0]", "0, 1", "[0, x]", "[0 1]", "(0, 1"] {
+            assert!(Range::parse("q", spec, PVALUE).is_err(), "{}", spec);
         }
-        Ok(())
     }
 
-    fn len(&self) -> usize {
-        self.name.len()
+    #[test]
+    fn test_range_expr() {
+        let beta = DataFrame::new(vec![
+            Series::new(PVALUE, [Some(0.1_f32), Some(0.3), Some(0.5), None]),
+            Series::new("INFO", [0.9_f32, 0.8, 0.7, 1.]),
+        ])
+        .unwrap();
+        let in_range = |spec: &str| -> Vec<Option<bool>> {
+            let range = Range::parse("q", spec, PVALUE).unwrap();
+            beta.clone()
+                .lazy()
+                .select([range.expr()])
+                .collect()
+                .unwrap()
+                .get_columns()[0]
+                .bool()
+                .unwrap()
+                .into_iter()
+                .collect()
+        };
+        assert_eq!(
+            in_range("(0.1, 0.5]"),
+            vec![Some(false), Some(true), Some(true), None]
+        );
+        assert_eq!(
+            in_range("INFO [0.8, 1)"),
+            vec![Some(true), Some(true), Some(false), Some(false)]
+        );
     }
 
-    fn get(&self, idx: usize) -> Result<(&str, f32, f32)> {
-        let ll = self.len();
-        if idx >= ll {
-            return Err(anyhow!(
-                "Get {idx} qrange object out of index, which has {ll} items"
-            ));
+    #[test]
+    fn test_parse_thresholds() {
+        assert_eq!(
+            parse_thresholds("0.05, 5e-8,1e-5,0.05").unwrap(),
+            vec![5e-8, 1e-5, 0.05]
+        );
+        assert!(parse_thresholds("0,0.05").is_err());
+        assert!(parse_thresholds("0.05,x").is_err());
+    }
+
+    #[test]
+    fn test_log_grid() {
+        let grid = log_grid("1e-8,1,9").unwrap();
+        assert_eq!(grid.len(), 9);
+        for (v, expected) in grid.iter().zip([1e-8_f32, 1e-7, 1e-6, 1e-5, 1e-4]) {
+            assert!((v / expected - 1.).abs() < 1e-5, "{} != {}", v, expected);
+        }
+        assert!((grid[8] - 1.).abs() < 1e-6);
+        for spec in ["1,1e-8,3", "1e-8,1,1", "0,1,3", "1e-8,1", "a,1,3"] {
+            assert!(log_grid(spec).is_err(), "{}", spec);
         }
-        Ok((&self.name[idx], self.from[idx], self.to[idx]))
     }
 }
@@ -0,0 +1,197 @@
+//! LD clumping for clumping and thresholding (C+T). Weight rows are matched
+//! to the LD bfile, either the target bfile or a reference panel, and index
+//! snp are picked greedily in order of P. Snp within the window of an index
+//! snp and in LD with it are clumped into it and removed, so that only index
+//! snp are left when Q ranges are applied.
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Result};
+use betareader::{A1, CHR, ID, POS, PVALUE};
+use genoreader::{ld::LdReader, meta::IDX, BfileSet};
+use log::{debug, info};
+use polars::prelude::{BooleanChunked, DataFrame, NamedFrom, Series, TakeRandom, TakeRandomUtf8};
+
+use super::{match_rows, with_bim_id_key, BETA_ROW};
+use crate::meta::{MatchMode, MetaArg};
+
+/// column of the number of snp clumped into each index snp
+pub const N_CLUMPED: &str = "N_CLUMPED";
+
+#[derive(Clone, Debug)]
+pub struct ClumpArg {
+    /// bfile prefix to compute LD from
+    pub bfile_path: String,
+    /// snp with r² larger than it to an index snp are clumped
+    pub r2: f64,
+    /// half window size in kb around an index snp
+    pub kb: u32,
+    /// only snp with P not larger than it can be index snp
+    pub pvalue: f32,
+}
+
+/// matched weight row to be clumped
+struct Candidate {
+    /// row number in beta
+    row: u32,
+    /// IDX of snp in bim of the LD bfile
+    idx: isize,
+    pos: i64,
+    pvalue: f32,
+    /// number of other rows matched to the same snp, which go with this row
+    n_dup: u32,
+}
+
+/// Clump beta by LD in the bfile of [ClumpArg]. Rows not matched in the LD
+/// bfile or with P above [ClumpArg::pvalue] are removed. Return beta of index
+/// snp, and the index snp with [N_CLUMPED].
+pub fn clump(
+    meta_arg: &MetaArg,
+    my_cols: &[String],
+    clump_arg: &ClumpArg,
+    beta: DataFrame,
+) -> Result<(DataFrame, DataFrame)> {
+    let bfile_set = BfileSet::new(&clump_arg.bfile_path)?;
+    let bim = match meta_arg.match_mode {
+        MatchMode::IdPos => with_bim_id_key(bfile_set.bim.clone())?,
+        _ => bfile_set.bim.clone(),
+    };
+    let matched = match_rows(meta_arg, my_cols, &bim, beta.clone())?.matched;
+    if matched.height() == 0 {
+        return Err(anyhow!(
+            "No snp matched between models and LD bfile {}",
+            clump_arg.bfile_path
+        ));
+    }
+
+    // candidates by chromosome, with position of bim snp
+    let chroms = bim.column(CHR)?.utf8()?;
+    let positions = bim.column(POS)?.i32()?;
+    let mut by_chrom: BTreeMap<String, Vec<Candidate>> = BTreeMap::new();
+    let rows = matched.column(BETA_ROW)?.u32()?;
+    let idxs = matched.column(IDX)?.u32()?;
+    let pvalues = matched.column(PVALUE)?.f32()?;
+    for ((row, idx), pvalue) in rows.into_iter().zip(idxs).zip(pvalues) {
+        let (row, idx, pvalue) = match (row, idx, pvalue) {
+            (Some(row), Some(idx), Some(pvalue)) if pvalue <= clump_arg.pvalue => {
+                (row, idx, pvalue)
+            }
+            _ => continue,
+        };
+        if let (Some(chrom), Some(pos)) = (chroms.get(idx as usize), positions.get(idx as usize)) {
+            by_chrom
+                .entry(chrom.to_owned())
+                .or_default()
+                .push(Candidate {
+                    row,
+                    idx: idx as isize,
+                    pos: pos as i64,
+                    pvalue,
+                    n_dup: 0,
+                });
+        }
+    }
+
+    // index row -> number of snp clumped into it
+    let mut n_clumped: HashMap<u32, u32> = HashMap::new();
+    let mut ld_reader = LdReader::new(&bfile_set)?;
+    let window = clump_arg.kb as i64 * 1000;
+    for (chrom, candidates) in by_chrom {
+        let candidates = dedup_by_snp(candidates);
+        let sid_idxs: Vec<isize> = candidates.iter().map(|v| v.idx).collect();
+        let genotypes = ld_reader.read(&sid_idxs)?;
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        order.sort_by(|a, b| candidates[*a].pvalue.total_cmp(&candidates[*b].pvalue));
+
+        let mut clumped = vec![false; candidates.len()];
+        let mut n_index = 0;
+        for ii in order {
+            if clumped[ii] {
+                continue;
+            }
+            clumped[ii] = true;
+            n_index += 1;
+            let pos = candidates[ii].pos;
+            let from = candidates.partition_point(|v| v.pos < pos - window);
+            let to = candidates.partition_point(|v| v.pos <= pos + window);
+            let mut count = candidates[ii].n_dup;
+            for jj in from..to {
+                if !clumped[jj] && genotypes[ii].r2(&genotypes[jj]) > clump_arg.r2 {
+                    clumped[jj] = true;
+                    count += candidates[jj].n_dup + 1;
+                }
+            }
+            n_clumped.insert(candidates[ii].row, count);
+        }
+        debug!(
+            "Clumped {} snp into {} index snp on chromosome {}",
+            candidates.len(),
+            n_index,
+            chrom
+        );
+    }
+    info!(
+        "Kept {} index snp after clumping {} weight rows by LD in {}",
+        n_clumped.len(),
+        beta.height(),
+        clump_arg.bfile_path
+    );
+
+    let mask: BooleanChunked = (0..beta.height() as u32)
+        .map(|v| n_clumped.contains_key(&v))
+        .collect();
+    let counts: Vec<u32> = (0..beta.height() as u32)
+        .filter_map(|v| n_clumped.get(&v).copied())
+        .collect();
+    let clumped_beta = beta.filter(&mask)?;
+    let report_cols: Vec<&str> = [ID, CHR, POS, A1, PVALUE]
+        .into_iter()
+        .filter(|v| clumped_beta.column(v).is_ok())
+        .collect();
+    let mut clumps = clumped_beta.select(report_cols)?;
+    clumps.with_column(Series::new(N_CLUMPED, counts))?;
+    Ok((clumped_beta, clumps))
+}
+
+/// Keep one row of least P for each snp, since weight rows matched to the
+/// same snp are in full LD. Return candidates sorted by position.
+fn dedup_by_snp(mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| a.idx.cmp(&b.idx).then(a.pvalue.total_cmp(&b.pvalue)));
+    let mut deduped: Vec<Candidate> = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        match deduped.last_mut() {
+            Some(last) if last.idx == candidate.idx => last.n_dup += 1,
+            _ => deduped.push(candidate),
+        }
+    }
+    deduped.sort_by_key(|v| v.pos);
+    deduped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dedup_by_snp, Candidate};
+
+    fn candidate(row: u32, idx: isize, pos: i64, pvalue: f32) -> Candidate {
+        Candidate {
+            row,
+            idx,
+            pos,
+            pvalue,
+            n_dup: 0,
+        }
+    }
+
+    #[test]
+    fn test_dedup_by_snp() {
+        let candidates = vec![
+            candidate(0, 3, 300, 0.01),
+            candidate(1, 1, 100, 0.5),
+            candidate(2, 3, 300, 0.001),
+            candidate(3, 2, 200, 0.1),
+            candidate(4, 3, 300, 0.2),
+        ];
+        let deduped = dedup_by_snp(candidates);
+        let rows: Vec<(u32, u32)> = deduped.iter().map(|v| (v.row, v.n_dup)).collect();
+        assert_eq!(rows, vec![(1, 0), (3, 0), (2, 2)]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Guard rails on match status. A model matching only a small part of its
+//! snp, e.g. after a genome build mismatch, still produces scores, which look
+//! fine but are wrong. Thresholds on match rate and weight coverage are
+//! checked for each score after all batches are matched, and failed checks
+//! are recorded in `check.json`.
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use super::status::{MatchStatus, ScoreStatus};
+
+/// names of checks
+pub const MIN_MATCH_RATE: &str = "min_match_rate";
+pub const MIN_WEIGHT_COVERAGE: &str = "min_weight_coverage";
+
+/// What to do when a score fails a check
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum GuardAction {
+    /// abort the run with non-zero exit
+    Fail,
+    /// keep the score and mark it as flagged in check.json
+    Flag,
+}
+
+impl GuardAction {
+    pub fn new(action: &str) -> Result<GuardAction> {
+        let my_action = match action {
+            "Fail" => GuardAction::Fail,
+            "Flag" => GuardAction::Flag,
+            _ => {
+                return Err(anyhow!(
+                    "Argument guard_action should be one of the following: [ Fail, Flag ], got {}",
+                    action
+                ))
+            }
+        };
+        Ok(my_action)
+    }
+}
+
+/// A failed check of a score
+#[derive(Debug, Serialize, Clone)]
+pub struct GuardFailure {
+    pub check: String,
+    pub threshold: f64,
+    /// observed value, none if the score has no snp
+    pub value: Option<f64>,
+}
+
+/// Threshold for all scores, or for a score, which takes precedence
+#[derive(Debug, Clone, Default)]
+struct Thresholds {
+    global: Option<f64>,
+    per_score: HashMap<String, f64>,
+}
+
+impl Thresholds {
+    /// parse specs such as `0.8` or `Lassosum=0.8`
+    fn new(specs: &[String], arg_name: &str) -> Result<Thresholds> {
+        let mut thresholds = Thresholds::default();
+        for spec in specs {
+            let (score_name, value) = match spec.split_once('=') {
+                Some((score_name, value)) => (Some(score_name), value),
+                None => (None, spec.as_str()),
+            };
+            let value: f64 = value
+                .parse()
+                .map_err(|_| anyhow!("Bad threshold in {}: {}", arg_name, spec))?;
+            if !(0. ..=1.).contains(&value) {
+                return Err(anyhow!(
+                    "Threshold in {} should be between 0 and 1, got {}",
+                    arg_name,
+                    spec
+                ));
+            }
+            match score_name {
+                Some(v) => {
+                    thresholds.per_score.insert(v.to_owned(), value);
+                }
+                None => thresholds.global = Some(value),
+            }
+        }
+        Ok(thresholds)
+    }
+
+    fn get(&self, score_name: &str) -> Option<f64> {
+        self.per_score.get(score_name).copied().or(self.global)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchGuard {
+    min_match_rate: Thresholds,
+    min_weight_coverage: Thresholds,
+    pub action: GuardAction,
+}
+
+impl MatchGuard {
+    pub fn new(
+        min_match_rate: &[String],
+        min_weight_coverage: &[String],
+        action: &str,
+    ) -> Result<MatchGuard> {
+        Ok(MatchGuard {
+            min_match_rate: Thresholds::new(min_match_rate, "--min-match-rate")?,
+            min_weight_coverage: Thresholds::new(min_weight_coverage, "--min-weight-coverage")?,
+            action: GuardAction::new(action)?,
+        })
+    }
+
+    /// score names given in per score thresholds
+    pub fn score_names(&self) -> Vec<&str> {
+        self.min_match_rate
+            .per_score
+            .keys()
+            .chain(self.min_weight_coverage.per_score.keys())
+            .map(|v| v.as_str())
+            .collect()
+    }
+
+    /// Check each score and record failures, return names of failed scores.
+    /// Fractions of [MatchStatus] should have been filled by
+    /// [MatchStatus::finalize].
+    pub fn check(&self, match_status: &mut MatchStatus) -> Vec<String> {
+        let mut failed = vec![];
+        for (score_name, score_status) in match_status.scores.iter_mut() {
+            let match_rate = match score_status.model_snp {
+                0 => None,
+                v => Some(score_status.match_snp as f64 / v as f64),
+            };
+            check_one(
+                score_status,
+                MIN_MATCH_RATE,
+                self.min_match_rate.get(score_name),
+                match_rate,
+            );
+            check_one(
+                score_status,
+                MIN_WEIGHT_COVERAGE,
+                self.min_weight_coverage.get(score_name),
+                score_status.match_weight_fraction,
+            );
+            if !score_status.guard_failures.is_empty() {
+                score_status.flagged = true;
+                failed.push(score_name.to_owned());
+            }
+        }
+        failed
+    }
+}
+
+fn check_one(
+    score_status: &mut ScoreStatus,
+    check: &str,
+    threshold: Option<f64>,
+    value: Option<f64>,
+) {
+    let threshold = match threshold {
+        Some(v) => v,
+        None => return,
+    };
+    let passed = match value {
+        Some(v) => v >= threshold,
+        None => false,
+    };
+    if !passed {
+        score_status.guard_failures.push(GuardFailure {
+            check: check.to_owned(),
+            threshold,
+            value,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use betareader::{Float, CHR};
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use super::{MatchGuard, MIN_MATCH_RATE, MIN_WEIGHT_COVERAGE};
+    use crate::join::status::{MatchStatus, ScoreStatus};
+
+    fn beta(chroms: &[&str], weights: &[Float]) -> DataFrame {
+        DataFrame::new(vec![
+            Series::new(CHR, chroms),
+            Series::new("score", weights),
+        ])
+        .unwrap()
+    }
+
+    fn batch_status(beta: &DataFrame, matched_beta: &DataFrame) -> MatchStatus {
+        let mut match_status = MatchStatus::new_empty();
+        let score_status = ScoreStatus::new(beta, matched_beta, "score").unwrap();
+        match_status.scores.insert("score".to_owned(), score_status);
+        match_status
+    }
+
+    #[test]
+    fn test_batch_without_match() {
+        let matched = beta(&["1", "1"], &[0.5, -0.5]);
+        let first = batch_status(&matched, &matched);
+        // no snp of the second batch is matched, which should be counted
+        let second = batch_status(&beta(&["2", "2", "2"], &[1.0, 1.0, 0.0]), &beta(&[], &[]));
+        let mut match_status = first + second;
+        match_status.finalize();
+
+        let guard =
+            MatchGuard::new(&["0.6".to_owned()], &["score=0.5".to_owned()], "Fail").unwrap();
+        assert_eq!(guard.check(&mut match_status), vec!["score"]);
+        let score_status = &match_status.scores["score"];
+        assert_eq!((score_status.model_snp, score_status.match_snp), (4, 2));
+        assert!(score_status.flagged);
+        let failures: Vec<_> = score_status
+            .guard_failures
+            .iter()
+            .map(|v| v.check.as_str())
+            .collect();
+        assert_eq!(failures, vec![MIN_MATCH_RATE, MIN_WEIGHT_COVERAGE]);
+        assert_eq!(score_status.guard_failures[1].value, Some(1.0 / 3.0));
+    }
+
+    #[test]
+    fn test_guard_pass() {
+        let matched = beta(&["1", "1"], &[0.5, -0.5]);
+        let mut match_status = batch_status(&beta(&["1", "1", "1"], &[0.5, -0.5, 0.2]), &matched);
+        match_status.finalize();
+        let guard = MatchGuard::new(&["0.6".to_owned()], &[], "Flag").unwrap();
+        assert!(guard.check(&mut match_status).is_empty());
+        assert!(!match_status.scores["score"].flagged);
+        // a score without snp fails
+        let mut match_status = batch_status(&beta(&[], &[]), &beta(&[], &[]));
+        match_status.finalize();
+        assert_eq!(guard.check(&mut match_status), vec!["score"]);
+        assert_eq!(match_status.scores["score"].guard_failures[0].value, None);
+    }
+}
@@ -0,0 +1,261 @@
+//! Binned scoring of Q ranges for CandT. Values of the Q range column are
+//! split into disjoint bins at the bounds of all Q ranges, so that each Q
+//! range is a run of consecutive bins. Each matched snp falls into a single
+//! bin, and its weight is put into the column of that bin, so the partial
+//! scores of all bins are calculated in one pass over genotype with as many
+//! non-zero weights as the raw score. Scores of Q ranges are then prefix sums
+//! over bins, instead of a weight column for each score and Q range, which
+//! are mostly copies of the same weights for nested P thresholds.
+//!
+//! With sorted bounds `b_0 < ... < b_m`, bin `2k + 1` is the value `b_k`
+//! itself and bin `2k` is the open interval between `b_{k-1}` and `b_k`, so
+//! that both inclusive and exclusive bounds are exact.
+use anyhow::Result;
+use betareader::{Float, FloatType};
+use ndarray::Array2;
+use polars::prelude::{DataFrame, NamedFrom, Series};
+
+use super::{beta_matrix::BetaMatrix, betahandler::Range};
+
+/// column of the bin index of each snp, null if out of all Q ranges
+pub const Q_BIN: &str = "Q_BIN";
+
+#[derive(Clone, Debug)]
+pub struct QBins {
+    /// column of all Q ranges
+    column: String,
+    /// sorted distinct bounds of Q ranges
+    bounds: Vec<f32>,
+    /// first and last bin of each Q range, none if the range is empty
+    ranges: Vec<Option<(usize, usize)>>,
+    score_names_raw: Vec<String>,
+}
+
+impl QBins {
+    /// None if Q ranges are on different columns, which can not be binned
+    /// together.
+    pub fn new(ranges: &[Range], score_names_raw: &[String]) -> Option<QBins> {
+        let column = &ranges.first()?.column;
+        if ranges.iter().any(|v| &v.column != column) {
+            return None;
+        }
+        let mut bounds: Vec<f32> = ranges.iter().flat_map(|v| [v.from, v.to]).collect();
+        bounds.sort_by(f32::total_cmp);
+        bounds.dedup();
+        let position = |bound: f32| bounds.partition_point(|v| *v < bound);
+        let ranges = ranges
+            .iter()
+            .map(|range| {
+                let (from, to) = (position(range.from), position(range.to));
+                let first = if range.from_inclusive {
+                    2 * from + 1
+                } else {
+                    2 * from + 2
+                };
+                let last = if range.to_inclusive {
+                    2 * to + 1
+                } else {
+                    2 * to
+                };
+                Some((first, last)).filter(|(first, last)| first <= last)
+            })
+            .collect();
+        Some(QBins {
+            column: column.to_owned(),
+            bounds,
+            ranges,
+            score_names_raw: score_names_raw.to_vec(),
+        })
+    }
+
+    fn n_bins(&self) -> usize {
+        2 * self.bounds.len() + 1
+    }
+
+    /// bin of a value, none if it is out of all Q ranges
+    fn bin(&self, value: f32) -> Option<usize> {
+        let k = self.bounds.partition_point(|v| *v < value);
+        let bin = match self.bounds.get(k) {
+            Some(v) if *v == value => 2 * k + 1,
+            _ => 2 * k,
+        };
+        self.ranges
+            .iter()
+            .flatten()
+            .any(|(first, last)| (*first..=*last).contains(&bin))
+            .then_some(bin)
+    }
+
+    /// add [Q_BIN] to beta by the value of the Q range column
+    pub fn with_bin(&self, mut beta: DataFrame) -> Result<DataFrame> {
+        let bins: Vec<Option<u32>> = beta
+            .column(&self.column)?
+            .f32()?
+            .into_iter()
+            .map(|v| v.and_then(|v| self.bin(v)).map(|v| v as u32))
+            .collect();
+        beta.with_column(Series::new(Q_BIN, bins))?;
+        Ok(beta)
+    }
+
+    /// Weight matrix of the raw scores followed by the bins of each raw
+    /// score, from matched beta with [Q_BIN].
+    pub fn weight_matrix(&self, matched_beta: &DataFrame) -> Result<BetaMatrix> {
+        let n_raw = self.score_names_raw.len();
+        let n_bins = self.n_bins();
+        let bins = matched_beta.column(Q_BIN)?.u32()?;
+        let mut columns = vec![vec![]; n_raw * (1 + n_bins)];
+        for (ii, score_name) in self.score_names_raw.iter().enumerate() {
+            let weights = matched_beta.column(score_name)?.unpack::<FloatType>()?;
+            for (row, (weight, bin)) in weights.into_iter().zip(bins).enumerate() {
+                let weight = match weight {
+                    Some(v) if v != 0. => v,
+                    _ => continue,
+                };
+                columns[ii].push((row, weight));
+                if let Some(bin) = bin {
+                    columns[n_raw + ii * n_bins + bin as usize].push((row, weight));
+                }
+            }
+        }
+        Ok(BetaMatrix::from_columns(matched_beta.height(), columns))
+    }
+
+    /// Columns of [QBins::weight_matrix] summed into the score `col`, in the
+    /// order of score names of Q ranges, which is each raw score followed
+    /// by its Q ranges.
+    pub fn source_cols(&self, col: usize) -> Vec<usize> {
+        let n_scores = 1 + self.ranges.len();
+        let (ii, rr) = (col / n_scores, col % n_scores);
+        if rr == 0 {
+            return vec![ii];
+        }
+        let start = self.score_names_raw.len() + ii * self.n_bins();
+        match self.ranges[rr - 1] {
+            Some((first, last)) => (start + first..=start + last).collect(),
+            None => vec![],
+        }
+    }
+
+    /// Scores of raw scores and Q ranges from partial scores of
+    /// [QBins::weight_matrix]. Each Q range is the difference of prefix sums
+    /// over bins at its ends.
+    pub fn to_ranges(&self, score: &Array2<Float>) -> Array2<Float> {
+        let n_raw = self.score_names_raw.len();
+        let n_bins = self.n_bins();
+        let n_scores = 1 + self.ranges.len();
+        let mut range_score = Array2::<Float>::zeros((score.nrows(), n_raw * n_scores));
+        // sum of bins before each bin
+        let mut prefix_sum = Array2::<Float>::zeros((score.nrows(), n_bins + 1));
+        for ii in 0..n_raw {
+            range_score
+                .column_mut(ii * n_scores)
+                .assign(&score.column(ii));
+            let start = n_raw + ii * n_bins;
+            for bin in 0..n_bins {
+                let next = &prefix_sum.column(bin) + &score.column(start + bin);
+                prefix_sum.column_mut(bin + 1).assign(&next);
+            }
+            for (rr, range) in self.ranges.iter().enumerate() {
+                if let Some((first, last)) = range {
+                    let sum = &prefix_sum.column(last + 1) - &prefix_sum.column(*first);
+                    range_score.column_mut(ii * n_scores + 1 + rr).assign(&sum);
+                }
+            }
+        }
+        range_score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use betareader::{Float, PVALUE};
+    use ndarray::{array, Array2};
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use super::{QBins, Q_BIN};
+    use crate::join::betahandler::Range;
+
+    fn range(from: f32, to: f32, from_inclusive: bool, to_inclusive: bool) -> Range {
+        Range {
+            name: String::new(),
+            column: PVALUE.to_owned(),
+            from,
+            to,
+            from_inclusive,
+            to_inclusive,
+        }
+    }
+
+    fn q_bins() -> QBins {
+        let ranges = [
+            range(0., 0.01, true, true),
+            range(0.01, 0.05, false, false),
+            range(0., 0.05, true, false),
+            range(0.05, 0.05, true, true),
+            // empty
+            range(0.5, 0.5, false, false),
+        ];
+        QBins::new(&ranges, &["s1".to_owned()]).unwrap()
+    }
+
+    #[test]
+    fn test_bins() {
+        let q_bins = q_bins();
+        assert_eq!(q_bins.bounds, vec![0., 0.01, 0.05, 0.5]);
+        // bin 2k + 1 is the bound k, and bin 2k is below it
+        assert_eq!(q_bins.ranges[0], Some((1, 3)));
+        assert_eq!(q_bins.ranges[1], Some((4, 4)));
+        assert_eq!(q_bins.ranges[2], Some((1, 4)));
+        assert_eq!(q_bins.ranges[3], Some((5, 5)));
+        assert_eq!(q_bins.ranges[4], None);
+        assert_eq!(q_bins.bin(0.), Some(1));
+        assert_eq!(q_bins.bin(0.03), Some(4));
+        assert_eq!(q_bins.bin(0.05), Some(5));
+        assert_eq!(q_bins.bin(0.2), None);
+        assert_eq!(q_bins.bin(-1.), None);
+        // raw score, then bins of each Q range
+        assert_eq!(q_bins.source_cols(0), vec![0]);
+        assert_eq!(q_bins.source_cols(1), vec![2, 3, 4]);
+        assert_eq!(q_bins.source_cols(5), vec![]);
+    }
+
+    #[test]
+    fn test_to_ranges() {
+        let q_bins = q_bins();
+        let pvalues: [Option<f32>; 7] = [
+            Some(0.),
+            Some(0.005),
+            Some(0.01),
+            Some(0.03),
+            Some(0.05),
+            Some(0.2),
+            None,
+        ];
+        let weights: Vec<Float> = vec![1., 2., 4., 8., 16., 32., 64.];
+        let beta = DataFrame::new(vec![
+            Series::new(PVALUE, pvalues.to_vec()),
+            Series::new("s1", weights),
+        ])
+        .unwrap();
+        let beta = q_bins.with_bin(beta).unwrap();
+        let bins: Vec<Option<u32>> = beta
+            .column(Q_BIN)
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            bins,
+            vec![Some(1), Some(2), Some(3), Some(4), Some(5), None, None]
+        );
+
+        let weight_matrix = q_bins.weight_matrix(&beta).unwrap();
+        let gt: Array2<f32> = array![[1., 1., 1., 1., 1., 1., 1.], [0., 1., 2., 0., 1., 2., 1.]];
+        let score = q_bins.to_ranges(&weight_matrix.score(&gt));
+        let expected: Array2<Float> =
+            array![[127., 7., 8., 15., 16., 0.], [154., 10., 0., 10., 16., 0.]];
+        assert_eq!(score, expected);
+    }
+}
@@ -0,0 +1,241 @@
+//! Per-variant match report. Each row of weights is given a disposition,
+//! telling whether it is matched or at which step of [super::match_snp] it is
+//! dropped, together with the bim snp it is matched or compared to. Rows
+//! rejected when reading weights are in the rejected file instead, and rows
+//! of duplicated variants dropped by DupPolicy in the duplicates file.
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use betareader::{A1, A2, CHR, ID, POS};
+use genoreader::meta::{ALT, IDX, REF};
+use polars::prelude::{DataFrame, DataFrameJoinOps, IntoLazy, NamedFrom, Series};
+
+use super::{is_swap, MatchedRows, BETA_ROW, NO_MATCH};
+use crate::meta::{MetaArg, QrangeOrScorenames, STATUS};
+
+/// column names of the report
+pub const DISPOSITION: &str = "DISPOSITION";
+pub const BIM_IDX: &str = "BIM_IDX";
+/// whether a row is in any Q range
+const IN_Q_RANGES: &str = "IN_Q_RANGES";
+/// dispositions of weight rows
+pub const MATCHED: &str = "Matched";
+pub const SWAPPED: &str = "Swapped";
+pub const OUTSIDE_Q_RANGE: &str = "OutsideQRange";
+pub const AMBIGUOUS_DROPPED: &str = "AmbiguousDropped";
+pub const ALLELE_MISMATCH: &str = "AlleleMismatch";
+pub const NOT_IN_BIM: &str = "NotInBim";
+pub const FILTERED_NULL: &str = "FilteredNull";
+
+/// Disposition of each row in beta of [match_rows], in the same order as
+/// beta. Matched snp out of all Q ranges are [OUTSIDE_Q_RANGE], since they
+/// are only in the unfiltered score.
+///
+/// [match_rows]: super::match_rows
+pub fn match_report(meta_arg: &MetaArg, bim: &DataFrame, rows: &MatchedRows) -> Result<DataFrame> {
+    let beta = &rows.input;
+    let matched = status_by_row(&rows.matched)?;
+    let mut candidates = HashMap::new();
+    let mut mismatched = HashMap::new();
+    for (row, status_idx) in status_by_row(&rows.classified)? {
+        if status_idx.0 == NO_MATCH {
+            mismatched.insert(row, status_idx);
+        } else {
+            candidates.insert(row, status_idx);
+        }
+    }
+    let filtered: HashSet<u32> = rows
+        .beta
+        .column(BETA_ROW)?
+        .u32()?
+        .into_iter()
+        .flatten()
+        .collect();
+    let in_q_ranges: Vec<bool> = match &meta_arg.q_range_enum {
+        QrangeOrScorenames::QRange(q_range) => beta
+            .clone()
+            .lazy()
+            .select([q_range.any_expr().alias(IN_Q_RANGES)])
+            .collect()?
+            .column(IN_Q_RANGES)?
+            .bool()?
+            .into_iter()
+            .map(|v| v.unwrap_or(false))
+            .collect(),
+        QrangeOrScorenames::ScoreNameRaws(_) => vec![true; beta.height()],
+    };
+
+    let mut dispositions = vec![];
+    let mut statuses = vec![];
+    let mut bim_idx = vec![];
+    for (row, in_q_ranges) in (0..beta.height() as u32).zip(in_q_ranges) {
+        let (disposition, status_idx) = if let Some(v) = matched.get(&row) {
+            let disposition = match in_q_ranges {
+                false => OUTSIDE_Q_RANGE,
+                true if is_swap(&v.0) => SWAPPED,
+                true => MATCHED,
+            };
+            (disposition, Some(v))
+        } else if let Some(v) = candidates.get(&row) {
+            // each row of weights is matched once, unless dropped as ambiguous
+            (AMBIGUOUS_DROPPED, Some(v))
+        } else if let Some(v) = mismatched.get(&row) {
+            (ALLELE_MISMATCH, Some(v))
+        } else if filtered.contains(&row) {
+            (NOT_IN_BIM, None)
+        } else {
+            (FILTERED_NULL, None)
+        };
+        dispositions.push(disposition);
+        statuses.push(status_idx.map(|v| v.0.as_str()));
+        bim_idx.push(status_idx.map(|v| v.1));
+    }
+
+    // identifier of weight rows and the bim snp
+    let beta_cols: Vec<&str> = [ID, CHR, POS, A1, A2]
+        .into_iter()
+        .filter(|v| beta.column(v).is_ok())
+        .collect();
+    let mut report = beta.select(beta_cols)?;
+    report.with_column(Series::new(DISPOSITION, dispositions))?;
+    report.with_column(Series::new(STATUS, statuses))?;
+    report.with_column(Series::new(BIM_IDX, bim_idx))?;
+    let mut bim_cols = bim.select([IDX, ID, CHR, POS, REF, ALT])?;
+    for name in [IDX, ID, CHR, POS, REF, ALT] {
+        bim_cols.rename(name, &format!("BIM_{name}"))?;
+    }
+    Ok(report.left_join(&bim_cols, [BIM_IDX], [BIM_IDX])?)
+}
+
+/// beta row -> (status, bim IDX), first one is kept for each row
+fn status_by_row(frame: &DataFrame) -> Result<HashMap<u32, (String, u32)>> {
+    let rows = frame.column(BETA_ROW)?.u32()?;
+    let statuses = frame.column(STATUS)?.utf8()?;
+    let idx = frame.column(IDX)?.u32()?;
+    let mut status_by_row = HashMap::new();
+    for ((row, status), idx) in rows.into_iter().zip(statuses).zip(idx) {
+        if let (Some(row), Some(status), Some(idx)) = (row, status, idx) {
+            status_by_row
+                .entry(row)
+                .or_insert_with(|| (status.to_owned(), idx));
+        }
+    }
+    Ok(status_by_row)
+}
+
+#[cfg(test)]
+mod tests {
+    use betareader::{A1, A2, CHR, ID, POS, PVALUE};
+    use genoreader::meta::{ALT, IDX, REF};
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use super::*;
+    use crate::{
+        join::{betahandler::QRange, match_rows},
+        meta::{AmbiguousPolicy, GenotypeScale, MatchMode, MissingStrategy, ScoreMode},
+    };
+
+    #[test]
+    fn test_match_report() {
+        let bim = DataFrame::new(vec![
+            Series::new(CHR, ["1", "1", "1", "1", "1"]),
+            Series::new(ID, ["rs1", "rs2", "rs3", "rs4", "rs5"]),
+            Series::new(POS, [100, 200, 300, 400, 500]),
+            Series::new(REF, ["C", "T", "C", "C", "C"]),
+            Series::new(ALT, ["A", "A", "A", "G", "A"]),
+        ])
+        .unwrap()
+        .with_row_count(IDX, None)
+        .unwrap();
+        let beta = DataFrame::new(vec![
+            Series::new(CHR, ["1", "1", "1", "1", "1", "2", "1", "1"]),
+            Series::new(POS, [100, 100, 300, 200, 400, 500, 100, 500]),
+            Series::new(A1, ["A", "A", "C", "A", "A", "A", "A", "A"]),
+            Series::new(A2, ["C", "C", "A", "T", "T", "C", "C", "C"]),
+            Series::new(
+                "SCORE",
+                [
+                    Some(1_f32),
+                    Some(1.),
+                    Some(1.),
+                    Some(1.),
+                    Some(1.),
+                    Some(1.),
+                    None,
+                    Some(1.),
+                ],
+            ),
+            Series::new(PVALUE, [0.1_f32, 0.1, 0.1, 0.1, 0.1, 0.1, 0.1, 0.9]),
+        ])
+        .unwrap();
+        let my_cols: Vec<String> = beta
+            .get_column_names()
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+
+        let q_path = std::env::temp_dir().join("pgs_report_test.q_ranges");
+        std::fs::write(&q_path, "0.5\t0\t0.5\n").unwrap();
+        let score_names = vec!["SCORE".to_owned()];
+        let q_range = QRange::new(q_path.to_str().unwrap(), &score_names, PVALUE).unwrap();
+        let meta_arg = MetaArg {
+            batch_size: 1,
+            thread_num: 1,
+            match_mode: MatchMode::Pos,
+            sorted_match: false,
+            match_report: true,
+            missing_strategy: MissingStrategy::Impute,
+            score_mode: ScoreMode::Sum,
+            genotype_scale: GenotypeScale::Raw,
+            ambiguous_policy: AmbiguousPolicy::Drop,
+            out_prefix: "",
+            q_range_enum: QrangeOrScorenames::QRange(q_range),
+            clump_arg: None,
+            score_per_chrom: false,
+        };
+        let rows = match_rows(&meta_arg, &my_cols, &bim, beta).unwrap();
+        let report = match_report(&meta_arg, &bim, &rows).unwrap();
+        let dispositions: Vec<&str> = report
+            .column(DISPOSITION)
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .map(|v| v.unwrap())
+            .collect();
+        assert_eq!(
+            dispositions,
+            vec![
+                MATCHED,
+                MATCHED,
+                SWAPPED,
+                AMBIGUOUS_DROPPED,
+                ALLELE_MISMATCH,
+                NOT_IN_BIM,
+                FILTERED_NULL,
+                OUTSIDE_Q_RANGE
+            ]
+        );
+        // bim snp matched or compared to
+        let bim_ids: Vec<Option<&str>> = report
+            .column("BIM_ID")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            bim_ids,
+            vec![
+                Some("rs1"),
+                Some("rs1"),
+                Some("rs3"),
+                Some("rs2"),
+                Some("rs4"),
+                None,
+                None,
+                Some("rs5")
+            ]
+        );
+    }
+}
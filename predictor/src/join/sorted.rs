@@ -0,0 +1,43 @@
+//! Sorted merge of weights and bim. When both are sorted by CHR and POS,
+//! each weight batch only need to be joined with the bim rows in its position
+//! range, which are read by [BimCursor] in lockstep with the weight batches.
+//! Duplicated positions are handled since all bim rows at the boundary of a
+//! batch are included, and kept for the next batch.
+use anyhow::{anyhow, Result};
+use betareader::{CHR, POS};
+use genoreader::{
+    reader::bim_cursor::{empty_bim, ChromKey},
+    BimCursor,
+};
+use polars::prelude::DataFrame;
+
+/// Get the bim rows for a sorted weight batch. Return bim without rows if
+/// there is no valid position in the batch, so that the batch is still
+/// counted in match status.
+pub fn sorted_bim_window(cursor: &mut BimCursor, beta: &DataFrame) -> Result<DataFrame> {
+    let chrom = beta.column(CHR)?.utf8()?;
+    let pos = beta.column(POS)?.i32()?;
+    let mut bounds = None;
+    for (chrom, pos) in chrom.into_iter().zip(pos) {
+        let key = match (chrom, pos) {
+            (Some(chrom), Some(pos)) => (ChromKey::new(chrom), pos),
+            _ => continue,
+        };
+        bounds = match bounds {
+            None => Some((key.clone(), key)),
+            Some((from, to)) => {
+                if key < to {
+                    return Err(anyhow!(
+                        "weights are not sorted by CHR and POS, sort them or match without \
+                         --sorted-match"
+                    ));
+                }
+                Some((from, key))
+            }
+        };
+    }
+    match bounds {
+        Some((from, to)) => cursor.window(&from, &to),
+        None => empty_bim(),
+    }
+}
@@ -0,0 +1,334 @@
+//! Match status written to `*.check.json`. Counts and weight sums are
+//! recorded for each batch and added up across snp batches and worker
+//! threads, while fractions are only derived from the sums by
+//! [MatchStatus::finalize] before writing, since they can not be added.
+use std::{collections::BTreeMap, fmt, ops::Add};
+
+use anyhow::Result;
+use betareader::{float_to_f64, FloatType, CHR};
+use polars::prelude::DataFrame;
+use serde::Serialize;
+
+use super::{guard::GuardFailure, is_swap, weight::Weights};
+use crate::meta::STATUS;
+
+/// version of the check.json layout, increased when fields are changed
+pub const CHECK_VERSION: u32 = 2;
+/// chromosome label of snp without CHR
+pub(crate) const NA_CHROM: &str = "NA";
+
+/// Match status, result of the join between bfile and beta.
+/// ```rust
+/// use crate::join::MatchStatus;
+/// let aa = MatchStatus::new_empty();
+/// let bb = MatchStatus::new(1234, 234, 198);
+/// aa = aa + bb;
+/// println("{}", aa);
+/// ```
+#[derive(Debug, Serialize, Clone)]
+pub struct MatchStatus {
+    pub version: u32,
+    pub bfile_snp: usize,
+    pub model_snp: usize,
+    pub match_snp: usize,
+    /// number of matched snp by status
+    pub good_snp: usize,
+    pub swap_snp: usize,
+    pub flip_snp: usize,
+    pub flip_swap_snp: usize,
+    pub ambiguous_snp: usize,
+    /// snp found in bim but alleles are not matched
+    pub mismatch_snp: usize,
+    /// snp only matched after allele normalization, such as trimming padded
+    /// indel or resolving `I`/`D` code
+    pub normalized_snp: usize,
+    /// status for each score, which is the model when multiple models are
+    /// scored together, and each Q range for CandT
+    pub scores: BTreeMap<String, ScoreStatus>,
+}
+
+/// Match status of a score column. Only snp with non-zero weight are counted.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ScoreStatus {
+    pub model_snp: usize,
+    pub match_snp: usize,
+    /// matched snp with genotype swapped
+    pub swap_snp: usize,
+    /// sum of |weight| of snp in model and of matched snp
+    pub model_weight: f64,
+    pub match_weight: f64,
+    /// `match_weight / model_weight`
+    pub match_weight_fraction: Option<f64>,
+    /// number of genotypes of matched snp, and those filled by missing
+    /// strategy, null if no genotype is read for the score.
+    pub genotypes: Option<u64>,
+    pub imputed_genotypes: Option<u64>,
+    /// `imputed_genotypes / genotypes`
+    pub imputed_fraction: Option<f64>,
+    /// weight type in weight file, such as beta or OR
+    pub weight_type: Option<String>,
+    /// transform applied to weight, such as ln(OR)
+    pub transform: Option<String>,
+    /// breakdown by chromosome
+    pub chroms: BTreeMap<String, ChromStatus>,
+    /// whether the score fails any check of [super::guard::MatchGuard]
+    pub flagged: bool,
+    pub guard_failures: Vec<GuardFailure>,
+}
+
+/// Match status of a score column on a chromosome
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ChromStatus {
+    pub model_snp: usize,
+    pub match_snp: usize,
+    pub model_weight: f64,
+    pub match_weight: f64,
+    pub match_weight_fraction: Option<f64>,
+}
+
+impl Add for ChromStatus {
+    type Output = ChromStatus;
+    fn add(self, another: ChromStatus) -> ChromStatus {
+        ChromStatus {
+            model_snp: self.model_snp + another.model_snp,
+            match_snp: self.match_snp + another.match_snp,
+            model_weight: self.model_weight + another.model_weight,
+            match_weight: self.match_weight + another.match_weight,
+            match_weight_fraction: None,
+        }
+    }
+}
+
+impl Add for ScoreStatus {
+    type Output = ScoreStatus;
+    fn add(self, another: ScoreStatus) -> ScoreStatus {
+        ScoreStatus {
+            model_snp: self.model_snp + another.model_snp,
+            match_snp: self.match_snp + another.match_snp,
+            swap_snp: self.swap_snp + another.swap_snp,
+            model_weight: self.model_weight + another.model_weight,
+            match_weight: self.match_weight + another.match_weight,
+            match_weight_fraction: None,
+            genotypes: add_count(self.genotypes, another.genotypes),
+            imputed_genotypes: add_count(self.imputed_genotypes, another.imputed_genotypes),
+            imputed_fraction: None,
+            weight_type: self.weight_type.or(another.weight_type),
+            transform: self.transform.or(another.transform),
+            chroms: merge_status(self.chroms, another.chroms),
+            flagged: self.flagged || another.flagged,
+            guard_failures: [self.guard_failures, another.guard_failures].concat(),
+        }
+    }
+}
+
+impl ScoreStatus {
+    /// Status of a score from beta of the model and matched beta
+    pub fn new(
+        beta: &DataFrame,
+        matched_beta: &DataFrame,
+        score_name: &str,
+    ) -> Result<ScoreStatus> {
+        let mut score_status = ScoreStatus::default();
+        for (chrom, weight, _) in score_weights(beta, score_name)? {
+            score_status.model_snp += 1;
+            score_status.model_weight += weight;
+            let chrom_status = score_status.chroms.entry(chrom).or_default();
+            chrom_status.model_snp += 1;
+            chrom_status.model_weight += weight;
+        }
+        for (chrom, weight, swap_flag) in score_weights(matched_beta, score_name)? {
+            score_status.match_snp += 1;
+            score_status.match_weight += weight;
+            score_status.swap_snp += swap_flag as usize;
+            let chrom_status = score_status.chroms.entry(chrom).or_default();
+            chrom_status.match_snp += 1;
+            chrom_status.match_weight += weight;
+        }
+        Ok(score_status)
+    }
+}
+
+/// (CHR, |weight|, whether swapped) of snp with non-zero weight
+fn score_weights(frame: &DataFrame, score_name: &str) -> Result<Vec<(String, f64, bool)>> {
+    let weights = frame.column(score_name)?.unpack::<FloatType>()?;
+    let chroms = frame.column(CHR)?.utf8()?;
+    let statuses: Vec<Option<&str>> = match frame.column(STATUS) {
+        Ok(v) => v.utf8()?.into_iter().collect(),
+        Err(_) => vec![None; frame.height()],
+    };
+    let score_weights = weights
+        .into_iter()
+        .zip(chroms)
+        .zip(statuses)
+        .filter_map(|((weight, chrom), status)| match weight {
+            Some(weight) if weight != 0. => Some((
+                chrom.unwrap_or(NA_CHROM).to_owned(),
+                float_to_f64(weight.abs()),
+                status.is_some_and(is_swap),
+            )),
+            _ => None,
+        })
+        .collect();
+    Ok(score_weights)
+}
+
+/// init an empty one
+#[allow(dead_code)]
+impl MatchStatus {
+    pub fn new(bfile_snp: usize, model_snp: usize, match_snp: usize) -> MatchStatus {
+        MatchStatus {
+            bfile_snp,
+            model_snp,
+            match_snp,
+            ..MatchStatus::new_empty()
+        }
+    }
+
+    pub fn new_empty() -> MatchStatus {
+        MatchStatus {
+            version: CHECK_VERSION,
+            bfile_snp: 0,
+            model_snp: 0,
+            match_snp: 0,
+            good_snp: 0,
+            swap_snp: 0,
+            flip_snp: 0,
+            flip_swap_snp: 0,
+            ambiguous_snp: 0,
+            mismatch_snp: 0,
+            normalized_snp: 0,
+            scores: BTreeMap::new(),
+        }
+    }
+
+    /// Record genotypes filled by missing strategy, `n_missing` is the number
+    /// of missing genotypes of each snp in [Weights], and `n_ind` is the
+    /// number of individuals.
+    pub fn add_imputed(&mut self, weights: &Weights, n_missing: &[u32], n_ind: usize) {
+        for (cc, score_name) in weights.score_names.iter().enumerate() {
+            let score_status = self.scores.entry(score_name.to_owned()).or_default();
+            let genotypes = score_status.genotypes.get_or_insert(0);
+            let imputed_genotypes = score_status.imputed_genotypes.get_or_insert(0);
+            for row in weights.nonzero_rows(cc) {
+                *genotypes += n_ind as u64;
+                *imputed_genotypes += n_missing[row] as u64;
+            }
+        }
+    }
+
+    /// fill fractions from sums, should be called after all batches are added
+    pub fn finalize(&mut self) {
+        for score_status in self.scores.values_mut() {
+            score_status.match_weight_fraction =
+                fraction(score_status.match_weight, score_status.model_weight);
+            score_status.imputed_fraction =
+                match (score_status.imputed_genotypes, score_status.genotypes) {
+                    (Some(imputed), Some(total)) => fraction(imputed as f64, total as f64),
+                    _ => None,
+                };
+            for chrom_status in score_status.chroms.values_mut() {
+                chrom_status.match_weight_fraction =
+                    fraction(chrom_status.match_weight, chrom_status.model_weight);
+            }
+        }
+    }
+}
+
+/// sum of counts recorded by any of the two
+fn add_count(count: Option<u64>, another: Option<u64>) -> Option<u64> {
+    match (count, another) {
+        (Some(count), Some(another)) => Some(count + another),
+        (count, another) => count.or(another),
+    }
+}
+
+fn fraction(part: f64, total: f64) -> Option<f64> {
+    if total > 0. {
+        Some(part / total)
+    } else {
+        None
+    }
+}
+
+/// provide add function. bfile_snp is the same for all batches, except the
+/// bim window for sorted match, which is set after all batches.
+impl Add for MatchStatus {
+    type Output = MatchStatus;
+    fn add(self, another: MatchStatus) -> MatchStatus {
+        MatchStatus {
+            version: self.version,
+            bfile_snp: self.bfile_snp.max(another.bfile_snp),
+            model_snp: self.model_snp + another.model_snp,
+            match_snp: self.match_snp + another.match_snp,
+            good_snp: self.good_snp + another.good_snp,
+            swap_snp: self.swap_snp + another.swap_snp,
+            flip_snp: self.flip_snp + another.flip_snp,
+            flip_swap_snp: self.flip_swap_snp + another.flip_swap_snp,
+            ambiguous_snp: self.ambiguous_snp + another.ambiguous_snp,
+            mismatch_snp: self.mismatch_snp + another.mismatch_snp,
+            normalized_snp: self.normalized_snp + another.normalized_snp,
+            scores: merge_status(self.scores, another.scores),
+        }
+    }
+}
+
+fn merge_status<T: Add<Output = T> + Default>(
+    mut status: BTreeMap<String, T>,
+    another: BTreeMap<String, T>,
+) -> BTreeMap<String, T> {
+    for (k, v) in another {
+        let old = status.remove(&k).unwrap_or_default();
+        status.insert(k, old + v);
+    }
+    status
+}
+
+/// Error of [super::match_snp] when no snp of a batch is matched. It carries
+/// the status of the batch, so that snp of the model in the batch are still
+/// counted when batching by snp.
+#[derive(Debug)]
+pub struct NoSnpMatched(pub MatchStatus);
+
+impl fmt::Display for NoSnpMatched {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No snp matched between models and bfile!")
+    }
+}
+
+impl std::error::Error for NoSnpMatched {}
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchStatus, ScoreStatus};
+
+    fn with_score(score_status: ScoreStatus) -> MatchStatus {
+        let mut match_status = MatchStatus::new_empty();
+        match_status.scores.insert("score".to_owned(), score_status);
+        match_status
+    }
+
+    #[test]
+    fn test_imputed_fraction() {
+        // batching by sample does not record genotypes
+        let mut match_status = with_score(ScoreStatus::default())
+            + with_score(ScoreStatus {
+                model_snp: 2,
+                ..Default::default()
+            });
+        match_status.finalize();
+        let score_status = &match_status.scores["score"];
+        assert_eq!(score_status.model_snp, 2);
+        assert_eq!(score_status.genotypes, None);
+        assert_eq!(score_status.imputed_fraction, None);
+
+        // a batch without matched snp is added with no genotypes
+        let recorded = ScoreStatus {
+            genotypes: Some(100),
+            imputed_genotypes: Some(5),
+            ..Default::default()
+        };
+        let mut match_status = with_score(recorded) + with_score(ScoreStatus::default());
+        match_status.finalize();
+        assert_eq!(match_status.scores["score"].imputed_fraction, Some(0.05));
+    }
+}
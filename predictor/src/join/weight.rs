@@ -1,28 +1,91 @@
-use anyhow::Result;
-use betareader::FREQ;
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use betareader::{Float, FloatType, CHR, FREQ, IS_DOMINANT, IS_RECESSIVE};
 use genoreader::meta::IDX;
+use log::debug;
 use ndarray::Array2;
 use polars::{
     lazy::dsl::lit,
-    prelude::{DataFrame, Float32Type, IntoLazy, IndexOrder},
+    prelude::{DataFrame, DataFrameJoinOps, IntoLazy},
+};
+
+use super::{
+    allele::resolve_ambiguous_swap, beta_matrix::BetaMatrix, is_swap, qbins::QBins,
+    status::NA_CHROM, AMBIGUOUS,
 };
+use crate::meta::{GenotypeScale, MissingStrategy, STATUS};
+
+/// Genetic model of a variant, how dosage of the effect allele is coded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeneticModel {
+    /// 0/1/2
+    Additive,
+    /// 0/1/1
+    Dominant,
+    /// 0/0/1
+    Recessive,
+}
+
+impl GeneticModel {
+    /// coded dosage of the effect allele
+    pub fn code(&self, dosage: f32) -> f32 {
+        match self {
+            GeneticModel::Additive => dosage,
+            GeneticModel::Dominant => dosage.min(1.),
+            GeneticModel::Recessive => (dosage - 1.).max(0.),
+        }
+    }
 
-use crate::meta::{MissingStrategy, STATUS};
+    /// Dosage filled into missing genotypes for mean dosage `2p` of the effect
+    /// allele. It is coded into the expected coded dosage under HWE,
+    /// `1 - (1 - p)^2` for dominant and `p^2` for recessive, since coding the
+    /// mean dosage itself clips it, such as 0.4 into 0 for recessive.
+    pub fn fill_dosage(&self, dosage: f32) -> f32 {
+        let p = dosage / 2.;
+        match self {
+            GeneticModel::Additive => dosage,
+            GeneticModel::Dominant => 1. - (1. - p).powi(2),
+            GeneticModel::Recessive => 1. + p.powi(2),
+        }
+    }
+
+    /// variance of coded dosage with mean `center` under HWE, `2p(1 - p)` for
+    /// additive and `c(1 - c)` for 0/1 coding
+    pub fn variance(&self, center: f32) -> f32 {
+        match self {
+            GeneticModel::Additive => center * (1. - center / 2.),
+            _ => center * (1. - center),
+        }
+    }
+}
 
 /// Store the matched snp and weight into a Weight obj, which contain and
 /// preprocessanything needed for prediction.
 #[derive(Clone, Debug)]
 pub struct Weights {
-    /// The 2d weight matrix
-    pub beta_values: Array2<f32>,
+    /// The 2d weight matrix, sparse if most weights are zero
+    pub beta_values: BetaMatrix,
     /// snp idx for bed that is matched with beta_values
     pub sid_idx: Vec<isize>,
     /// StATUS and FREQ vec, FREQ may be empty
     pub status_freq_vec: Vec<(Option<String>, Option<f32>)>,
+    /// genetic model for each snp
+    pub genetic_models: Vec<GeneticModel>,
     /// missing strategy for fill missing value
     pub missing_strategy: MissingStrategy,
+    /// scale of genotype before scoring
+    pub genotype_scale: GenotypeScale,
     /// score names
     pub score_names: Vec<String>,
+    /// bins of Q ranges, by which columns of `beta_values` are summed into
+    /// scores of Q ranges
+    pub q_bins: Option<QBins>,
+    /// mean dosage of the counted allele of each snp in the whole cohort,
+    /// used instead of the mean of a batch of samples
+    pub cohort_dosage: Option<Vec<f32>>,
+    /// chromosome of each snp, only set for partial scores by chromosome
+    pub chroms: Option<Vec<String>>,
 }
 
 /// [Weights] containing weights and meta data for pgs prediction
@@ -31,11 +94,32 @@ impl Weights {
         mut matched_beta: DataFrame,
         score_names: Vec<String>,
         missing_strategy: MissingStrategy,
+        genotype_scale: GenotypeScale,
+        q_bins: Option<QBins>,
     ) -> Result<Weights> {
         // weights
-        let beta_values = matched_beta
-            .select(&score_names)?
-            .to_ndarray::<Float32Type>(IndexOrder::Fortran)?;
+        let beta_values = match &q_bins {
+            Some(q_bins) => q_bins.weight_matrix(&matched_beta)?,
+            None => {
+                let columns = score_names
+                    .iter()
+                    .map(|score_name| {
+                        let weights = matched_beta.column(score_name)?.unpack::<FloatType>()?;
+                        Ok(weights
+                            .into_iter()
+                            .enumerate()
+                            .filter_map(|(row, v)| v.filter(|v| *v != 0.).map(|v| (row, v)))
+                            .collect())
+                    })
+                    .collect::<Result<Vec<Vec<(usize, Float)>>>>()?;
+                BetaMatrix::from_columns(matched_beta.height(), columns)
+            }
+        };
+        debug!(
+            "Got {} weight matrix with {} non-zero weights",
+            if beta_values.is_sparse() { "sparse" } else { "dense" },
+            beta_values.nnz()
+        );
         // get sid index in bfile
         let sid_idx: Vec<isize> = matched_beta
             .column(IDX)?
@@ -43,6 +127,7 @@ impl Weights {
             .into_no_null_iter()
             .map(|v| v as isize)
             .collect();
+        let genetic_models = get_genetic_models(&matched_beta)?;
         // if no freq, add freq
         if matched_beta.column(FREQ).is_err() {
             matched_beta = matched_beta
@@ -63,8 +148,157 @@ impl Weights {
             beta_values,
             sid_idx,
             status_freq_vec,
+            genetic_models,
             missing_strategy,
+            genotype_scale,
             score_names,
+            q_bins,
+            cohort_dosage: None,
+            chroms: None,
+        })
+    }
+
+    /// set [Weights::chroms] from CHR in bim of each matched snp, since CHR
+    /// may not be in beta when matched by ID
+    pub fn with_chroms(mut self, matched_beta: &DataFrame, bim: &DataFrame) -> Result<Weights> {
+        let chroms = matched_beta
+            .select([IDX])?
+            .left_join(&bim.select([IDX, CHR])?, [IDX], [IDX])?
+            .column(CHR)?
+            .utf8()?
+            .into_iter()
+            .map(|v| v.unwrap_or(NA_CHROM).to_owned())
+            .collect();
+        self.chroms = Some(chroms);
+        Ok(self)
+    }
+
+    /// whether the mean dosage of snp is needed, to impute or center missing
+    /// genotypes, or to resolve ambiguous snp
+    pub fn needs_dosage(&self) -> bool {
+        let scaled = self.genotype_scale != GenotypeScale::Raw;
+        let by_mean = match self.missing_strategy {
+            MissingStrategy::Impute => true,
+            MissingStrategy::Zero | MissingStrategy::Rescale => scaled,
+            MissingStrategy::Freq => false,
+        };
+        by_mean
+            || self
+                .status_freq_vec
+                .iter()
+                .any(|(status, _)| status.as_deref() == Some(AMBIGUOUS))
+    }
+
+    /// score of each ind for [Weights::score_names]
+    pub fn score(&self, gt: &Array2<f32>) -> Array2<Float> {
+        self.to_ranges(self.beta_values.score(gt))
+    }
+
+    /// partial score of each chromosome for [Weights::score_names], none if
+    /// [Weights::chroms] is not set
+    pub fn score_by_chrom(&self, gt: &Array2<f32>) -> Option<BTreeMap<String, Array2<Float>>> {
+        let chroms = self.chroms.as_ref()?;
+        let mut chrom_rows: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (row, chrom) in chroms.iter().enumerate() {
+            chrom_rows.entry(chrom.as_str()).or_default().push(row);
+        }
+        let partials = chrom_rows
+            .into_iter()
+            .map(|(chrom, rows)| {
+                let partial = self.to_ranges(self.beta_values.score_rows(gt, &rows));
+                (chrom.to_owned(), partial)
+            })
+            .collect();
+        Some(partials)
+    }
+
+    /// sum columns of Q range bins into scores of Q ranges
+    fn to_ranges(&self, score: Array2<Float>) -> Array2<Float> {
+        match &self.q_bins {
+            Some(q_bins) => q_bins.to_ranges(&score),
+            None => score,
+        }
+    }
+
+    /// rows (snp) with non-zero weight in a score of [Weights::score_names]
+    pub fn nonzero_rows(&self, col: usize) -> Vec<usize> {
+        match &self.q_bins {
+            Some(q_bins) => q_bins
+                .source_cols(col)
+                .into_iter()
+                .flat_map(|cc| self.beta_values.nonzero_rows(cc))
+                .collect(),
+            None => self.beta_values.nonzero_rows(col),
+        }
+    }
+
+    /// Get swap flag for each snp. [AMBIGUOUS] snp are resolved by comparing
+    /// FREQ with the observed ALT dosage, which is obtained by `get_dosage`
+    /// with column index of the snp. `get_dosage` is only called for
+    /// ambiguous snp.
+    pub fn get_swap_vec<F>(&self, mut get_dosage: F) -> Result<Vec<bool>>
+    where
+        F: FnMut(usize) -> Result<f32>,
+    {
+        let mut swap_vec = Vec::with_capacity(self.status_freq_vec.len());
+        for (cc, (status, freq)) in self.status_freq_vec.iter().enumerate() {
+            let swap_flag = match status.as_deref() {
+                Some(AMBIGUOUS) => {
+                    let freq =
+                        freq.ok_or_else(|| anyhow!("FREQ is needed to resolve ambiguous snp"))?;
+                    resolve_ambiguous_swap(freq, get_dosage(cc)?)
+                }
+                Some(v) => is_swap(v),
+                None => false,
+            };
+            swap_vec.push(swap_flag);
+        }
+        Ok(swap_vec)
+    }
+}
+
+/// genetic model from optional flag columns, additive if not present
+fn get_genetic_models(matched_beta: &DataFrame) -> Result<Vec<GeneticModel>> {
+    let get_flags = |name: &str| -> Result<Vec<bool>> {
+        match matched_beta.column(name) {
+            Ok(v) => Ok(v.bool()?.into_iter().map(|v| v.unwrap_or(false)).collect()),
+            Err(_) => Ok(vec![false; matched_beta.height()]),
+        }
+    };
+    let genetic_models = get_flags(IS_DOMINANT)?
+        .into_iter()
+        .zip(get_flags(IS_RECESSIVE)?)
+        .map(|flags| match flags {
+            (true, _) => GeneticModel::Dominant,
+            (_, true) => GeneticModel::Recessive,
+            _ => GeneticModel::Additive,
         })
+        .collect();
+    Ok(genetic_models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GeneticModel;
+
+    #[test]
+    fn test_fill_dosage() {
+        // p = 0.2, genotype frequencies under HWE are 0.64, 0.32 and 0.04
+        let dosage = 0.4;
+        let cases = [
+            (GeneticModel::Additive, 0.4),
+            (GeneticModel::Dominant, 0.36),
+            (GeneticModel::Recessive, 0.04),
+        ];
+        for (genetic_model, expected) in cases {
+            let coded = genetic_model.code(genetic_model.fill_dosage(dosage));
+            assert!((coded - expected).abs() < 1e-6, "{:?}", genetic_model);
+        }
+        // observed genotypes
+        assert_eq!(GeneticModel::Dominant.code(2.), 1.);
+        assert_eq!(GeneticModel::Recessive.code(1.), 0.);
+        assert_eq!(GeneticModel::Recessive.code(2.), 1.);
+        // missing genotypes of Zero are still zero
+        assert_eq!(GeneticModel::Recessive.code(GeneticModel::Recessive.fill_dosage(0.)), 0.);
     }
 }
@@ -7,3 +7,4 @@ pub mod join;
 pub mod meta;
 pub mod predict;
 pub mod metrics;
+pub mod select;
@@ -1,16 +1,20 @@
 use anyhow::{anyhow, Result};
 
-use crate::join::betahandler::QRange;
+use crate::join::{betahandler::QRange, clump::ClumpArg};
 
 /// auto generated column name
 pub const STATUS: &str = "STATUS";
 pub const RANK: &str = "RANK";
+pub const MATCH_RULE: &str = "MATCH_RULE";
 
 #[derive(Clone, Debug, Copy)]
 pub enum MissingStrategy {
     Impute,
     Zero,
     Freq,
+    /// missing genotypes are dropped, and the score of each ind is rescaled
+    /// by `total_variants / nonmissing_variants`
+    Rescale,
 }
 
 impl MissingStrategy {
@@ -19,10 +23,11 @@ impl MissingStrategy {
             "Impute" => MissingStrategy::Impute,
             "Zero" => MissingStrategy::Zero,
             "Freq" => MissingStrategy::Freq,
+            "Rescale" => MissingStrategy::Rescale,
             _ => {
                 return Err(anyhow!(
                     "Argument missing_strategy should be one of the following: [ Impute, Zero, \
-                     Freq ], got {}",
+                     Freq, Rescale ], got {}",
                     strategy
                 ))
             }
@@ -31,6 +36,127 @@ impl MissingStrategy {
     }
 }
 
+/// How score is reported, as in plink2 `--score`
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum ScoreMode {
+    /// sum of dosage × weight, `cols=scoresums` of plink2
+    Sum,
+    /// sum divided by the number of alleles of matched snp, the default of
+    /// plink2. Missing genotypes are excluded from the number of alleles for
+    /// [MissingStrategy::Zero] and [MissingStrategy::Rescale], as
+    /// `no-mean-imputation` of plink2.
+    Avg,
+}
+
+impl ScoreMode {
+    pub fn new(mode: &str) -> Result<ScoreMode> {
+        let my_mode = match mode {
+            "Sum" => ScoreMode::Sum,
+            "Avg" => ScoreMode::Avg,
+            _ => {
+                return Err(anyhow!(
+                    "Argument score_mode should be one of the following: [ Sum, Avg ], got {}",
+                    mode
+                ))
+            }
+        };
+        Ok(my_mode)
+    }
+}
+
+/// Transform of dosage before scoring, as modifiers of plink2 `--score`.
+/// Dosage is centered by the mean dosage of the effect allele `2p`, which is
+/// the value filled into missing genotypes, so missing genotypes contribute
+/// nothing.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum GenotypeScale {
+    /// dosage as is
+    Raw,
+    /// `dosage - 2p`, `center` of plink2
+    Center,
+    /// `(dosage - 2p) / sqrt(2p(1 - p))`, `variance-standardize` of plink2.
+    /// Monomorphic snp contribute nothing.
+    Standardize,
+}
+
+impl GenotypeScale {
+    pub fn new(scale: &str) -> Result<GenotypeScale> {
+        let my_scale = match scale {
+            "Raw" => GenotypeScale::Raw,
+            "Center" => GenotypeScale::Center,
+            "Standardize" => GenotypeScale::Standardize,
+            _ => {
+                return Err(anyhow!(
+                    "Argument genotype_scale should be one of the following: [ Raw, Center, \
+                     Standardize ], got {}",
+                    scale
+                ))
+            }
+        };
+        Ok(my_scale)
+    }
+}
+
+/// How to deal with ambiguous (palindromic, A/T or C/G) snp, whose strand can
+/// not be told by alleles.
+#[derive(Clone, Debug, Copy)]
+pub enum AmbiguousPolicy {
+    /// remove ambiguous snp
+    Drop,
+    /// keep ambiguous snp and match them by allele as is
+    Keep,
+    /// compare FREQ in beta with frequency in bfile to decide whether to swap
+    Freq,
+}
+
+impl AmbiguousPolicy {
+    pub fn new(policy: &str) -> Result<AmbiguousPolicy> {
+        let my_policy = match policy {
+            "Drop" => AmbiguousPolicy::Drop,
+            "Keep" => AmbiguousPolicy::Keep,
+            "Freq" => AmbiguousPolicy::Freq,
+            _ => {
+                return Err(anyhow!(
+                    "Argument ambiguous should be one of the following: [ Drop, Keep, Freq ], \
+                     got {}",
+                    policy
+                ))
+            }
+        };
+        Ok(my_policy)
+    }
+}
+
+/// How to match snp between weights and bim
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// match by CHR and POS
+    Pos,
+    /// match by ID only
+    Id,
+    /// match by ID first, structured ID such as `chr1:12345:A:G` is compared
+    /// with ID synthesized from bim, then fallback to CHR and POS
+    IdPos,
+}
+
+impl MatchMode {
+    pub fn new(mode: &str) -> Result<MatchMode> {
+        let my_mode = match mode {
+            "Pos" => MatchMode::Pos,
+            "Id" => MatchMode::Id,
+            "IdPos" => MatchMode::IdPos,
+            _ => {
+                return Err(anyhow!(
+                    "Argument match_mode should be one of the following: [ Pos, Id, IdPos ], got \
+                     {}",
+                    mode
+                ))
+            }
+        };
+        Ok(my_mode)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum QrangeOrScorenames<'a> {
     QRange(QRange<'a>),
@@ -40,10 +166,22 @@ pub enum QrangeOrScorenames<'a> {
 pub struct MetaArg<'a> {
     pub batch_size: usize,
     pub thread_num: usize,
-    pub match_id_flag: bool,
+    pub match_mode: MatchMode,
+    /// whether weights and bim are sorted by CHR and POS, and matched by a
+    /// sorted merge
+    pub sorted_match: bool,
+    /// whether to write disposition of each weight row to *.match.tsv
+    pub match_report: bool,
     pub missing_strategy: MissingStrategy,
+    pub score_mode: ScoreMode,
+    pub genotype_scale: GenotypeScale,
+    pub ambiguous_policy: AmbiguousPolicy,
     pub out_prefix: &'a str,
     pub q_range_enum: QrangeOrScorenames<'a>,
+    /// clump snp by LD before Q ranges are applied
+    pub clump_arg: Option<ClumpArg>,
+    /// whether to keep the partial score of each chromosome
+    pub score_per_chrom: bool,
 }
 
 impl<'a> MetaArg<'a> {
@@ -1,10 +1,11 @@
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use betareader::{Float, FloatType};
 use genoreader::meta::PHENO;
 use polars::{
     lazy::dsl::{col, lit, pearson_corr, spearman_rank_corr},
     series::Series,
-    prelude::{DataFrame, DataType,IntoLazy}
+    prelude::{DataFrame, DataType, IntoLazy, NamedFrom}
 };
 
 /// calculate correlation between score and phenotype
@@ -24,7 +25,8 @@ pub fn cal_cor_fn(scores: &DataFrame, score_names: &Vec<&str>) -> Result<DataFra
             .with_column(lit(i.to_owned()).alias("Name"))
             .groupby(["Name"])
             .agg([
-                pearson_corr(col(PHENO), col(i), 0)
+                // polars divides the covariance by n - 1, so the std must too
+                pearson_corr(col(PHENO), col(i), 1)
                     .cast(DataType::Float32)
                     .alias(p_name),
                 spearman_rank_corr(col(PHENO), col(i), 0, true)
@@ -38,3 +40,142 @@ pub fn cal_cor_fn(scores: &DataFrame, score_names: &Vec<&str>) -> Result<DataFra
     Ok(cor_res)
 }
 
+/// calculate AUC of score for binary phenotype, where the larger one of the
+/// two phenotype values is case
+pub fn cal_auc_fn(scores: &DataFrame, score_names: &[&str]) -> Result<DataFrame> {
+    let pheno: Vec<Option<f32>> = scores.column(PHENO)?.f32()?.into_iter().collect();
+    let mut levels: Vec<f32> = pheno
+        .iter()
+        .flatten()
+        .copied()
+        .filter(|v| !v.is_nan())
+        .collect();
+    levels.sort_by(f32::total_cmp);
+    levels.dedup();
+    if levels.len() != 2 {
+        return Err(anyhow!(
+            "AUC needs binary PHENO, got {} distinct values",
+            levels.len()
+        ));
+    }
+
+    let mut aucs = vec![];
+    for i in score_names {
+        let mut pairs: Vec<(Float, bool)> = scores
+            .column(i)?
+            .unpack::<FloatType>()?
+            .into_iter()
+            .zip(&pheno)
+            .filter_map(|(score, pheno)| match (score, pheno) {
+                (Some(score), Some(pheno)) if !score.is_nan() && !pheno.is_nan() => {
+                    Some((score, *pheno == levels[1]))
+                }
+                _ => None,
+            })
+            .collect();
+        aucs.push(cal_auc(&mut pairs));
+    }
+    let auc_res = DataFrame::new(vec![
+        Series::new("Name", score_names),
+        Series::new("auc", aucs),
+    ])?;
+    Ok(auc_res)
+}
+
+/// Mann-Whitney U of (score, is case) scaled to [0, 1], tied scores get the
+/// average rank
+fn cal_auc(pairs: &mut [(Float, bool)]) -> Option<f32> {
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let n_case = pairs.iter().filter(|v| v.1).count();
+    let n_control = pairs.len() - n_case;
+    if n_case == 0 || n_control == 0 {
+        return None;
+    }
+    let mut case_rank_sum = 0_f64;
+    let mut start = 0;
+    while start < pairs.len() {
+        let end = start
+            + pairs[start..]
+                .iter()
+                .take_while(|v| v.0 == pairs[start].0)
+                .count();
+        // ranks from 1
+        let rank = (start + end + 1) as f64 / 2.;
+        case_rank_sum += rank * pairs[start..end].iter().filter(|v| v.1).count() as f64;
+        start = end;
+    }
+    let u = case_rank_sum - (n_case * (n_case + 1)) as f64 / 2.;
+    Some((u / (n_case * n_control) as f64) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use betareader::Float;
+    use genoreader::meta::PHENO;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use super::{cal_auc, cal_auc_fn, cal_cor_fn};
+
+    fn auc(controls: &[Float], cases: &[Float]) -> Option<f32> {
+        let mut pairs: Vec<(Float, bool)> = controls
+            .iter()
+            .map(|v| (*v, false))
+            .chain(cases.iter().map(|v| (*v, true)))
+            .collect();
+        cal_auc(&mut pairs)
+    }
+
+    #[test]
+    fn test_cal_auc() {
+        assert_eq!(auc(&[1., 2.], &[3., 4.]), Some(1.));
+        assert_eq!(auc(&[3., 4.], &[1., 2.]), Some(0.));
+        // a tie between case and control counts as half
+        assert_eq!(auc(&[1., 1.], &[1., 1.]), Some(0.5));
+        let tied = auc(&[1., 2., 2.], &[2., 3.]).unwrap();
+        assert!((tied - 5. / 6.).abs() < 1e-6);
+        assert_eq!(auc(&[1., 2.], &[]), None);
+    }
+
+    #[test]
+    fn test_cal_cor_fn() {
+        let scores = DataFrame::new(vec![
+            Series::new(PHENO, [1_f32, 2., 3., 4.]),
+            Series::new("s1", [0.1 as Float, 0.2, 0.3, 0.4]),
+        ])
+        .unwrap();
+        let cor = cal_cor_fn(&scores, &vec!["s1"]).unwrap();
+        let pearson: Vec<Option<f32>> = cor
+            .column("pearson")
+            .unwrap()
+            .f32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert!((pearson[0].unwrap() - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cal_auc_fn() {
+        let scores = DataFrame::new(vec![
+            Series::new(PHENO, [Some(1_f32), Some(2.), Some(2.), None, Some(1.)]),
+            Series::new("s1", [0.1 as Float, 0.3, 0.2, 0.5, Float::NAN]),
+        ])
+        .unwrap();
+        let auc = cal_auc_fn(&scores, &["s1"]).unwrap();
+        let auc: Vec<Option<f32>> = auc
+            .column("auc")
+            .unwrap()
+            .f32()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(auc, vec![Some(1.)]);
+
+        let scores = DataFrame::new(vec![
+            Series::new(PHENO, [1_f32, 2., 3.]),
+            Series::new("s1", [0.1 as Float, 0.3, 0.2]),
+        ])
+        .unwrap();
+        assert!(cal_auc_fn(&scores, &["s1"]).is_err());
+    }
+}
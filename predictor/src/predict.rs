@@ -1,5 +1,7 @@
+mod batch_score;
 mod score;
 mod utils;
 
+pub use batch_score::{BatchScore, ChromScore};
 pub use score::{cal_score_array, cal_score_array_freq_reader, cal_scores};
-pub use utils::{get_empty_score, score_to_frame};
+pub use utils::{finish_score, get_empty_score, score_to_frame, AlleleCount};
@@ -0,0 +1,222 @@
+//! Score of a batch of snp or samples, which is summed over snp batches. With
+//! partial scores by chromosome, the partial score of each chromosome is kept
+//! in its own accumulator. The total is then summed from partial scores in
+//! order of chromosome after rescaling, so that partial scores in the output
+//! sum exactly to the total.
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    ops::Add,
+};
+
+use anyhow::Result;
+use betareader::{Float, CHR};
+use genoreader::meta::{FID, IID};
+use ndarray::Array2;
+use polars::prelude::{DataFrame, NamedFrom, Series};
+
+use super::utils::{allele_count, finish_score, score_to_frame, AlleleCount};
+use crate::{
+    join::weight::Weights,
+    meta::{MissingStrategy, ScoreMode},
+};
+
+/// partial score of each chromosome
+pub type ChromScore = BTreeMap<String, Array2<Float>>;
+
+#[derive(Clone, Debug)]
+pub struct BatchScore {
+    pub score: Array2<Float>,
+    /// partial score of each chromosome, only if [Weights::chroms] is set
+    pub chroms: Option<ChromScore>,
+    pub allele_count: AlleleCount,
+}
+
+impl BatchScore {
+    /// score of processed genotype, `missing_iid` is the inds with missing
+    /// genotype of each snp
+    pub fn new(weights: &Weights, gt: &Array2<f32>, missing_iid: &[Vec<u32>]) -> BatchScore {
+        let chroms = weights.score_by_chrom(gt);
+        let score = match &chroms {
+            Some(chroms) => sum_chroms(chroms),
+            None => weights.score(gt),
+        };
+        BatchScore {
+            score,
+            chroms,
+            allele_count: allele_count(weights, gt.nrows(), missing_iid),
+        }
+    }
+
+    /// Rescale or average by alleles of all batches, see [finish_score].
+    /// Partial scores are rescaled in the same way, and summed into the total
+    /// again.
+    pub fn finish(&mut self, score_mode: ScoreMode, missing_strategy: MissingStrategy) {
+        match &mut self.chroms {
+            Some(chroms) => {
+                for partial in chroms.values_mut() {
+                    finish_score(score_mode, missing_strategy, partial, &self.allele_count);
+                }
+                self.score = sum_chroms(chroms);
+            }
+            None => finish_score(
+                score_mode,
+                missing_strategy,
+                &mut self.score,
+                &self.allele_count,
+            ),
+        }
+    }
+
+    /// Frame of total scores, and frame of partial scores in long format with
+    /// a row for each ind and chromosome
+    pub fn to_frames(
+        self,
+        fam: &DataFrame,
+        score_names: &[String],
+    ) -> Result<(DataFrame, Option<DataFrame>)> {
+        let chrom_frame = match self.chroms {
+            Some(chroms) => {
+                let mut columns = vec![FID, IID, CHR];
+                columns.extend(score_names.iter().map(String::as_str));
+                let mut chrom_frame: Option<DataFrame> = None;
+                for (chrom, partial) in chroms {
+                    let mut frame = score_to_frame(fam, partial, score_names)?;
+                    frame.with_column(Series::new(CHR, vec![chrom; fam.height()]))?;
+                    let frame = frame.select(&columns)?;
+                    chrom_frame = match chrom_frame {
+                        Some(v) => Some(v.vstack(&frame)?),
+                        None => Some(frame),
+                    };
+                }
+                chrom_frame
+            }
+            None => None,
+        };
+        let score_frame = score_to_frame(fam, self.score, score_names)?;
+        Ok((score_frame, chrom_frame))
+    }
+}
+
+impl Add for BatchScore {
+    type Output = BatchScore;
+
+    fn add(self, other: BatchScore) -> BatchScore {
+        let chroms = match (self.chroms, other.chroms) {
+            (Some(mut chroms), Some(other_chroms)) => {
+                for (chrom, partial) in other_chroms {
+                    match chroms.entry(chrom) {
+                        Entry::Occupied(mut v) => *v.get_mut() += &partial,
+                        Entry::Vacant(v) => {
+                            v.insert(partial);
+                        }
+                    }
+                }
+                Some(chroms)
+            }
+            (chroms, other_chroms) => chroms.or(other_chroms),
+        };
+        BatchScore {
+            score: self.score + other.score,
+            chroms,
+            allele_count: self.allele_count + other.allele_count,
+        }
+    }
+}
+
+/// sum of partial scores in order of chromosome
+fn sum_chroms(chroms: &ChromScore) -> Array2<Float> {
+    let mut partials = chroms.values();
+    let first = partials.next().cloned().unwrap_or_default();
+    partials.fold(first, |sum, partial| sum + partial)
+}
+
+#[cfg(test)]
+mod tests {
+    use betareader::{Float, FloatType, CHR};
+    use genoreader::meta::{FID, IID, PHENO};
+    use ndarray::{array, Array2};
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use super::BatchScore;
+    use crate::{
+        join::{
+            beta_matrix::BetaMatrix,
+            weight::{GeneticModel, Weights},
+        },
+        meta::{GenotypeScale, MissingStrategy, ScoreMode},
+    };
+
+    /// a score with weight 1, 2, ... on snp of `chroms`
+    fn weights(chroms: &[&str]) -> Weights {
+        let n_snp = chroms.len();
+        let columns = vec![(0..n_snp).map(|row| (row, (row + 1) as Float)).collect()];
+        Weights {
+            beta_values: BetaMatrix::from_columns(n_snp, columns),
+            sid_idx: (0..n_snp as isize).collect(),
+            status_freq_vec: vec![(None, None); n_snp],
+            genetic_models: vec![GeneticModel::Additive; n_snp],
+            missing_strategy: MissingStrategy::Rescale,
+            genotype_scale: GenotypeScale::Raw,
+            score_names: vec!["s1".to_owned()],
+            q_bins: None,
+            cohort_dosage: None,
+            chroms: Some(chroms.iter().map(|v| v.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn test_batch_score_chroms() {
+        let chrom_weights = weights(&["1", "1", "2"]);
+        let gt: Array2<f32> = array![[1., 0., 2.], [2., 1., 0.]];
+        let batch_score = BatchScore::new(&chrom_weights, &gt, &[vec![], vec![], vec![1]]);
+        let chroms = batch_score.chroms.as_ref().unwrap();
+        assert_eq!(chroms["1"], array![[1.], [4.]]);
+        assert_eq!(chroms["2"], array![[6.], [0.]]);
+        assert_eq!(batch_score.score, array![[7.], [4.]]);
+
+        // partial scores of another snp batch are merged by chromosome
+        let other = weights(&["2"]);
+        let gt: Array2<f32> = array![[1.], [1.]];
+        let mut batch_score = batch_score + BatchScore::new(&other, &gt, &[vec![]]);
+        let chroms = batch_score.chroms.as_ref().unwrap();
+        assert_eq!(chroms["2"], array![[7.], [1.]]);
+        assert_eq!(batch_score.score, array![[8.], [5.]]);
+
+        // partial scores are rescaled by alleles of all chromosomes, 8 of 6
+        // for the second ind, and still sum to the total
+        batch_score.finish(ScoreMode::Sum, MissingStrategy::Rescale);
+        let chroms = batch_score.chroms.as_ref().unwrap();
+        assert_eq!(chroms["1"], array![[1.], [4. * 8. / 6.]]);
+        assert_eq!(chroms["2"], array![[7.], [8. / 6.]]);
+        assert_eq!(batch_score.score, &chroms["1"] + &chroms["2"]);
+    }
+
+    #[test]
+    fn test_to_frames() {
+        let fam = DataFrame::new(vec![
+            Series::new(FID, ["f1", "f2"]),
+            Series::new(IID, ["i1", "i2"]),
+            Series::new(PHENO, [1_f32, 2.]),
+        ])
+        .unwrap();
+        let chrom_weights = weights(&["1", "2"]);
+        let gt: Array2<f32> = array![[1., 1.], [0., 2.]];
+        let batch_score = BatchScore::new(&chrom_weights, &gt, &[vec![], vec![]]);
+        let (score_frame, chrom_frame) = batch_score
+            .to_frames(&fam, &chrom_weights.score_names)
+            .unwrap();
+        assert_eq!(score_frame.shape(), (2, 4));
+        let chrom_frame = chrom_frame.unwrap();
+        // a row for each ind and chromosome
+        assert_eq!(chrom_frame.get_column_names(), vec![FID, IID, CHR, "s1"]);
+        assert_eq!(chrom_frame.height(), 4);
+        let s1: Vec<Option<Float>> = chrom_frame
+            .column("s1")
+            .unwrap()
+            .unpack::<FloatType>()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(s1, vec![Some(1.), Some(0.), Some(2.), Some(4.)]);
+    }
+}
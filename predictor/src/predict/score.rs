@@ -2,79 +2,138 @@ use std::cmp;
 
 use anyhow::Result;
 use genoreader::{BedReaderNoLib, ReadGenotype, FreqBedReader};
-use ndarray::Array2;
 use polars::prelude::DataFrame;
 
-use super::utils::{process_gt, score_to_frame};
-use crate::join::weight::Weights;
-use crate::join::SWAP;
-use crate::meta::MissingStrategy;
+use super::{
+    batch_score::BatchScore,
+    utils::{apply_genetic_model, process_gt, scale_gt},
+};
+use crate::join::weight::{GeneticModel, Weights};
+use crate::meta::{GenotypeScale, MissingStrategy, ScoreMode};
 
+/// Score of a batch of samples, partial scores by chromosome if
+/// [Weights::chroms] is set, and number of missing genotypes of each snp
 pub fn cal_scores(
     weights: &Weights,
     i: usize,
     batch_size: usize,
     bed: &BedReaderNoLib,
     score_names: &[String],
-) -> Result<DataFrame> {
+    score_mode: ScoreMode,
+) -> Result<(DataFrame, Option<DataFrame>, Vec<u32>)> {
     // cal batch
     let _start = i * batch_size;
     let _end = cmp::min((i + 1) * batch_size, bed.iid_count);
     let iid = Some(bed.iid_idx[_start.._end].to_vec());
-    // get gt
-    let score = cal_score_array(bed, weights, &iid)?;
+    // get gt, all snp are in weights so score can be averaged here
+    let (mut score, missing_iid) = cal_score_array(bed, weights, &iid)?;
+    score.finish(score_mode, weights.missing_strategy);
+    let n_missing = missing_iid.iter().map(|v| v.len() as u32).collect();
 
     // get beta and cal score
     let batch_fam = bed.get_ind(&iid, false)?;
-    let score_frame: DataFrame = score_to_frame(&batch_fam, score, score_names)?;
-    Ok(score_frame)
+    let (score_frame, chrom_frame) = score.to_frames(&batch_fam, score_names)?;
+    Ok((score_frame, chrom_frame, n_missing))
 }
 
 
 
+/// score and number of alleles of each ind, see [BatchScore], and the inds
+/// with missing genotype of each snp
 pub fn cal_score_array(
     bed: &BedReaderNoLib,
     weights: &Weights,
     iid_idx: &Option<Vec<isize>>,
-) -> Result<Array2<f32>> {
+) -> Result<(BatchScore, Vec<Vec<u32>>)> {
     let mut gt= bed.get_geno(&Some(weights.sid_idx.clone()), iid_idx)?;
 
     // process gt
-    process_gt(weights, &mut gt)?;
+    let missing_iid = process_gt(weights, &mut gt)?;
 
     // get beta and cal score
-    let score = gt.dot(&weights.beta_values);
-    Ok(score)
+    let score = BatchScore::new(weights, &gt, &missing_iid);
+    Ok((score, missing_iid))
 }
 
+/// score with number of alleles of each ind and score, and FREQ filled into
+/// missing genotypes for Impute
 pub fn cal_score_array_freq_reader(
     reader: &mut FreqBedReader,
     weights: &Weights,
-) -> Result<(Array2<f32>,Option<Vec<f32>>)> {
+) -> Result<(BatchScore, Option<Vec<f32>>)> {
 
-    let (stat_vec, freq_vec): (Vec<_>, Vec<_>) = weights.status_freq_vec.iter().cloned().unzip();
-    let stat_vec: Vec<bool> = stat_vec.into_iter().map(|x| match x {
-        None => false,
-        Some(v) => v == SWAP,
-    }).collect();
+    let freq_vec: Vec<Option<f32>> = weights.status_freq_vec.iter().map(|(_, f)| *f).collect();
+    // ambiguous snp are resolved by reading their frequency first
+    let stat_vec: Vec<bool> = weights.get_swap_vec(|cc| {
+        let freq = reader.read_freq(&weights.sid_idx[cc..cc + 1])?;
+        Ok(freq[0])
+    })?;
 
-    let (gt, freq_vec) = match weights.missing_strategy {
-        MissingStrategy::Impute => {
-            reader.read_snp(&weights.sid_idx, Some(&stat_vec), None)?
+    // missing genotypes are filled by the mean to be zero after centering
+    let scaled = weights.genotype_scale != GenotypeScale::Raw;
+    // filled value is swapped with genotype, and then coded by genetic model
+    let coded = weights.genetic_models.iter().any(|v| *v != GeneticModel::Additive);
+    let code_fill = |fill_vec: Vec<f32>| -> Vec<f32> {
+        fill_vec
+            .iter()
+            .zip(&stat_vec)
+            .zip(&weights.genetic_models)
+            .map(|((fill, swap_flag), genetic_model)| match swap_flag {
+                true => 2. - genetic_model.fill_dosage(2. - fill),
+                false => genetic_model.fill_dosage(*fill),
+            })
+            .collect()
+    };
+    let (mut gt, freq_vec, fill_vec) = match (weights.missing_strategy, scaled) {
+        (MissingStrategy::Impute, _)
+        | (MissingStrategy::Zero | MissingStrategy::Rescale, true) if !coded => {
+            let (gt, freq_vec) = reader.read_snp(&weights.sid_idx, Some(&stat_vec), None)?;
+            let fill_vec = freq_vec.clone().unwrap_or_default();
+            (gt, freq_vec, fill_vec)
+        },
+        (MissingStrategy::Impute, _)
+        | (MissingStrategy::Zero | MissingStrategy::Rescale, true) => {
+            // the mean is coded before filled, so it is read first
+            let freq_vec = reader.read_freq(&weights.sid_idx)?;
+            let fill_vec = code_fill(freq_vec.clone());
+            let (gt, _) = reader.read_snp(&weights.sid_idx, Some(&stat_vec), Some(&fill_vec))?;
+            (gt, Some(freq_vec), fill_vec)
         },
-        MissingStrategy::Zero => {
-            let freq_vec: Vec<f32> = vec![0.;weights.sid_idx.len()];
-            reader.read_snp(&weights.sid_idx, Some(&stat_vec), Some(&freq_vec))?
+        (MissingStrategy::Zero | MissingStrategy::Rescale, false) => {
+            // filled value is swapped with genotype, so it is 2 to be 0 after swap
+            let fill_vec: Vec<f32> = code_fill(
+                stat_vec.iter().map(|swap_flag| if *swap_flag { 2. } else { 0. }).collect(),
+            );
+            let (gt, freq_vec) =
+                reader.read_snp(&weights.sid_idx, Some(&stat_vec), Some(&fill_vec))?;
+            (gt, freq_vec, fill_vec)
         },
-        MissingStrategy::Freq => {
-            let freq_vec: Vec<f32> = freq_vec.into_iter().map(|x| x.unwrap_or(0.)).collect();
-            reader.read_snp(&weights.sid_idx, Some(&stat_vec), Some(&freq_vec))?
+        (MissingStrategy::Freq, _) => {
+            let fill_vec: Vec<f32> =
+                code_fill(freq_vec.into_iter().map(|x| x.unwrap_or(0.)).collect());
+            let (gt, freq_vec) =
+                reader.read_snp(&weights.sid_idx, Some(&stat_vec), Some(&fill_vec))?;
+            (gt, freq_vec, fill_vec)
         }
     };
+    // genotype has been swapped and filled in bit map decoding
+    apply_genetic_model(weights, &mut gt);
+    if scaled {
+        // filled value is swapped with genotype, and coded into the center
+        let centers: Vec<f32> = fill_vec
+            .iter()
+            .zip(&stat_vec)
+            .zip(&weights.genetic_models)
+            .map(|((fill, swap_flag), genetic_model)| {
+                genetic_model.code(if *swap_flag { 2. - fill } else { *fill })
+            })
+            .collect();
+        scale_gt(weights.genotype_scale, &weights.genetic_models, &centers, &mut gt);
+    }
 
     // get beta and cal score
-    let score = gt.dot(&weights.beta_values);
-    Ok((score,freq_vec))
+    let score = BatchScore::new(weights, &gt, reader.last_missing_iid());
+    Ok((score, freq_vec))
 }
 
 
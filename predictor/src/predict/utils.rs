@@ -1,4 +1,7 @@
+use std::ops::Add;
+
 use anyhow::{anyhow, Result};
+use betareader::{Float, FLOAT_DTYPE};
 use genoreader::meta::{FID, IID, PHENO};
 use ndarray::prelude::*;
 use polars::{
@@ -7,8 +10,8 @@ use polars::{
 };
 
 use crate::{
-    join::{weight::Weights, SWAP},
-    meta::MissingStrategy,
+    join::weight::{GeneticModel, Weights},
+    meta::{GenotypeScale, MissingStrategy, ScoreMode},
 };
 
 fn missing_as_freq(freq: f32, swap_flag: bool) -> Box<dyn FnMut(f32) -> f32> {
@@ -19,54 +22,200 @@ fn missing_as_freq(freq: f32, swap_flag: bool) -> Box<dyn FnMut(f32) -> f32> {
     }
 }
 
-// This function swap and fill na in a single walk through of weights
-pub fn process_gt(weights: &Weights, gt: &mut Array2<f32>) -> Result<()> {
+/// mean of non missing genotype
+fn non_na_mean(col: ArrayView1<f32>) -> f32 {
+    let (non_na_count, sum) = col.into_iter().fold((0_f32, 0_f32), |(mut n, mut s), x| {
+        if !x.is_nan() {
+            n += 1.;
+            s += x;
+        }
+        (n, s)
+    });
+    sum / non_na_count
+}
+
+// This function swap and fill na in a single walk through of weights. Return
+// the inds with missing genotype of each snp.
+pub fn process_gt(weights: &Weights, gt: &mut Array2<f32>) -> Result<Vec<Vec<u32>>> {
     // https://stackoverflow.com/questions/73318562/how-to-iterate-over-two-different-series-dataframes-and-how-to-access-a-specific
 
     let mut freq: f32;
     let mut my_fn: Box<dyn FnMut(f32) -> f32>;
-    let swap_identifier = &Some(SWAP.to_owned());
-    for (cc, (status, default_freq)) in weights.status_freq_vec.iter().enumerate() {
-        // use unwrap here since it is unliekly to be None.
-        let swap_flag = status == swap_identifier;
-
-        // deal with missing with different strategy
+    let mut missing_iid = Vec::with_capacity(weights.sid_idx.len());
+    let mut centers = Vec::with_capacity(weights.sid_idx.len());
+    let scaled = weights.genotype_scale != GenotypeScale::Raw;
+    // mean of the column before swap, of the whole cohort if it is given, so
+    // that it does not depend on the batch of samples
+    let mean_dosage = |gt: &Array2<f32>, cc: usize| match &weights.cohort_dosage {
+        Some(v) => v[cc],
+        None => non_na_mean(gt.slice(s![.., cc])),
+    };
+    // ambiguous snp are resolved by the mean of the column before swap
+    let swap_vec = weights.get_swap_vec(|cc| Ok(mean_dosage(gt, cc)))?;
+    for (cc, ((_, default_freq), swap_flag)) in
+        weights.status_freq_vec.iter().zip(swap_vec).enumerate()
+    {
+        missing_iid.push(
+            gt.slice(s![.., cc])
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| x.is_nan())
+                .map(|(row, _)| row as u32)
+                .collect(),
+        );
+        // deal with missing with different strategy, missing genotypes are
+        // filled by the mean to be zero after centering
+        let genetic_model = weights.genetic_models[cc];
         freq = match weights.missing_strategy {
-            MissingStrategy::Zero => 0.,
+            MissingStrategy::Zero | MissingStrategy::Rescale if !scaled => 0.,
             MissingStrategy::Freq => match default_freq {
                 Some(v) => *v,
                 None => return Err(anyhow!("Got None in Series FREQ")),
             },
-            MissingStrategy::Impute => {
+            MissingStrategy::Impute | MissingStrategy::Zero | MissingStrategy::Rescale => {
                 // cal non na mean
-                let (non_na_count, sum) =
-                    gt.slice(s![.., cc])
-                        .into_iter()
-                        .fold((0_f32, 0_f32), |(mut n, mut s), x| {
-                            if !x.is_nan() {
-                                n += 1.;
-                                s += x;
-                            }
-                            (n, s)
-                        });
+                let mean = mean_dosage(gt, cc);
                 if swap_flag {
-                    2. - (sum / non_na_count)
+                    2. - mean
                 } else {
-                    sum / non_na_count
+                    mean
                 }
             }
         };
+        // filled dosage is coded into the expected coded dosage
+        freq = genetic_model.fill_dosage(freq);
+        centers.push(genetic_model.code(freq));
         // function factory
         my_fn = missing_as_freq(freq, swap_flag);
         // apply on gt
         gt.slice_mut(s![.., cc]).mapv_inplace(my_fn);
     }
-    Ok(())
+    apply_genetic_model(weights, gt);
+    scale_gt(weights.genotype_scale, &weights.genetic_models, &centers, gt);
+    Ok(missing_iid)
+}
+
+/// Center or standardize each genotype column by its center, which is the
+/// mean coded dosage of the effect allele, `2p` for additive model, see
+/// [GenotypeScale]
+pub fn scale_gt(
+    genotype_scale: GenotypeScale,
+    genetic_models: &[GeneticModel],
+    centers: &[f32],
+    gt: &mut Array2<f32>,
+) {
+    if genotype_scale == GenotypeScale::Raw {
+        return;
+    }
+    for (cc, (center, genetic_model)) in centers.iter().zip(genetic_models).enumerate() {
+        let variance = genetic_model.variance(*center);
+        let factor = match genotype_scale {
+            GenotypeScale::Standardize if variance > 0. => 1. / variance.sqrt(),
+            GenotypeScale::Standardize => 0.,
+            _ => 1.,
+        };
+        gt.slice_mut(s![.., cc])
+            .mapv_inplace(|x| (x - center) * factor);
+    }
+}
+
+/// Number of alleles of each score, two for each snp with non-zero weight in
+/// the score, summed over snp batches
+#[derive(Clone, Debug)]
+pub struct AlleleCount {
+    /// alleles of matched snp of each score
+    pub total: Array1<Float>,
+    /// alleles of non-missing genotypes of each ind (row) and score (column)
+    pub nonmissing: Array2<Float>,
+}
+
+impl Add for AlleleCount {
+    type Output = AlleleCount;
+
+    fn add(self, other: AlleleCount) -> AlleleCount {
+        AlleleCount {
+            total: self.total + other.total,
+            nonmissing: self.nonmissing + other.nonmissing,
+        }
+    }
+}
+
+/// Count alleles of each score from snp with non-zero weight in it, where
+/// `missing_iid` is the inds with missing genotype of each snp, see
+/// [Weights::nonzero_rows]
+pub fn allele_count(weights: &Weights, n_ind: usize, missing_iid: &[Vec<u32>]) -> AlleleCount {
+    let n_score = weights.score_names.len();
+    let mut total = Array1::zeros(n_score);
+    let mut nonmissing = Array2::zeros((n_ind, n_score));
+    for (col, (total, mut nonmissing)) in total.iter_mut().zip(nonmissing.columns_mut()).enumerate()
+    {
+        let rows = weights.nonzero_rows(col);
+        *total = 2. * rows.len() as Float;
+        nonmissing.fill(*total);
+        for row in rows {
+            for ind in &missing_iid[row] {
+                nonmissing[*ind as usize] -= 2.;
+            }
+        }
+    }
+    AlleleCount { total, nonmissing }
+}
+
+/// Rescale each score of each ind by `total / nonmissing` for
+/// [MissingStrategy::Rescale], and divide it by the number of alleles for
+/// [ScoreMode::Avg], where missing genotypes are excluded for
+/// [MissingStrategy::Zero] and [MissingStrategy::Rescale], as
+/// `no-mean-imputation` of plink2. Score of ind without any non-missing
+/// genotype is NaN.
+pub fn finish_score(
+    score_mode: ScoreMode,
+    missing_strategy: MissingStrategy,
+    score: &mut Array2<Float>,
+    allele_count: &AlleleCount,
+) {
+    let rescaled = matches!(missing_strategy, MissingStrategy::Rescale);
+    if score_mode == ScoreMode::Sum && !rescaled {
+        return;
+    }
+    for (mut row, nonmissing) in score
+        .outer_iter_mut()
+        .zip(allele_count.nonmissing.outer_iter())
+    {
+        for ((v, nonmissing), total) in row.iter_mut().zip(nonmissing).zip(&allele_count.total) {
+            let factor = match (missing_strategy, score_mode) {
+                (MissingStrategy::Zero | MissingStrategy::Rescale, ScoreMode::Avg) => {
+                    1. / nonmissing
+                }
+                (_, ScoreMode::Avg) => 1. / total,
+                (_, ScoreMode::Sum) => total / nonmissing,
+            };
+            let factor = if factor.is_finite() {
+                factor
+            } else {
+                Float::NAN
+            };
+            *v *= factor;
+        }
+    }
+}
+
+/// Apply dominant (0/1/1) or recessive (0/0/1) coding on dosage. It should be
+/// applied after swap and missing fill, since the coding is on the effect
+/// allele. Missing genotypes should be filled by
+/// [GeneticModel::fill_dosage], so that they are coded into the expected
+/// coded dosage rather than a clipped mean.
+pub fn apply_genetic_model(weights: &Weights, gt: &mut Array2<f32>) {
+    for (cc, genetic_model) in weights.genetic_models.iter().enumerate() {
+        if *genetic_model != GeneticModel::Additive {
+            gt.slice_mut(s![.., cc])
+                .mapv_inplace(|x| genetic_model.code(x));
+        }
+    }
 }
 
 pub fn score_to_frame(
     fam: &DataFrame,
-    score: Array2<f32>,
+    score: Array2<Float>,
     score_names: &[String],
 ) -> Result<DataFrame> {
     let mut my_columns = vec![
@@ -75,7 +224,7 @@ pub fn score_to_frame(
         fam.column(PHENO).cloned()?,
     ];
     for (i, score_name) in score_names.iter().enumerate() {
-        let my_score: Vec<f32> = score.slice(s![.., i]).to_vec();
+        let my_score: Vec<Float> = score.slice(s![.., i]).to_vec();
         my_columns.push(Series::new(score_name, my_score))
     }
     let score = DataFrame::new(my_columns)?;
@@ -89,8 +238,121 @@ pub fn get_empty_score(score_names: &[String]) -> Result<DataFrame> {
         Series::new_empty(PHENO, &DataType::Float32),
     ];
     for score_name in score_names {
-        my_columns.push(Series::new_empty(score_name, &DataType::Float32))
+        my_columns.push(Series::new_empty(score_name, &FLOAT_DTYPE))
     }
     let score = DataFrame::new(my_columns)?;
     Ok(score)
 }
+
+#[cfg(test)]
+mod tests {
+    use betareader::Float;
+    use ndarray::{array, concatenate, s, Array2, Axis};
+
+    use super::{allele_count, finish_score, process_gt};
+    use crate::{
+        join::{
+            beta_matrix::BetaMatrix,
+            weight::{GeneticModel, Weights},
+        },
+        meta::{GenotypeScale, MissingStrategy, ScoreMode},
+    };
+
+    /// s1 on the first two snp, and s2 on the last one
+    fn weights(missing_strategy: MissingStrategy) -> Weights {
+        let columns = vec![vec![(0, 1.), (1, 2.)], vec![(2, 3.)]];
+        Weights {
+            beta_values: BetaMatrix::from_columns(3, columns),
+            sid_idx: vec![0, 1, 2],
+            status_freq_vec: vec![(None, None); 3],
+            genetic_models: vec![GeneticModel::Additive; 3],
+            missing_strategy,
+            genotype_scale: GenotypeScale::Raw,
+            score_names: vec!["s1".to_owned(), "s2".to_owned()],
+            q_bins: None,
+            cohort_dosage: None,
+            chroms: None,
+        }
+    }
+
+    /// score as plink2 with `no-mean-imputation`, where missing genotypes
+    /// are only counted in scores with non-zero weight on them
+    fn plink2_score(
+        score_mode: ScoreMode,
+        missing_strategy: MissingStrategy,
+    ) -> (Vec<Vec<u32>>, Array2<Float>) {
+        let weights = weights(missing_strategy);
+        let mut gt = array![[f32::NAN, 1., 2.], [2., 1., f32::NAN]];
+        let missing_iid = process_gt(&weights, &mut gt).unwrap();
+        let allele_count = allele_count(&weights, gt.nrows(), &missing_iid);
+        let mut score = weights.score(&gt);
+        finish_score(score_mode, missing_strategy, &mut score, &allele_count);
+        (missing_iid, score)
+    }
+
+    #[test]
+    fn test_allele_count() {
+        let weights = weights(MissingStrategy::Zero);
+        let missing_iid = vec![vec![0], vec![], vec![1]];
+        let allele_count = allele_count(&weights, 2, &missing_iid);
+        assert_eq!(allele_count.total, array![4., 2.]);
+        assert_eq!(allele_count.nonmissing, array![[2., 2.], [4., 0.]]);
+    }
+
+    #[test]
+    fn test_finish_score() {
+        let (missing_iid, score) = plink2_score(ScoreMode::Avg, MissingStrategy::Zero);
+        assert_eq!(missing_iid, vec![vec![0], vec![], vec![1]]);
+        assert_eq!(score.row(0), array![1., 3.]);
+        assert_eq!(score[[1, 0]], 1.);
+        // no non-missing genotype in s2
+        assert!(score[[1, 1]].is_nan());
+
+        let (_, score) = plink2_score(ScoreMode::Sum, MissingStrategy::Rescale);
+        assert_eq!(score.row(0), array![4., 6.]);
+        assert_eq!(score[[1, 0]], 4.);
+        assert!(score[[1, 1]].is_nan());
+
+        // all alleles are counted with the filled mean
+        let (_, score) = plink2_score(ScoreMode::Avg, MissingStrategy::Impute);
+        assert_eq!(score, array![[1., 3.], [1., 3.]]);
+
+        let (_, score) = plink2_score(ScoreMode::Sum, MissingStrategy::Zero);
+        assert_eq!(score, array![[2., 6.], [4., 0.]]);
+    }
+
+    /// score of `gt` in batches of `batch_size` samples
+    fn batch_score(weights: &Weights, gt: &Array2<f32>, batch_size: usize) -> Array2<Float> {
+        let scores: Vec<Array2<Float>> = (0..gt.nrows())
+            .step_by(batch_size)
+            .map(|start| {
+                let end = (start + batch_size).min(gt.nrows());
+                let mut gt = gt.slice(s![start..end, ..]).to_owned();
+                process_gt(weights, &mut gt).unwrap();
+                weights.score(&gt)
+            })
+            .collect();
+        let views: Vec<_> = scores.iter().map(|v| v.view()).collect();
+        concatenate(Axis(0), &views).unwrap()
+    }
+
+    #[test]
+    fn test_impute_by_cohort_dosage() {
+        let mut weights = weights(MissingStrategy::Impute);
+        let gt = array![
+            [f32::NAN, 1., 2.],
+            [2., 0., f32::NAN],
+            [0., f32::NAN, 1.],
+            [1., 2., 0.]
+        ];
+        // the mean of a batch of samples depends on the batch size
+        assert_ne!(batch_score(&weights, &gt, 1), batch_score(&weights, &gt, 4));
+
+        weights.cohort_dosage = Some(vec![1., 1., 1.]);
+        let score = batch_score(&weights, &gt, 4);
+        assert_eq!(score, array![[3., 6.], [2., 3.], [2., 3.], [5., 0.]]);
+        for batch_size in 1..4 {
+            assert_eq!(batch_score(&weights, &gt, batch_size), score);
+        }
+    }
+}
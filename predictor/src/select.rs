@@ -0,0 +1,329 @@
+//! Selection of the best Q range of each score for CandT. Picking the Q
+//! range with the best metric on the same samples overfits, so the selection
+//! is evaluated by k-fold cross validation or a held-out split: the Q range
+//! is selected on training samples, and its metric is measured on held-out
+//! samples only.
+use anyhow::{anyhow, Result};
+use genoreader::meta::PHENO;
+use log::debug;
+use polars::prelude::{BooleanChunked, DataFrame, DataType};
+
+use crate::metrics::{cal_auc_fn, cal_cor_fn};
+
+/// missing phenotype in fam
+const MISSING_PHENO: f32 = -9.;
+
+/// Metric to select Q range, larger is better
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum SelectMetric {
+    Pearson,
+    Spearman,
+    /// squared pearson correlation
+    R2,
+    /// AUC for binary phenotype
+    Auc,
+}
+
+impl SelectMetric {
+    pub fn new(metric: &str) -> Result<SelectMetric> {
+        let my_metric = match metric {
+            "Pearson" => SelectMetric::Pearson,
+            "Spearman" => SelectMetric::Spearman,
+            "R2" => SelectMetric::R2,
+            "AUC" => SelectMetric::Auc,
+            _ => {
+                return Err(anyhow!(
+                    "Argument select_metric should be one of the following: [ Pearson, \
+                     Spearman, R2, AUC ], got {}",
+                    metric
+                ))
+            }
+        };
+        Ok(my_metric)
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            SelectMetric::Pearson => "pearson",
+            SelectMetric::Spearman => "spearman",
+            SelectMetric::R2 => "r2",
+            SelectMetric::Auc => "auc",
+        }
+    }
+
+    /// metric of each score on samples of `scores`, none if it can not be
+    /// calculated, such as a score without variance
+    fn evaluate(&self, scores: &DataFrame, score_names: &[&str]) -> Result<Vec<Option<f64>>> {
+        let (metrics, column) = match self {
+            SelectMetric::Pearson | SelectMetric::R2 => {
+                (cal_cor_fn(scores, &score_names.to_vec())?, "pearson")
+            }
+            SelectMetric::Spearman => (cal_cor_fn(scores, &score_names.to_vec())?, "spearman"),
+            SelectMetric::Auc => (cal_auc_fn(scores, score_names)?, "auc"),
+        };
+        let values = metrics
+            .column(column)?
+            .cast(&DataType::Float64)?
+            .f64()?
+            .into_iter()
+            .map(|v| match v {
+                Some(v) if v.is_finite() && *self == SelectMetric::R2 => Some(v * v),
+                Some(v) if v.is_finite() => Some(v),
+                _ => None,
+            })
+            .collect();
+        Ok(values)
+    }
+}
+
+/// How samples are split to evaluate the selection
+#[derive(Clone, Debug, Copy)]
+pub enum Split {
+    /// k-fold cross validation
+    KFold(usize),
+    /// fraction of samples held out
+    Holdout(f64),
+}
+
+#[derive(Clone, Debug)]
+pub struct SelectArg {
+    pub metric: SelectMetric,
+    pub split: Split,
+    /// seed to shuffle samples before splitting
+    pub seed: u64,
+}
+
+/// Selected Q range of a score
+#[derive(Clone, Debug)]
+pub struct Selection {
+    /// raw score
+    pub score_name: String,
+    /// score of the selected Q range, `{score}_{name}`
+    pub selected: String,
+    /// metric of the selected Q range on all samples for k-fold, or on
+    /// training samples for held-out split
+    pub in_sample: Option<f64>,
+    /// mean and sd of metric on held-out samples of each fold
+    pub out_of_fold: Option<f64>,
+    pub out_of_fold_sd: Option<f64>,
+}
+
+/// Select the best of `candidates`, which are scores of Q ranges of
+/// `score_name`. For k-fold, the Q range is finally selected on all samples,
+/// and the out-of-fold metric estimates the performance of the selection.
+/// For held-out split, it is selected on training samples.
+pub fn select_best(
+    scores: &DataFrame,
+    score_name: &str,
+    candidates: &[String],
+    select_arg: &SelectArg,
+) -> Result<Selection> {
+    let scores = with_pheno(scores)?;
+    if let Split::KFold(k) = select_arg.split {
+        if k > scores.height() {
+            return Err(anyhow!(
+                "Can not split {} samples with PHENO into {} folds, use fewer --cv-folds",
+                scores.height(),
+                k
+            ));
+        }
+    }
+    let names: Vec<&str> = candidates.iter().map(|v| v.as_str()).collect();
+    let metric = select_arg.metric;
+    let folds = assign_folds(scores.height(), select_arg.split, select_arg.seed);
+    let n_rounds = match select_arg.split {
+        Split::KFold(k) => k,
+        Split::Holdout(_) => 1,
+    };
+
+    let mut held_out = vec![];
+    let mut train_best = None;
+    for test_fold in 0..n_rounds {
+        let is_test: BooleanChunked = folds.iter().map(|v| *v == test_fold).collect();
+        let is_train: BooleanChunked = folds.iter().map(|v| *v != test_fold).collect();
+        let train = scores.filter(&is_train)?;
+        let best = match pick_best(&metric.evaluate(&train, &names)?) {
+            Some(v) => v,
+            None => continue,
+        };
+        let test = scores.filter(&is_test)?;
+        let value = metric.evaluate(&test, &[names[best.0]])?[0];
+        debug!(
+            "Fold {} of {}: selected {} with {} {:?} in training and {:?} held out",
+            test_fold + 1,
+            n_rounds,
+            names[best.0],
+            metric.name(),
+            best.1,
+            value
+        );
+        held_out.extend(value);
+        train_best = Some(best);
+    }
+
+    let best = match select_arg.split {
+        Split::KFold(_) => pick_best(&metric.evaluate(&scores, &names)?),
+        Split::Holdout(_) => train_best,
+    };
+    let (cc, in_sample) =
+        best.ok_or_else(|| anyhow!("No Q range of {} can be evaluated", score_name))?;
+    let (out_of_fold, out_of_fold_sd) = mean_sd(&held_out);
+    Ok(Selection {
+        score_name: score_name.to_owned(),
+        selected: candidates[cc].to_owned(),
+        in_sample: Some(in_sample),
+        out_of_fold,
+        out_of_fold_sd,
+    })
+}
+
+/// samples with phenotype, which is not null, NaN or -9
+fn with_pheno(scores: &DataFrame) -> Result<DataFrame> {
+    let mask: BooleanChunked = scores
+        .column(PHENO)?
+        .f32()?
+        .into_iter()
+        .map(|v| v.is_some_and(|v| !v.is_nan() && v != MISSING_PHENO))
+        .collect();
+    let scores = scores.filter(&mask)?;
+    if scores.height() == 0 {
+        return Err(anyhow!("No sample with PHENO to select Q range"));
+    }
+    Ok(scores)
+}
+
+/// index and value of the largest metric
+fn pick_best(values: &[Option<f64>]) -> Option<(usize, f64)> {
+    values
+        .iter()
+        .enumerate()
+        .filter_map(|(cc, v)| v.map(|v| (cc, v)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Fold of each sample after shuffling. For held-out split, held-out samples
+/// are in fold 0 and the others in fold 1.
+fn assign_folds(n: usize, split: Split, seed: u64) -> Vec<usize> {
+    let mut folds = vec![0; n];
+    for (pos, sample) in shuffle(n, seed).into_iter().enumerate() {
+        folds[sample] = match split {
+            Split::KFold(k) => pos % k,
+            Split::Holdout(fraction) => ((pos as f64) >= fraction * n as f64) as usize,
+        };
+    }
+    folds
+}
+
+/// Fisher-Yates shuffle of `0..n` with splitmix64, so that folds are the same
+/// for the same seed
+fn shuffle(n: usize, seed: u64) -> Vec<usize> {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    let mut order: Vec<usize> = (0..n).collect();
+    for i in (1..n).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+    order
+}
+
+fn mean_sd(values: &[f64]) -> (Option<f64>, Option<f64>) {
+    let n = values.len() as f64;
+    if values.is_empty() {
+        return (None, None);
+    }
+    let mean = values.iter().sum::<f64>() / n;
+    let sd = match values.len() {
+        1 => None,
+        _ => Some((values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.)).sqrt()),
+    };
+    (Some(mean), sd)
+}
+
+#[cfg(test)]
+mod tests {
+    use genoreader::meta::PHENO;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+
+    use super::{assign_folds, select_best, SelectArg, SelectMetric, Split};
+
+    /// `good` follows PHENO, and `bad` is its reverse
+    fn scores(n: usize) -> DataFrame {
+        let pheno: Vec<f32> = (0..n).map(|v| v as f32).collect();
+        let bad: Vec<f32> = pheno.iter().map(|v| -v).collect();
+        DataFrame::new(vec![
+            Series::new(PHENO, &pheno),
+            Series::new("s_good", &pheno),
+            Series::new("s_bad", bad),
+        ])
+        .unwrap()
+    }
+
+    fn select_arg(split: Split) -> SelectArg {
+        SelectArg {
+            metric: SelectMetric::Pearson,
+            split,
+            seed: 42,
+        }
+    }
+
+    #[test]
+    fn test_assign_folds() {
+        let folds = assign_folds(10, Split::KFold(3), 42);
+        let counts: Vec<usize> = (0..3)
+            .map(|k| folds.iter().filter(|v| **v == k).count())
+            .collect();
+        assert_eq!(counts, vec![4, 3, 3]);
+        // the same folds for the same seed
+        assert_eq!(folds, assign_folds(10, Split::KFold(3), 42));
+        assert_ne!(folds, assign_folds(10, Split::KFold(3), 7));
+
+        // held-out samples are in fold 0
+        let folds = assign_folds(10, Split::Holdout(0.3), 42);
+        assert_eq!(folds.iter().filter(|v| **v == 0).count(), 3);
+        assert_eq!(folds.iter().filter(|v| **v == 1).count(), 7);
+    }
+
+    #[test]
+    fn test_select_best() {
+        let candidates = vec!["s_bad".to_owned(), "s_good".to_owned()];
+        let selection =
+            select_best(&scores(10), "s", &candidates, &select_arg(Split::KFold(3))).unwrap();
+        assert_eq!(selection.selected, "s_good");
+        assert!((selection.in_sample.unwrap() - 1.).abs() < 1e-6);
+        assert!((selection.out_of_fold.unwrap() - 1.).abs() < 1e-6);
+        assert!(selection.out_of_fold_sd.unwrap().abs() < 1e-6);
+
+        // missing phenotype is excluded, and there are too few samples left
+        let mut too_few = scores(4);
+        too_few
+            .with_column(Series::new(PHENO, [Some(1_f32), None, Some(-9.), Some(f32::NAN)]))
+            .unwrap();
+        let err = select_best(&too_few, "s", &candidates, &select_arg(Split::KFold(3)))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Can not split 1 samples with PHENO into 3 folds"));
+    }
+
+    #[test]
+    fn test_select_best_holdout() {
+        let candidates = vec!["s_bad".to_owned(), "s_good".to_owned()];
+        let selection = select_best(
+            &scores(10),
+            "s",
+            &candidates,
+            &select_arg(Split::Holdout(0.3)),
+        )
+        .unwrap();
+        assert_eq!(selection.selected, "s_good");
+        // a single held-out split has no sd
+        assert!((selection.out_of_fold.unwrap() - 1.).abs() < 1e-6);
+        assert_eq!(selection.out_of_fold_sd, None);
+    }
+}